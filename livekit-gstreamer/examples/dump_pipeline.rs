@@ -0,0 +1,59 @@
+use livekit_gstreamer::{
+    GStreamerError, GstMediaStream, PublishOptions, VideoFrameFormat, VideoPublishOptions,
+};
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> Result<(), GStreamerError> {
+    // Initialize gstreamer
+    gstreamer::init().unwrap();
+
+    // Note: Make sure to replace the device_id with the correct device and the codecs and resolutions are supported by the device
+    let mut stream = GstMediaStream::new(PublishOptions::Video(VideoPublishOptions {
+        codec: "image/jpeg".to_string(),
+        width: 1280,
+        height: 720,
+        framerate: 30,
+        device_id: "/dev/video0".to_string(),
+        local_file_save_options: None,
+        publish_resolution: None,
+        frame_format: VideoFrameFormat::I420,
+    }));
+
+    stream.start().await?;
+
+    // Let the pipeline negotiate its caps and fill its queues
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    if let Some(info) = stream.inspect() {
+        println!("Pipeline {} is {}", info.name, info.state);
+        for element in &info.elements {
+            println!(
+                "{} ({})",
+                element.path,
+                element.factory.as_deref().unwrap_or("unknown")
+            );
+            for pad in &element.pads {
+                println!(
+                    "    {} [{}]: {}",
+                    pad.name,
+                    pad.direction,
+                    pad.caps.as_deref().unwrap_or("not negotiated")
+                );
+            }
+        }
+        for (element, queue) in info.queues() {
+            println!(
+                "Queue {} is {:.0}% full",
+                element.name,
+                queue.fill_ratio() * 100.0
+            );
+        }
+    }
+
+    // Render with `dot -Tsvg <file>.dot > pipeline.svg`
+    let paths = stream.write_debug_dump("pipeline-dumps")?;
+    println!("Wrote {:?}", paths);
+
+    stream.stop().await
+}
@@ -1,10 +1,12 @@
 use gstreamer::Buffer;
 use livekit::{Room, RoomEvent};
-use livekit_gstreamer::{GStreamerError, GstMediaStream, LKParticipantError};
+use livekit_gstreamer::{
+    stream_ended, GStreamerError, GstMediaStream, LKParticipantError, StreamState,
+};
 use std::sync::Arc;
 use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::mpsc::UnboundedReceiver;
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{broadcast, mpsc, watch};
 
 #[allow(dead_code)]
 pub async fn wait_lk(
@@ -54,22 +56,22 @@ pub async fn wait_lk(
 pub async fn wait_streams(
     streams: &mut [GstMediaStream],
     frame_rxs: Vec<broadcast::Receiver<Arc<Buffer>>>,
-    close_rxs: Vec<broadcast::Receiver<()>>,
+    state_rxs: Vec<watch::Receiver<StreamState>>,
 ) -> Result<(), GStreamerError> {
     println!("Waiting for multiple streams...");
 
     let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
 
-    for ((mut frame_rx, mut close_rx), stream_index) in
-        frame_rxs.into_iter().zip(close_rxs).zip(0..)
+    for ((mut frame_rx, mut state_rx), stream_index) in
+        frame_rxs.into_iter().zip(state_rxs).zip(0..)
     {
         let shutdown_tx = shutdown_tx.clone();
 
         tokio::spawn(async move {
             loop {
                 tokio::select! {
-                    _ = close_rx.recv() => {
-                        println!("Stream {stream_index} closed");
+                    state = stream_ended(&mut state_rx) => {
+                        println!("Stream {stream_index} ended: {:?}", state);
                         let _ = shutdown_tx.send(()).await;
                         break;
                     }
@@ -9,7 +9,7 @@ use amqprs::{
 use livekit::{Room, RoomEvent, RoomOptions};
 use livekit_gstreamer::{
     initialize_gstreamer, GstMediaStream, LKParticipant, LocalFileSaveOptions, PublishOptions,
-    VideoPublishOptions,
+    VideoFrameFormat, VideoPublishOptions,
 };
 use syncflow_client::{ProjectClient, ProjectClientError};
 use syncflow_shared::{
@@ -128,7 +128,10 @@ async fn publish_streams(
         device_id: "/dev/video4".to_string(),
         local_file_save_options: Some(LocalFileSaveOptions {
             output_dir: "recordings".to_string(),
+            ..Default::default()
         }),
+        publish_resolution: None,
+        frame_format: VideoFrameFormat::I420,
     }));
 
     stream.start().await.unwrap();
@@ -3,7 +3,8 @@ use livekit::{Room, RoomOptions};
 
 use livekit_api::access_token;
 use livekit_gstreamer::{
-    GstMediaStream, LKParticipant, LKParticipantError, PublishOptions, VideoPublishOptions,
+    GstMediaStream, LKParticipant, LKParticipantError, PublishOptions, VideoFrameFormat,
+    VideoPublishOptions,
 };
 use std::{env, sync::Arc};
 
@@ -48,6 +49,8 @@ async fn main() -> Result<(), LKParticipantError> {
         framerate: 30,
         device_id: "/dev/video4".to_string(),
         local_file_save_options: None,
+        publish_resolution: None,
+        frame_format: VideoFrameFormat::I420,
     }));
 
     stream.start().await.unwrap();
@@ -4,7 +4,7 @@ use livekit::{Room, RoomOptions};
 use livekit_api::access_token;
 use livekit_gstreamer::{
     GstMediaStream, LKParticipant, LKParticipantError, LocalFileSaveOptions, PublishOptions,
-    VideoPublishOptions,
+    VideoFrameFormat, VideoPublishOptions,
 };
 use std::{env, sync::Arc};
 
@@ -50,8 +50,11 @@ async fn main() -> Result<(), LKParticipantError> {
         local_file_save_options: {
             Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             })
-        }
+        },
+        publish_resolution: None,
+        frame_format: VideoFrameFormat::I420,
     }));
 
     stream.start().await.unwrap();
@@ -0,0 +1,95 @@
+use dotenvy::dotenv;
+use livekit::{Room, RoomOptions};
+
+use livekit_api::access_token;
+use livekit_gstreamer::{
+    CompositeInput, CompositeInputKind, CompositeLayout, CompositePublishOptions, GstMediaStream,
+    LKParticipant, LKParticipantError, LocalFileSaveOptions, PictureInPictureLayout,
+    PublishOptions, VideoFrameFormat,
+};
+use std::{env, sync::Arc};
+
+#[path = "./helper/wait.rs"]
+mod wait;
+
+#[tokio::main]
+async fn main() -> Result<(), LKParticipantError> {
+    // Only run on linux
+    if !cfg!(target_os = "linux") {
+        panic!("This example is only supported on Linux");
+    }
+
+    dotenv().ok();
+    // Initialize gstreamer
+    gstreamer::init().unwrap();
+    std::env::set_var("RUST_LOG", "info");
+    env_logger::init();
+
+    let url = env::var("LIVEKIT_URL").expect("LIVEKIT_URL is not set");
+    let api_key = env::var("LIVEKIT_API_KEY").expect("LIVEKIT_API_KEY is not set");
+    let api_secret = env::var("LIVEKIT_API_SECRET").expect("LIVEKIT_API_SECRET is not set");
+
+    let token = access_token::AccessToken::with_api_key(&api_key, &api_secret)
+        .with_identity("rust-bot-lecture-view")
+        .with_name("Rust Bot Lecture View")
+        .with_grants(access_token::VideoGrants {
+            room_join: true,
+            room: "DemoRoom".to_string(),
+            ..Default::default()
+        })
+        .to_jwt()
+        .unwrap();
+
+    let (room, mut room_rx) = Room::connect(&url, &token, RoomOptions::default())
+        .await
+        .unwrap();
+
+    let new_room = Arc::new(room);
+
+    // Slides fill the frame, the presenter camera sits in the bottom right corner
+    let mut stream = GstMediaStream::new(PublishOptions::Composite(CompositePublishOptions {
+        width: 1280,
+        height: 720,
+        framerate: 30,
+        inputs: vec![
+            CompositeInput {
+                kind: CompositeInputKind::Screen,
+                device_id: "DP-3-2".to_string(),
+                codec: "video/x-raw".to_string(),
+                width: 1920,
+                height: 1080,
+                framerate: 30,
+                rect: None,
+            },
+            CompositeInput {
+                kind: CompositeInputKind::Camera,
+                device_id: "/dev/video0".to_string(),
+                codec: "image/jpeg".to_string(),
+                width: 640,
+                height: 480,
+                framerate: 30,
+                rect: None,
+            },
+        ],
+        layout: CompositeLayout::PictureInPicture(PictureInPictureLayout::default()),
+        local_file_save_options: Some(LocalFileSaveOptions {
+            output_dir: "recordings".to_string(),
+            ..Default::default()
+        }),
+        frame_format: VideoFrameFormat::I420,
+    }));
+
+    stream.start().await?;
+
+    let mut participant = LKParticipant::new(new_room.clone());
+
+    participant.publish_stream(&mut stream, None).await?;
+
+    log::info!(
+        "Connected to room: {} - {}",
+        new_room.name(),
+        String::from(new_room.sid().await)
+    );
+
+    wait::wait_lk(&mut [stream], new_room.clone(), &mut room_rx).await
+}
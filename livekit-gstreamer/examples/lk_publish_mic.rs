@@ -46,8 +46,11 @@ async fn main() -> Result<(), LKParticipantError> {
             framerate: 48000,
             channels: 1,
             selected_channel: None,
+            voice_activity: None,
+            processing: None,
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
         }))
     } else {
@@ -57,9 +60,12 @@ async fn main() -> Result<(), LKParticipantError> {
             device_id: r"\\?\SWD#MMDEVAPI#{0.0.1.00000000}.{400ac096-5f57-4207-87c5-b9d208f12749}#{2eef81be-33fa-4800-9670-1cd474972c3f}".to_string(),
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
             channels: 2,
-            selected_channel: Some(1)
+            selected_channel: Some(1),
+            voice_activity: None,
+            processing: None
         }))
     };
 
@@ -70,8 +76,11 @@ async fn main() -> Result<(), LKParticipantError> {
             framerate: 48000,
             channels: 1,
             selected_channel: None,
+            voice_activity: None,
+            processing: None,
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
         }))
     } else {
@@ -81,9 +90,12 @@ async fn main() -> Result<(), LKParticipantError> {
             device_id: r"\\?\SWD#MMDEVAPI#{0.0.1.00000000}.{400ac096-5f57-4207-87c5-b9d208f12749}#{2eef81be-33fa-4800-9670-1cd474972c3f}".to_string(),
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
             channels: 2,
-            selected_channel: Some(2)
+            selected_channel: Some(2),
+            voice_activity: None,
+            processing: None
         }))
     };
 
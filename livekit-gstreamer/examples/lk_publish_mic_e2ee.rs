@@ -0,0 +1,62 @@
+use dotenvy::dotenv;
+use livekit_gstreamer::{
+    AudioPublishOptions, GstMediaStream, LKE2eeOptions, LKParticipant, LKParticipantError,
+    LocalFileSaveOptions, PublishOptions,
+};
+
+use livekit_api::access_token;
+use std::env;
+
+#[path = "./helper/wait.rs"]
+mod wait;
+
+#[tokio::main]
+async fn main() -> Result<(), LKParticipantError> {
+    dotenv().ok();
+    // Initialize gstreamer
+    gstreamer::init().unwrap();
+    std::env::set_var("RUST_LOG", "info");
+    env_logger::init();
+
+    let url = env::var("LIVEKIT_URL").expect("LIVEKIT_URL is not set");
+    let api_key = env::var("LIVEKIT_API_KEY").expect("LIVEKIT_API_KEY is not set");
+    let api_secret = env::var("LIVEKIT_API_SECRET").expect("LIVEKIT_API_SECRET is not set");
+    // Subscribers have to join with the same key to hear the microphone
+    let shared_key = env::var("E2EE_SHARED_KEY").expect("E2EE_SHARED_KEY is not set");
+
+    let token = access_token::AccessToken::with_api_key(&api_key, &api_secret)
+        .with_identity("rust-bot-microphone-e2ee")
+        .with_name("Rust Bot Microphone E2EE")
+        .with_grants(access_token::VideoGrants {
+            room_join: true,
+            room: "DemoRoom".to_string(),
+            ..Default::default()
+        })
+        .to_jwt()
+        .unwrap();
+
+    let mut stream = GstMediaStream::new(PublishOptions::Audio(AudioPublishOptions {
+        codec: "audio/x-raw".to_string(),
+        device_id: "hw:0".to_string(),
+        framerate: 48000,
+        channels: 1,
+        selected_channel: None,
+        voice_activity: None,
+        processing: None,
+        local_file_save_options: Some(LocalFileSaveOptions {
+            output_dir: "recordings".to_string(),
+            ..Default::default()
+        }),
+    }));
+
+    stream.start().await?;
+
+    let (mut participant, mut room_rx) =
+        LKParticipant::connect(&url, &token, Some(&LKE2eeOptions::new(shared_key))).await?;
+
+    // Fails, rather than publishing in the clear, if the track cannot be encrypted
+    let track_sid = participant.publish_stream(&mut stream, None).await?;
+    log::info!("Published encrypted track with SID: {}", track_sid);
+
+    wait::wait_lk(&mut [stream], participant.room(), &mut room_rx).await
+}
@@ -0,0 +1,86 @@
+use dotenvy::dotenv;
+use livekit::{Room, RoomOptions};
+use livekit_gstreamer::{
+    AudioMixerInput, AudioMixerPublishOptions, GstMediaStream, LKParticipant, LKParticipantError,
+    LocalFileSaveOptions, PublishOptions,
+};
+
+use livekit_api::access_token;
+use std::{env, sync::Arc};
+
+#[path = "./helper/wait.rs"]
+mod wait;
+
+#[tokio::main]
+async fn main() -> Result<(), LKParticipantError> {
+    // Only run on linux
+    if !cfg!(target_os = "linux") {
+        panic!("This example is only supported on Linux");
+    }
+
+    dotenv().ok();
+    // Initialize gstreamer
+    gstreamer::init().unwrap();
+    std::env::set_var("RUST_LOG", "info");
+    env_logger::init();
+
+    let url = env::var("LIVEKIT_URL").expect("LIVEKIT_URL is not set");
+    let api_key = env::var("LIVEKIT_API_KEY").expect("LIVEKIT_API_KEY is not set");
+    let api_secret = env::var("LIVEKIT_API_SECRET").expect("LIVEKIT_API_SECRET is not set");
+
+    let token = access_token::AccessToken::with_api_key(&api_key, &api_secret)
+        .with_identity("rust-bot-room-mix")
+        .with_name("Rust Bot Room Mix")
+        .with_grants(access_token::VideoGrants {
+            room_join: true,
+            room: "server-room3".to_string(),
+            ..Default::default()
+        })
+        .to_jwt()
+        .unwrap();
+
+    let mut stream = GstMediaStream::new(PublishOptions::Mixer(AudioMixerPublishOptions {
+        codec: "audio/x-raw".to_string(),
+        framerate: 48000,
+        channels: 1,
+        inputs: vec![
+            AudioMixerInput {
+                device_id: "hw:0".to_string(),
+                channels: 1,
+                selected_channel: None,
+                gain: 1.0,
+            },
+            AudioMixerInput {
+                device_id: "hw:1".to_string(),
+                channels: 1,
+                selected_channel: None,
+                gain: 0.8,
+            },
+        ],
+        record_inputs: true,
+        local_file_save_options: Some(LocalFileSaveOptions {
+            output_dir: "recordings".to_string(),
+            ..Default::default()
+        }),
+    }));
+
+    let (room, mut room_rx) = Room::connect(&url, &token, RoomOptions::default())
+        .await
+        .unwrap();
+
+    let new_room = Arc::new(room);
+
+    stream.start().await?;
+
+    let mut participant = LKParticipant::new(new_room.clone());
+
+    participant.publish_stream(&mut stream, None).await?;
+
+    log::info!(
+        "Connected to room: {} - {}",
+        new_room.name(),
+        String::from(new_room.sid().await)
+    );
+
+    wait::wait_lk(&mut [stream], new_room.clone(), &mut room_rx).await
+}
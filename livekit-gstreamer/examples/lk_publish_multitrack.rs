@@ -3,8 +3,8 @@ use livekit::{Room, RoomOptions};
 
 use livekit_api::access_token;
 use livekit_gstreamer::{
-    AudioPublishOptions, GstMediaStream, LKParticipant, LKParticipantError, PublishOptions,
-    VideoPublishOptions,
+    AudioPublishOptions, GstMediaStream, LKParticipant, LKParticipantError, LKPublishSettings,
+    LKVideoCodec, PublishOptions, VideoFrameFormat, VideoPublishOptions,
 };
 use std::{env, sync::Arc};
 
@@ -54,6 +54,8 @@ async fn main() -> Result<(), LKParticipantError> {
         framerate: 30,
         device_id: "/dev/video0".to_string(),
         local_file_save_options: None,
+        publish_resolution: None,
+        frame_format: VideoFrameFormat::I420,
     }));
 
     let mut stream2 = GstMediaStream::new(PublishOptions::Video(VideoPublishOptions {
@@ -63,6 +65,8 @@ async fn main() -> Result<(), LKParticipantError> {
         framerate: 30,
         device_id: "/dev/video2".to_string(),
         local_file_save_options: None,
+        publish_resolution: None,
+        frame_format: VideoFrameFormat::I420,
     }));
 
     let mut stream3 = GstMediaStream::new(PublishOptions::Audio(AudioPublishOptions {
@@ -71,6 +75,8 @@ async fn main() -> Result<(), LKParticipantError> {
         framerate: 32000,
         channels: 1,
         selected_channel: None,
+        voice_activity: None,
+        processing: None,
         local_file_save_options: None,
     }));
 
@@ -80,6 +86,8 @@ async fn main() -> Result<(), LKParticipantError> {
         framerate: 48000,
         channels: 1,
         selected_channel: None,
+        voice_activity: None,
+        processing: None,
         local_file_save_options: None,
     }));
 
@@ -93,8 +101,24 @@ async fn main() -> Result<(), LKParticipantError> {
         stream1.get_device_name().unwrap()
     );
 
+    // Cheaper to encode than the default VP9, with simulcast layers for large rooms
+    let settings = LKPublishSettings {
+        simulcast: true,
+        video_codec: Some(LKVideoCodec::VP8),
+        max_bitrate: Some(1_500_000),
+        ..Default::default()
+    };
+    for layer in settings.video_layers(stream2.options()) {
+        log::info!(
+            "Simulcast layer {}: {}x{} at up to {:?} bps",
+            layer.rid,
+            layer.width,
+            layer.height,
+            layer.max_bitrate
+        );
+    }
     participant
-        .publish_stream(&mut stream2, Some("camera-2".into()))
+        .publish_stream_with_settings(&mut stream2, Some("camera-2".into()), settings)
         .await?;
     log::info!(
         "Published {} stream from device: {}",
@@ -3,7 +3,8 @@ use livekit::{Room, RoomOptions};
 
 use livekit_api::access_token;
 use livekit_gstreamer::{
-    GstMediaStream, LKParticipant, LKParticipantError, PublishOptions, VideoPublishOptions,
+    GstMediaStream, LKParticipant, LKParticipantError, LKPublishSettings, PublishOptions,
+    VideoFrameFormat, VideoPublishOptions,
 };
 use std::{env, sync::Arc};
 
@@ -45,13 +46,18 @@ async fn main() -> Result<(), LKParticipantError> {
         framerate: 30,
         device_id: "/dev/video4".to_string(),
         local_file_save_options: None,
+        publish_resolution: None,
+        frame_format: VideoFrameFormat::I420,
     }));
 
     stream.start().await.unwrap();
 
     let mut participant = LKParticipant::new(new_room.clone());
 
-    let track_sid = participant.publish_stream(&mut stream, None).await?;
+    // The participant owns the stream and stops it when the track is unpublished
+    let track_sid = participant
+        .publish_owned_stream(stream, None, LKPublishSettings::default())
+        .await?;
 
     log::info!(
         "Connected to room: {} - {}",
@@ -61,6 +67,7 @@ async fn main() -> Result<(), LKParticipantError> {
     log::info!("Published track with SID for one minute: {}", track_sid);
     tokio::time::sleep(std::time::Duration::from_secs(60)).await;
     log::info!("Unpublishing track with SID: {}", track_sid);
+    participant.close().await?;
 
     Ok(())
 }
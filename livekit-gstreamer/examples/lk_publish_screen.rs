@@ -4,7 +4,7 @@ use livekit::{Room, RoomOptions};
 use livekit_api::access_token;
 use livekit_gstreamer::{
     GstMediaStream, LKParticipant, LKParticipantError, LocalFileSaveOptions, PublishOptions,
-    ScreenPublishOptions,
+    PublishResolution, ScreenPublishOptions, VideoFrameFormat,
 };
 use std::{env, sync::Arc};
 
@@ -48,7 +48,13 @@ async fn main() -> Result<(), LKParticipantError> {
             screen_id_or_name: "DP-3-2".to_string(),
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
+            publish_resolution: Some(PublishResolution {
+                width: 1280,
+                height: 720,
+            }),
+            frame_format: VideoFrameFormat::NV12,
         }))
     } else {
         GstMediaStream::new(PublishOptions::Screen(ScreenPublishOptions {
@@ -59,7 +65,10 @@ async fn main() -> Result<(), LKParticipantError> {
             screen_id_or_name: "65537".to_string(),
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
+            publish_resolution: None,
+            frame_format: VideoFrameFormat::I420,
         }))
     };
 
@@ -51,8 +51,11 @@ async fn main() -> Result<(), LKParticipantError> {
         framerate: 96000,
         channels: 10,
         selected_channel: Some(1),
+        voice_activity: None,
+        processing: None,
         local_file_save_options: Some(LocalFileSaveOptions {
             output_dir: "recordings".to_string(),
+            ..Default::default()
         }),
     };
 
@@ -62,8 +65,11 @@ async fn main() -> Result<(), LKParticipantError> {
         framerate: 96000,
         channels: 10,
         selected_channel: Some(2),
+        voice_activity: None,
+        processing: None,
         local_file_save_options: Some(LocalFileSaveOptions {
             output_dir: "recordings".to_string(),
+            ..Default::default()
         }),
     };
 
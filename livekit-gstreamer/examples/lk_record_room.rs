@@ -0,0 +1,74 @@
+use dotenvy::dotenv;
+use livekit::{Room, RoomOptions};
+use livekit_gstreamer::{
+    LKParticipantError, LKSubscriber, LKSubscriberOptions, LocalFileSaveOptions,
+};
+
+use livekit_api::access_token;
+use std::env;
+
+#[tokio::main]
+async fn main() -> Result<(), LKParticipantError> {
+    dotenv().ok();
+    // Initialize gstreamer
+    gstreamer::init().unwrap();
+    std::env::set_var("RUST_LOG", "info");
+    env_logger::init();
+
+    let url = env::var("LIVEKIT_URL").expect("LIVEKIT_URL is not set");
+    let api_key = env::var("LIVEKIT_API_KEY").expect("LIVEKIT_API_KEY is not set");
+    let api_secret = env::var("LIVEKIT_API_SECRET").expect("LIVEKIT_API_SECRET is not set");
+
+    let token = access_token::AccessToken::with_api_key(&api_key, &api_secret)
+        .with_identity("rust-bot-recorder")
+        .with_name("Rust Bot Recorder")
+        .with_grants(access_token::VideoGrants {
+            room_join: true,
+            room: "server-room3".to_string(),
+            can_publish: false,
+            can_subscribe: true,
+            ..Default::default()
+        })
+        .to_jwt()
+        .unwrap();
+
+    let (room, mut room_rx) = Room::connect(&url, &token, RoomOptions::default())
+        .await
+        .unwrap();
+
+    log::info!("Recording room: {}", room.name());
+
+    let mut subscriber = LKSubscriber::new(LKSubscriberOptions {
+        local_file_save_options: LocalFileSaveOptions {
+            output_dir: "recordings".to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    loop {
+        tokio::select! {
+            event = room_rx.recv() => {
+                match event {
+                    Some(event) => {
+                        subscriber.handle_event(&event).await?;
+                        if let livekit::RoomEvent::Disconnected { reason } = event {
+                            log::info!("Disconnected from room: {:?}", reason);
+                            break;
+                        }
+                    }
+                    None => break,
+                }
+            }
+            _ = tokio::signal::ctrl_c() => {
+                log::info!("Received Ctrl+C, finishing recordings");
+                break;
+            }
+        }
+    }
+
+    subscriber.stop().await?;
+    room.close().await?;
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use dotenvy::dotenv;
+use livekit::{Room, RoomOptions};
+
+use livekit_api::access_token;
+use livekit_gstreamer::{
+    GstMediaStream, LKParticipant, LKParticipantError, LKPublishSettings, LKRemoteControlOptions,
+    PublishOptions, VideoFrameFormat, VideoPublishOptions,
+};
+use std::{env, sync::Arc};
+
+#[tokio::main]
+async fn main() -> Result<(), LKParticipantError> {
+    dotenv().ok();
+    // Initialize gstreamer
+    gstreamer::init().unwrap();
+    std::env::set_var("RUST_LOG", "info");
+    env_logger::init();
+
+    let url = env::var("LIVEKIT_URL").expect("LIVEKIT_URL is not set");
+    let api_key = env::var("LIVEKIT_API_KEY").expect("LIVEKIT_API_KEY is not set");
+    let api_secret = env::var("LIVEKIT_API_SECRET").expect("LIVEKIT_API_SECRET is not set");
+    // The participant allowed to control this one, e.g. an operator dashboard
+    let controller = env::var("CONTROLLER_IDENTITY").expect("CONTROLLER_IDENTITY is not set");
+
+    let token = access_token::AccessToken::with_api_key(&api_key, &api_secret)
+        .with_identity("rust-bot-remote")
+        .with_name("Rust Bot Remote Control")
+        .with_grants(access_token::VideoGrants {
+            room_join: true,
+            room: "DemoRoom".to_string(),
+            ..Default::default()
+        })
+        .to_jwt()
+        .unwrap();
+
+    let (room, _) = Room::connect(&url, &token, RoomOptions::default())
+        .await
+        .unwrap();
+
+    let new_room = Arc::new(room);
+
+    // Note: Make sure to replace the device_id with the correct device and the codecs and resolutions are supported by the device
+    let mut stream = GstMediaStream::new(PublishOptions::Video(VideoPublishOptions {
+        codec: "video/x-raw".to_string(),
+        width: 1280,
+        height: 720,
+        framerate: 30,
+        device_id: "/dev/video0".to_string(),
+        local_file_save_options: None,
+        publish_resolution: None,
+        frame_format: VideoFrameFormat::I420,
+    }));
+
+    stream.start().await.unwrap();
+
+    let mut participant = LKParticipant::new(new_room.clone());
+    let track_sid = participant
+        .publish_owned_stream(stream, None, LKPublishSettings::default())
+        .await?;
+    log::info!("Published track with SID: {}", track_sid);
+
+    // The controller calls e.g. `syncflow.snapshot` with `{"trackSid": "<sid>"}`
+    let mut requests = participant.enable_remote_control(LKRemoteControlOptions {
+        allowed_identities: vec![controller],
+    });
+
+    loop {
+        tokio::select! {
+            request = requests.recv() => {
+                let Some(request) = request else {
+                    break;
+                };
+                log::info!("{} requested {:?}", request.caller_identity, request.command);
+                participant.handle_remote_request(request).await;
+            }
+            _ = tokio::signal::ctrl_c() => {
+                break;
+            }
+        }
+    }
+
+    participant.disable_remote_control();
+    participant.close().await?;
+
+    Ok(())
+}
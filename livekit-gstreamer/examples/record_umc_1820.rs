@@ -22,8 +22,11 @@ async fn main() -> Result<(), GStreamerError> {
         framerate: 96000,
         channels: 10,
         selected_channel: Some(2),
+        voice_activity: None,
+        processing: None,
         local_file_save_options: Some(LocalFileSaveOptions {
             output_dir: "recordings".to_string(),
+            ..Default::default()
         }),
     };
 
@@ -31,7 +34,7 @@ async fn main() -> Result<(), GStreamerError> {
 
     stream.start().await?;
 
-    let (frame_rx, close_rx) = stream.subscribe().unwrap();
+    let (frame_rx, state_rx) = stream.subscribe().unwrap();
 
-    wait::wait_streams(&mut [stream], vec![frame_rx], vec![close_rx]).await
+    wait::wait_streams(&mut [stream], vec![frame_rx], vec![state_rx]).await
 }
@@ -0,0 +1,51 @@
+use livekit_gstreamer::{
+    stream_ended, AudioPublishOptions, GStreamerError, GstMediaStream, PublishOptions,
+};
+
+#[tokio::main]
+async fn main() -> Result<(), GStreamerError> {
+    gstreamer::init().map_err(|e| {
+        GStreamerError::PipelineError(format!("Failed to initialize gstreamer: {}", e))
+    })?;
+
+    let mut stream = GstMediaStream::new(PublishOptions::Audio(AudioPublishOptions {
+        codec: "audio/x-raw".to_string(),
+        device_id: "hw:1".to_string(),
+        framerate: 48000,
+        channels: 1,
+        selected_channel: None,
+        voice_activity: None,
+        processing: None,
+        local_file_save_options: None,
+    }));
+
+    stream.start().await?;
+
+    let mut levels_rx = stream.subscribe_levels().unwrap();
+    let (_, mut state_rx) = stream.subscribe().unwrap();
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {
+                println!("Received Ctrl+C");
+                break;
+            }
+            state = stream_ended(&mut state_rx) => {
+                println!("Stream ended: {:?}", state);
+                break;
+            }
+            levels = levels_rx.recv() => {
+                if let Ok(levels) = levels {
+                    for (idx, channel) in levels.channels.iter().enumerate() {
+                        println!(
+                            "Channel {}: rms {:.1} dB, peak {:.1} dB",
+                            idx, channel.rms_db, channel.peak_db
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    stream.stop().await
+}
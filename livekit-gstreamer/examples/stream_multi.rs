@@ -1,6 +1,6 @@
 use livekit_gstreamer::{
     AudioPublishOptions, GStreamerError, GstMediaStream, LocalFileSaveOptions, PublishOptions,
-    VideoPublishOptions,
+    VideoFrameFormat, VideoPublishOptions,
 };
 
 #[path = "./helper/wait.rs"]
@@ -19,8 +19,11 @@ async fn main() -> Result<(), GStreamerError> {
             framerate: 48000,
             channels: 1,
             selected_channel: None,
+            voice_activity: None,
+            processing: None,
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
         }))
     } else if cfg!(target_os = "windows") {
@@ -30,9 +33,12 @@ async fn main() -> Result<(), GStreamerError> {
             device_id: r"\\?\SWD#MMDEVAPI#{0.0.1.00000000}.{400ac096-5f57-4207-87c5-b9d208f12749}#{2eef81be-33fa-4800-9670-1cd474972c3f}".to_string(),
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
             channels: 2,
-            selected_channel: None
+            selected_channel: None,
+            voice_activity: None,
+            processing: None
         }))
     } else {
         GstMediaStream::new(PublishOptions::Audio(AudioPublishOptions {
@@ -42,8 +48,11 @@ async fn main() -> Result<(), GStreamerError> {
             framerate: 48000,
             channels: 1,
             selected_channel: None,
+            voice_activity: None,
+            processing: None,
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
         }))
     };
@@ -59,7 +68,10 @@ async fn main() -> Result<(), GStreamerError> {
             device_id: "/dev/video0".to_string(),
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
+            publish_resolution: None,
+            frame_format: VideoFrameFormat::I420,
         }))
     } else if cfg!(target_os = "windows") {
         GstMediaStream::new(PublishOptions::Video(VideoPublishOptions {
@@ -70,7 +82,10 @@ async fn main() -> Result<(), GStreamerError> {
             device_id: r"\\?\usb#vid_0c45&pid_6a10&mi_00#6&303dd63&0&0000#{e5323777-f976-4f5b-9b55-b94699c46e44}\global".to_string(),
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
+            publish_resolution: None,
+            frame_format: VideoFrameFormat::I420,
         }))
     } else {
         GstMediaStream::new(PublishOptions::Video(VideoPublishOptions {
@@ -81,7 +96,10 @@ async fn main() -> Result<(), GStreamerError> {
             device_id: "0x1000000c45636b".to_string(),
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
+            publish_resolution: None,
+            frame_format: VideoFrameFormat::I420,
         }))
     };
 
@@ -89,13 +107,13 @@ async fn main() -> Result<(), GStreamerError> {
 
     audio_stream.start().await?;
 
-    let (audio_frame_rx, audio_close_rx) = audio_stream.subscribe().unwrap();
-    let (video_frame_rx, video_close_rx) = video_stream.subscribe().unwrap();
+    let (audio_frame_rx, audio_state_rx) = audio_stream.subscribe().unwrap();
+    let (video_frame_rx, video_state_rx) = video_stream.subscribe().unwrap();
 
     wait::wait_streams(
         &mut [audio_stream, video_stream],
         vec![audio_frame_rx, video_frame_rx],
-        vec![audio_close_rx, video_close_rx],
+        vec![audio_state_rx, video_state_rx],
     )
     .await
 }
@@ -17,8 +17,11 @@ async fn main() -> Result<(), GStreamerError> {
             framerate: 48000,
             channels: 1,
             selected_channel: None,
+            voice_activity: None,
+            processing: None,
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
         }))
     } else if cfg!(target_os = "macos") {
@@ -29,8 +32,11 @@ async fn main() -> Result<(), GStreamerError> {
             framerate: 48000,
             channels: 1,
             selected_channel: None,
+            voice_activity: None,
+            processing: None,
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
         }))
     } else {
@@ -40,15 +46,18 @@ async fn main() -> Result<(), GStreamerError> {
             device_id: r"\\?\SWD#MMDEVAPI#{0.0.1.00000000}.{400ac096-5f57-4207-87c5-b9d208f12749}#{2eef81be-33fa-4800-9670-1cd474972c3f}".to_string(),
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
             channels: 2,
-            selected_channel: None
+            selected_channel: None,
+            voice_activity: None,
+            processing: None
         }))
     };
 
     stream.start().await?;
 
-    let (frame_rx, close_rx) = stream.subscribe().unwrap();
+    let (frame_rx, state_rx) = stream.subscribe().unwrap();
 
-    wait::wait_streams(&mut [stream], vec![frame_rx], vec![close_rx]).await
+    wait::wait_streams(&mut [stream], vec![frame_rx], vec![state_rx]).await
 }
@@ -1,6 +1,7 @@
 use dotenvy::dotenv;
 use livekit_gstreamer::{
     GStreamerError, GstMediaStream, LocalFileSaveOptions, PublishOptions, ScreenPublishOptions,
+    VideoFrameFormat,
 };
 
 #[path = "./helper/wait.rs"]
@@ -20,7 +21,10 @@ async fn main() -> Result<(), GStreamerError> {
             screen_id_or_name: "DP-3-2".to_string(),
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
+            publish_resolution: None,
+            frame_format: VideoFrameFormat::I420,
         }))
     } else if cfg!(target_os = "macos") {
         GstMediaStream::new(PublishOptions::Screen(ScreenPublishOptions {
@@ -31,7 +35,10 @@ async fn main() -> Result<(), GStreamerError> {
             screen_id_or_name: "Built-in Display".to_string(),
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
+            publish_resolution: None,
+            frame_format: VideoFrameFormat::I420,
         }))
     } else {
         GstMediaStream::new(PublishOptions::Screen(ScreenPublishOptions {
@@ -42,13 +49,16 @@ async fn main() -> Result<(), GStreamerError> {
             screen_id_or_name: "131073".to_string(),
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
+            publish_resolution: None,
+            frame_format: VideoFrameFormat::I420,
         }))
     };
 
     stream.start().await.unwrap();
 
-    let (frame_rx, close_rx) = stream.subscribe().unwrap();
+    let (frame_rx, state_rx) = stream.subscribe().unwrap();
 
-    wait::wait_streams(&mut [stream], vec![frame_rx], vec![close_rx]).await
+    wait::wait_streams(&mut [stream], vec![frame_rx], vec![state_rx]).await
 }
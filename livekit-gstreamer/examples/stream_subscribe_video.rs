@@ -1,6 +1,7 @@
 use dotenvy::dotenv;
 use livekit_gstreamer::{
-    GStreamerError, GstMediaStream, LocalFileSaveOptions, PublishOptions, VideoPublishOptions,
+    GStreamerError, GstMediaStream, LocalFileSaveOptions, PublishOptions, VideoFrameFormat,
+    VideoPublishOptions,
 };
 
 #[path = "./helper/wait.rs"]
@@ -30,7 +31,10 @@ async fn main() -> Result<(), GStreamerError> {
             },
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
+            publish_resolution: None,
+            frame_format: VideoFrameFormat::I420,
         }))
     } else {
         GstMediaStream::new(PublishOptions::Video(VideoPublishOptions {
@@ -41,13 +45,16 @@ async fn main() -> Result<(), GStreamerError> {
             device_id: r"\\?\usb#vid_0c45&pid_6a10&mi_00#6&303dd63&0&0000#{e5323777-f976-4f5b-9b55-b94699c46e44}\global".to_string(),
             local_file_save_options: Some(LocalFileSaveOptions {
                 output_dir: "recordings".to_string(),
+                ..Default::default()
             }),
+            publish_resolution: None,
+            frame_format: VideoFrameFormat::I420,
         }))
     };
 
     stream.start().await.unwrap();
 
-    let (frame_rx, close_rx) = stream.subscribe().unwrap();
+    let (frame_rx, state_rx) = stream.subscribe().unwrap();
 
-    wait::wait_streams(&mut [stream], vec![frame_rx], vec![close_rx]).await
+    wait::wait_streams(&mut [stream], vec![frame_rx], vec![state_rx]).await
 }
@@ -0,0 +1,86 @@
+use futures::StreamExt;
+use livekit_gstreamer::{
+    AudioPublishOptions, BackpressurePolicy, FrameRecvError, GStreamerError, GstMediaStream,
+    PublishOptions, VideoFrameFormat, VideoPublishOptions,
+};
+
+#[tokio::main]
+async fn main() -> Result<(), GStreamerError> {
+    // Initialize gstreamer
+    gstreamer::init().unwrap();
+
+    // Note: Make sure to replace the device_ids with the correct devices and the codecs and resolutions are supported by the devices
+    let mut video_stream = GstMediaStream::new(PublishOptions::Video(VideoPublishOptions {
+        codec: "image/jpeg".to_string(),
+        width: 1280,
+        height: 720,
+        framerate: 30,
+        device_id: "/dev/video0".to_string(),
+        local_file_save_options: None,
+        publish_resolution: None,
+        frame_format: VideoFrameFormat::NV12,
+    }));
+    let mut audio_stream = GstMediaStream::new(PublishOptions::Audio(AudioPublishOptions {
+        codec: "audio/x-raw".to_string(),
+        device_id: "hw:0".to_string(),
+        framerate: 48000,
+        channels: 1,
+        selected_channel: None,
+        voice_activity: None,
+        processing: None,
+        local_file_save_options: None,
+    }));
+
+    video_stream.start().await?;
+    audio_stream.start().await?;
+
+    // Both end once their stream stops. Only the latest video frame is kept, while
+    // the pipeline waits for the audio subscriber rather than dropping samples.
+    let mut video_frames = video_stream
+        .subscribe_video_frames(BackpressurePolicy::LatestOnly)
+        .unwrap()
+        .into_stream();
+    let mut audio_rx = audio_stream
+        .subscribe_audio_frames(BackpressurePolicy::Lossless { capacity: 50 })
+        .unwrap();
+
+    loop {
+        tokio::select! {
+            Some(frame) = video_frames.next() => match frame {
+                Ok(frame) => println!(
+                    "Video {}x{} {:?}, strides {:?}, captured at {:?} ns",
+                    frame.width(),
+                    frame.height(),
+                    frame.format(),
+                    frame.strides,
+                    frame.capture_time_ns
+                ),
+                Err(e) => println!("Video: {}", e),
+            },
+            frame = audio_rx.recv() => match frame {
+                Ok(frame) => println!(
+                    "Audio {} samples of {} at {} Hz, pts {:?}, duration {:?}",
+                    frame.samples_per_channel.unwrap_or_default(),
+                    frame.format_name,
+                    frame.rate,
+                    frame.pts,
+                    frame.duration
+                ),
+                Err(FrameRecvError::Ended(state)) => {
+                    println!("Audio stream ended: {:?}", state);
+                    break;
+                }
+                Err(e) => println!("Audio: {}", e),
+            },
+            _ = tokio::signal::ctrl_c() => {
+                break;
+            }
+        }
+    }
+
+    println!("Video subscribers: {:?}", video_stream.subscriber_metrics());
+    println!("Audio subscriber: {:?}", audio_rx.metrics());
+
+    video_stream.stop().await?;
+    audio_stream.stop().await
+}
@@ -0,0 +1,179 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Duration of the frames WebRTC expects from an audio source
+pub const AUDIO_FRAME_DURATION_MS: u32 = 10;
+
+/// Interleaved little endian PCM sample formats, named as in GStreamer caps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AudioSampleFormat {
+    #[default]
+    S16LE,
+    /// Packed, three bytes per sample
+    S24LE,
+    S32LE,
+    F32LE,
+}
+
+impl AudioSampleFormat {
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            AudioSampleFormat::S16LE => 2,
+            AudioSampleFormat::S24LE => 3,
+            AudioSampleFormat::S32LE | AudioSampleFormat::F32LE => 4,
+        }
+    }
+
+    /// The format named `format` in GStreamer caps, if it is one of the handled ones
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "S16LE" => Some(AudioSampleFormat::S16LE),
+            "S24LE" => Some(AudioSampleFormat::S24LE),
+            "S32LE" => Some(AudioSampleFormat::S32LE),
+            "F32LE" => Some(AudioSampleFormat::F32LE),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AudioSampleFormat::S16LE => "S16LE",
+            AudioSampleFormat::S24LE => "S24LE",
+            AudioSampleFormat::S32LE => "S32LE",
+            AudioSampleFormat::F32LE => "F32LE",
+        }
+    }
+
+    fn to_i16(self, bytes: &[u8]) -> i16 {
+        match self {
+            AudioSampleFormat::S16LE => i16::from_le_bytes([bytes[0], bytes[1]]),
+            AudioSampleFormat::S24LE => {
+                (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 16) as i16
+            }
+            AudioSampleFormat::S32LE => {
+                (i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) >> 16) as i16
+            }
+            AudioSampleFormat::F32LE => {
+                let sample = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+            }
+        }
+    }
+}
+
+/// Re-chunks interleaved PCM buffers of any size into the 10 ms S16 frames WebRTC
+/// expects. Buffers do not need to hold whole samples or be aligned, a sample split
+/// across two buffers is put back together.
+#[derive(Debug)]
+pub struct AudioFrameAdapter {
+    format: AudioSampleFormat,
+    /// Bytes of an incomplete sample at the end of the last buffer
+    partial: Vec<u8>,
+    samples: VecDeque<i16>,
+    frame_len: usize,
+}
+
+impl AudioFrameAdapter {
+    pub fn new(format: AudioSampleFormat, sample_rate: u32, num_channels: u32) -> Self {
+        let samples_per_channel = (sample_rate * AUDIO_FRAME_DURATION_MS / 1000).max(1);
+        Self {
+            format,
+            partial: Vec::with_capacity(format.bytes_per_sample()),
+            samples: VecDeque::new(),
+            frame_len: (samples_per_channel * num_channels.max(1)) as usize,
+        }
+    }
+
+    /// Interleaved samples in a frame, over all channels
+    pub fn frame_len(&self) -> usize {
+        self.frame_len
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        let bytes_per_sample = self.format.bytes_per_sample();
+        let mut data = data;
+
+        if !self.partial.is_empty() {
+            let missing = (bytes_per_sample - self.partial.len()).min(data.len());
+            self.partial.extend_from_slice(&data[..missing]);
+            data = &data[missing..];
+            if self.partial.len() < bytes_per_sample {
+                return;
+            }
+            self.samples.push_back(self.format.to_i16(&self.partial));
+            self.partial.clear();
+        }
+
+        let mut chunks = data.chunks_exact(bytes_per_sample);
+        self.samples
+            .extend(chunks.by_ref().map(|sample| self.format.to_i16(sample)));
+        self.partial.extend_from_slice(chunks.remainder());
+    }
+
+    /// The next complete 10 ms frame, if enough samples have been pushed
+    pub fn pop_frame(&mut self) -> Option<Vec<i16>> {
+        if self.samples.len() < self.frame_len {
+            return None;
+        }
+        Some(self.samples.drain(..self.frame_len).collect())
+    }
+
+    /// Drops samples left over from a previous stream
+    pub fn clear(&mut self) {
+        self.partial.clear();
+        self.samples.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rechunks_into_10ms_frames() {
+        let mut adapter = AudioFrameAdapter::new(AudioSampleFormat::S16LE, 48000, 2);
+        assert_eq!(adapter.frame_len(), 960);
+
+        let samples = (0..1500i16).collect::<Vec<_>>();
+        let bytes = samples
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        // Odd sized buffers split samples in two
+        adapter.push(&bytes[..1001]);
+        assert!(adapter.pop_frame().is_none());
+        adapter.push(&bytes[1001..]);
+
+        let frame = adapter.pop_frame().unwrap();
+        assert_eq!(frame, samples[..960]);
+        assert!(adapter.pop_frame().is_none());
+    }
+
+    #[test]
+    fn test_converts_sample_formats() {
+        let cases: [(AudioSampleFormat, Vec<u8>); 4] = [
+            (AudioSampleFormat::S16LE, (-1234i16).to_le_bytes().to_vec()),
+            (
+                AudioSampleFormat::S24LE,
+                ((-1234i32) << 8).to_le_bytes()[..3].to_vec(),
+            ),
+            (
+                AudioSampleFormat::S32LE,
+                ((-1234i32) << 16).to_le_bytes().to_vec(),
+            ),
+            (
+                AudioSampleFormat::F32LE,
+                (-1234.0f32 / i16::MAX as f32).to_le_bytes().to_vec(),
+            ),
+        ];
+
+        for (format, bytes) in cases {
+            let mut adapter = AudioFrameAdapter::new(format, 100, 1);
+            adapter.push(&bytes);
+            assert_eq!(adapter.pop_frame(), Some(vec![-1234]), "{:?}", format);
+            assert_eq!(AudioSampleFormat::parse(format.as_str()), Some(format));
+        }
+        assert_eq!(AudioSampleFormat::parse("F64LE"), None);
+    }
+}
@@ -0,0 +1,301 @@
+use crate::frame_subscription::StreamFrameSender;
+use crate::media_device::{GStreamerError, GstMediaDevice, SUPPORTED_AUDIO_CODECS};
+use crate::media_stream::AudioMixerInput;
+use crate::utils::{make_element, random_string};
+use gstreamer::prelude::*;
+use std::sync::Arc;
+
+pub const AUDIO_MIXER_DEVICE_CLASS: &str = "Audio/Mixer";
+
+impl GstMediaDevice {
+    /// A virtual device standing in for several microphones mixed into one stream
+    pub fn audio_mixer(devices: &[GstMediaDevice]) -> Self {
+        GstMediaDevice {
+            display_name: format!(
+                "Mixer ({})",
+                devices
+                    .iter()
+                    .map(|d| d.display_name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            device_class: AUDIO_MIXER_DEVICE_CLASS.to_string(),
+            device_path: devices
+                .iter()
+                .map(|d| d.device_path.as_str())
+                .collect::<Vec<_>>()
+                .join("+"),
+        }
+    }
+
+    /// Mixes the given inputs with `audiomixer` into a single S16LE stream. Each input
+    /// can optionally be recorded to its own file before mixing, and the mix itself
+    /// is recorded to `filename`.
+    pub fn audio_mixer_pipeline(
+        &self,
+        codec: &str,
+        inputs: &[(GstMediaDevice, AudioMixerInput, Option<String>)],
+        channels: i32,
+        framerate: i32,
+        tx: Arc<StreamFrameSender>,
+        filename: Option<String>,
+    ) -> Result<gstreamer::Pipeline, GStreamerError> {
+        if self.device_class != AUDIO_MIXER_DEVICE_CLASS {
+            return Err(GStreamerError::PipelineError(
+                "Device is not an audio mixer".to_string(),
+            ));
+        }
+
+        if !SUPPORTED_AUDIO_CODECS.contains(&codec) {
+            return Err(GStreamerError::PipelineError(format!(
+                "Unsupported codec {}",
+                codec
+            )));
+        }
+
+        if inputs.is_empty() {
+            return Err(GStreamerError::PipelineError(
+                "Audio mixer needs at least one input".to_string(),
+            ));
+        }
+
+        for (device, input, _) in inputs {
+            if !device.supports_audio(codec, input.channels, framerate) {
+                return Err(GStreamerError::PipelineError(format!(
+                    "Device {} does not support requested configuration",
+                    device.display_name
+                )));
+            }
+        }
+
+        let pipeline = gstreamer::Pipeline::with_name(&random_string("audio-mixer"));
+
+        let mixer = make_element("audiomixer")?;
+        let mixer_caps = gstreamer::Caps::builder("audio/x-raw")
+            .field("format", "S16LE")
+            .field("channels", channels)
+            .field("rate", framerate)
+            .build();
+        let mixer_capsfilter = make_element("capsfilter")?;
+        mixer_capsfilter.set_property("caps", &mixer_caps);
+        let level = self.level_element()?;
+        let tee = make_element("tee")?;
+        let queue_appsink = make_element("queue")?;
+        let broadcast_appsink = self.broadcast_appsink(tx, None)?;
+
+        pipeline
+            .add_many([
+                &mixer,
+                &mixer_capsfilter,
+                &level,
+                &tee,
+                &queue_appsink,
+                broadcast_appsink.upcast_ref(),
+            ])
+            .map_err(|_| {
+                GStreamerError::PipelineError("Failed to add elements to pipeline".to_string())
+            })?;
+
+        gstreamer::Element::link_many([&mixer, &mixer_capsfilter, &level, &tee])
+            .map_err(|_| GStreamerError::PipelineError("Failed to link mixer".to_string()))?;
+
+        // Inputs picking channels of the same interface share its source, as the
+        // device can only be opened once
+        let mut devices: Vec<(&GstMediaDevice, Vec<(&AudioMixerInput, &Option<String>)>)> = vec![];
+        for (device, input, input_filename) in inputs {
+            match devices
+                .iter_mut()
+                .find(|(_, device_inputs)| device_inputs[0].0.device_id == input.device_id)
+            {
+                Some((_, device_inputs)) => device_inputs.push((input, input_filename)),
+                None => devices.push((device, vec![(input, input_filename)])),
+            }
+        }
+        for (device, device_inputs) in devices {
+            self.add_mixer_device(&pipeline, &mixer, device, &device_inputs, framerate)?;
+        }
+
+        let tee_appsink_pad = tee.request_pad_simple("src_%u").ok_or_else(|| {
+            GStreamerError::PipelineError("Failed to request tee pad for appsink".into())
+        })?;
+
+        let queue_appsink_pad = queue_appsink
+            .static_pad("sink")
+            .ok_or_else(|| GStreamerError::PipelineError("Appsink queue has no sink pad".into()))?;
+
+        tee_appsink_pad.link(&queue_appsink_pad).map_err(|_| {
+            GStreamerError::PipelineError("Failed to link tee to appsink queue".into())
+        })?;
+
+        gstreamer::Element::link_many([&queue_appsink, broadcast_appsink.upcast_ref()])
+            .map_err(|_| GStreamerError::PipelineError("Failed to link appsink".to_string()))?;
+
+        if let Some(ref path) = filename {
+            self.add_audio_file_branch(&pipeline, &tee, path)?;
+        }
+
+        pipeline
+            .iterate_elements()
+            .foreach(|e| {
+                let _ = e.sync_state_with_parent();
+            })
+            .map_err(|_| {
+                GStreamerError::PipelineError("Failed to sync state with parent".to_string())
+            })?;
+
+        Ok(pipeline)
+    }
+
+    /// Opens the device once and adds a branch to the mixer for each of its inputs,
+    /// the ones with a `selected_channel` are fed from a shared `deinterleave`
+    fn add_mixer_device(
+        &self,
+        pipeline: &gstreamer::Pipeline,
+        mixer: &gstreamer::Element,
+        device: &GstMediaDevice,
+        inputs: &[(&AudioMixerInput, &Option<String>)],
+        framerate: i32,
+    ) -> Result<(), GStreamerError> {
+        let channels = inputs[0].0.channels;
+        if inputs.iter().any(|(input, _)| input.channels != channels) {
+            return Err(GStreamerError::PipelineError(format!(
+                "Inputs of device {} request different channel counts",
+                device.display_name
+            )));
+        }
+        let mut selected_channels = vec![];
+        for (input, _) in inputs {
+            if let Some(selected_channel) = input.selected_channel {
+                if selected_channels.contains(&selected_channel) {
+                    return Err(GStreamerError::PipelineError(format!(
+                        "Channel {} of device {} is selected by more than one input",
+                        selected_channel, device.display_name
+                    )));
+                }
+                selected_channels.push(selected_channel);
+            }
+        }
+
+        let source = device.get_audio_element()?;
+        let convert = make_element("audioconvert")?;
+        let resample = make_element("audioresample")?;
+
+        let mut caps = gstreamer::Caps::builder("audio/x-raw")
+            .field("format", "S16LE")
+            .field("channels", channels)
+            .field("rate", framerate);
+        if !selected_channels.is_empty() {
+            caps = caps.field("channel-mask", gstreamer::Bitmask::new((1 << channels) - 1));
+        }
+        let caps_element = make_element("capsfilter")?;
+        caps_element.set_property("caps", caps.build());
+        let device_tee = make_element("tee")?;
+
+        pipeline
+            .add_many([&source, &convert, &resample, &caps_element, &device_tee])
+            .map_err(|_| GStreamerError::PipelineError("Failed to add mixer input".to_string()))?;
+
+        gstreamer::Element::link_many([&source, &convert, &resample, &caps_element, &device_tee])
+            .map_err(|_| GStreamerError::PipelineError("Failed to link mixer input".to_string()))?;
+
+        let mut routes = vec![];
+        for (input, filename) in inputs {
+            let queue = self.add_mixer_input(pipeline, mixer, input, filename)?;
+            match input.selected_channel {
+                Some(selected_channel) => {
+                    routes.push((format!("src_{}", selected_channel - 1), queue));
+                }
+                None => link_tee(&device_tee, &queue)?,
+            }
+        }
+
+        if !routes.is_empty() {
+            let deinterleave_queue = make_element("queue")?;
+            let deinterleave = make_element("deinterleave")?;
+            pipeline
+                .add_many([&deinterleave_queue, &deinterleave])
+                .map_err(|_| {
+                    GStreamerError::PipelineError("Failed to add deinterleave".to_string())
+                })?;
+            link_tee(&device_tee, &deinterleave_queue)?;
+            deinterleave_queue.link(&deinterleave).map_err(|_| {
+                GStreamerError::PipelineError("Failed to link deinterleave".to_string())
+            })?;
+
+            deinterleave.connect_pad_added(move |_, src_pad| {
+                for (pad_name, queue) in &routes {
+                    if src_pad.name() != pad_name.as_str() {
+                        continue;
+                    }
+                    let queue_sink_pad = queue.static_pad("sink").unwrap();
+                    if queue_sink_pad.is_linked() {
+                        return;
+                    }
+                    src_pad.link(&queue_sink_pad).unwrap();
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Adds the gain, recording and mixing branch of an input, returning the queue
+    /// to feed its audio to
+    fn add_mixer_input(
+        &self,
+        pipeline: &gstreamer::Pipeline,
+        mixer: &gstreamer::Element,
+        input: &AudioMixerInput,
+        filename: &Option<String>,
+    ) -> Result<gstreamer::Element, GStreamerError> {
+        let queue = make_element("queue")?;
+        let tee = make_element("tee")?;
+        let mixer_queue = make_element("queue")?;
+        let mixer_convert = make_element("audioconvert")?;
+        let mixer_resample = make_element("audioresample")?;
+
+        pipeline
+            .add_many([&queue, &tee, &mixer_queue, &mixer_convert, &mixer_resample])
+            .map_err(|_| GStreamerError::PipelineError("Failed to add mixer input".to_string()))?;
+
+        gstreamer::Element::link_many([&queue, &tee])
+            .map_err(|_| GStreamerError::PipelineError("Failed to link mixer input".to_string()))?;
+
+        gstreamer::Element::link_many([&mixer_queue, &mixer_convert, &mixer_resample])
+            .map_err(|_| GStreamerError::PipelineError("Failed to link mixer input".to_string()))?;
+
+        link_tee(&tee, &mixer_queue)?;
+
+        let mixer_sink_pad = mixer.request_pad_simple("sink_%u").ok_or_else(|| {
+            GStreamerError::PipelineError("Failed to request audiomixer pad".into())
+        })?;
+        mixer_sink_pad.set_property("volume", input.gain);
+
+        let resample_src_pad = mixer_resample
+            .static_pad("src")
+            .ok_or_else(|| GStreamerError::PipelineError("Resample has no src pad".into()))?;
+        resample_src_pad.link(&mixer_sink_pad).map_err(|_| {
+            GStreamerError::PipelineError("Failed to link input to audiomixer".into())
+        })?;
+
+        if let Some(ref path) = filename {
+            self.add_audio_file_branch(pipeline, &tee, path)?;
+        }
+
+        Ok(queue)
+    }
+}
+
+/// Links a new branch of the tee to the sink pad of the queue
+fn link_tee(tee: &gstreamer::Element, queue: &gstreamer::Element) -> Result<(), GStreamerError> {
+    let tee_pad = tee
+        .request_pad_simple("src_%u")
+        .ok_or_else(|| GStreamerError::PipelineError("Failed to request tee pad".into()))?;
+    let queue_pad = queue
+        .static_pad("sink")
+        .ok_or_else(|| GStreamerError::PipelineError("Queue has no sink pad".into()))?;
+    tee_pad
+        .link(&queue_pad)
+        .map(|_| ())
+        .map_err(|_| GStreamerError::PipelineError("Failed to link tee to queue".into()))
+}
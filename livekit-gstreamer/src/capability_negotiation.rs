@@ -0,0 +1,365 @@
+use crate::media_device::{
+    GStreamerError, GstMediaDevice, MediaCapability, SUPPORTED_AUDIO_CODECS, SUPPORTED_VIDEO_CODECS,
+};
+use serde::{Deserialize, Serialize};
+
+/// Sample rate picked for audio devices when no ideal one is asked for
+const DEFAULT_AUDIO_FRAMERATE: i32 = 48000;
+
+/// Acceptable values of a setting. Values outside of `min`..=`max` are never
+/// picked, among the others the closest to `ideal` wins.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ConstrainRange {
+    pub min: Option<i32>,
+    pub ideal: Option<i32>,
+    pub max: Option<i32>,
+}
+
+impl ConstrainRange {
+    pub fn ideal(ideal: i32) -> Self {
+        Self {
+            ideal: Some(ideal),
+            ..Default::default()
+        }
+    }
+
+    pub fn contains(&self, value: i32) -> bool {
+        self.min.is_none_or(|min| value >= min) && self.max.is_none_or(|max| value <= max)
+    }
+
+    /// The value within both `min`..=`max` and `lower`..=`upper` closest to the
+    /// ideal, or to `fallback` without one
+    fn pick(&self, lower: i32, upper: i32, fallback: i32) -> Option<i32> {
+        let lower = self.min.map_or(lower, |min| min.max(lower));
+        let upper = self.max.map_or(upper, |max| max.min(upper));
+        (lower <= upper).then(|| self.ideal.unwrap_or(fallback).clamp(lower, upper))
+    }
+
+    /// Relative distance of `value` to the ideal, zero without one
+    fn distance(&self, value: i32) -> f64 {
+        match self.ideal {
+            Some(ideal) if ideal > 0 => (value - ideal).abs() as f64 / ideal as f64,
+            _ => 0.0,
+        }
+    }
+
+    fn is_ideal(&self, value: i32) -> bool {
+        self.ideal.is_none_or(|ideal| ideal == value)
+    }
+}
+
+/// Desired capture mode of a camera or screen
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct VideoConstraints {
+    pub width: ConstrainRange,
+    pub height: ConstrainRange,
+    pub framerate: ConstrainRange,
+    /// Codecs in order of preference. A preferred codec wins over a closer mode
+    /// of another codec, other supported codecs are only used when the device
+    /// offers none of them.
+    pub codecs: Vec<String>,
+}
+
+/// Desired capture mode of a microphone
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AudioConstraints {
+    pub channels: ConstrainRange,
+    pub framerate: ConstrainRange,
+    /// Codecs in order of preference, see `VideoConstraints::codecs`
+    pub codecs: Vec<String>,
+}
+
+/// The supported mode picked for a camera or screen
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NegotiatedVideoMode {
+    pub codec: String,
+    pub width: i32,
+    pub height: i32,
+    pub framerate: i32,
+    /// Whether every ideal value and the most preferred codec were available
+    pub exact: bool,
+}
+
+/// The supported mode picked for a microphone
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NegotiatedAudioMode {
+    pub codec: String,
+    pub channels: i32,
+    pub framerate: i32,
+    /// Whether every ideal value and the most preferred codec were available
+    pub exact: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum NegotiatedMode {
+    Video(NegotiatedVideoMode),
+    Audio(NegotiatedAudioMode),
+}
+
+/// Position of the codec in the preferences, after all of them if not preferred
+fn codec_rank(codecs: &[String], codec: &str) -> usize {
+    codecs
+        .iter()
+        .position(|c| c == codec)
+        .unwrap_or(codecs.len())
+}
+
+/// Picks the supported camera or screen mode closest to the constraints. Cameras
+/// offer fixed sizes while screens can be captured at any size up to their own.
+pub fn negotiate_video(
+    capabilities: &[MediaCapability],
+    constraints: &VideoConstraints,
+) -> Option<NegotiatedVideoMode> {
+    let candidates = capabilities.iter().flat_map(|capability| {
+        let (codec, sizes, framerates) = match capability {
+            MediaCapability::Video(c) => (&c.codec, Some((c.width, c.height)), &c.framerates),
+            MediaCapability::Screen(c) => {
+                let size = constraints
+                    .width
+                    .pick(1, c.width, c.width)
+                    .zip(constraints.height.pick(1, c.height, c.height));
+                (&c.codec, size, &c.framerates)
+            }
+            MediaCapability::Audio(_) => return vec![],
+        };
+        let Some((width, height)) = sizes else {
+            return vec![];
+        };
+        framerates
+            .iter()
+            .map(|&framerate| (codec.clone(), width, height, framerate))
+            .collect()
+    });
+
+    let score = |(codec, width, height, framerate): &(String, i32, i32, i32)| {
+        (
+            codec_rank(&constraints.codecs, codec),
+            constraints.width.distance(*width)
+                + constraints.height.distance(*height)
+                + constraints.framerate.distance(*framerate),
+            // Ties go to the larger and smoother mode
+            -(width * height),
+            -framerate,
+        )
+    };
+
+    let (codec, width, height, framerate) = candidates
+        .filter(|(codec, width, height, framerate)| {
+            SUPPORTED_VIDEO_CODECS.contains(&codec.as_str())
+                && constraints.width.contains(*width)
+                && constraints.height.contains(*height)
+                && constraints.framerate.contains(*framerate)
+        })
+        .min_by(|a, b| {
+            let (a, b) = (score(a), score(b));
+            a.0.cmp(&b.0)
+                .then(a.1.total_cmp(&b.1))
+                .then(a.2.cmp(&b.2))
+                .then(a.3.cmp(&b.3))
+        })?;
+
+    Some(NegotiatedVideoMode {
+        exact: codec_rank(&constraints.codecs, &codec) == 0
+            && constraints.width.is_ideal(width)
+            && constraints.height.is_ideal(height)
+            && constraints.framerate.is_ideal(framerate),
+        codec,
+        width,
+        height,
+        framerate,
+    })
+}
+
+/// Picks the supported microphone mode closest to the constraints
+pub fn negotiate_audio(
+    capabilities: &[MediaCapability],
+    constraints: &AudioConstraints,
+) -> Option<NegotiatedAudioMode> {
+    let (codec, channels, framerate) = capabilities
+        .iter()
+        .filter_map(|capability| match capability {
+            MediaCapability::Audio(c) => Some(c),
+            _ => None,
+        })
+        .filter(|c| {
+            SUPPORTED_AUDIO_CODECS.contains(&c.codec.as_str())
+                && constraints.channels.contains(c.channels)
+        })
+        .filter_map(|c| {
+            let framerate = constraints.framerate.pick(
+                c.framerates.0,
+                c.framerates.1,
+                DEFAULT_AUDIO_FRAMERATE,
+            )?;
+            Some((c.codec.clone(), c.channels, framerate))
+        })
+        .min_by(|a, b| {
+            let score = |(codec, channels, framerate): &(String, i32, i32)| {
+                (
+                    codec_rank(&constraints.codecs, codec),
+                    constraints.channels.distance(*channels)
+                        + constraints.framerate.distance(*framerate),
+                )
+            };
+            let (a, b) = (score(a), score(b));
+            a.0.cmp(&b.0).then(a.1.total_cmp(&b.1))
+        })?;
+
+    Some(NegotiatedAudioMode {
+        exact: codec_rank(&constraints.codecs, &codec) == 0
+            && constraints.channels.is_ideal(channels)
+            && constraints.framerate.is_ideal(framerate),
+        codec,
+        channels,
+        framerate,
+    })
+}
+
+impl GstMediaDevice {
+    /// The camera or screen mode of the device closest to the constraints
+    pub fn negotiate_video(
+        &self,
+        constraints: &VideoConstraints,
+    ) -> Result<NegotiatedVideoMode, GStreamerError> {
+        if self.device_class == "Audio/Source" {
+            return Err(GStreamerError::DeviceError(
+                "Device is an audio source".to_string(),
+            ));
+        }
+        negotiate_video(&self.capabilities(), constraints).ok_or_else(|| {
+            GStreamerError::DeviceError(format!(
+                "{} has no mode within the constraints {:?}",
+                self.display_name, constraints
+            ))
+        })
+    }
+
+    /// The microphone mode of the device closest to the constraints
+    pub fn negotiate_audio(
+        &self,
+        constraints: &AudioConstraints,
+    ) -> Result<NegotiatedAudioMode, GStreamerError> {
+        if self.device_class == "Video/Source" {
+            return Err(GStreamerError::DeviceError(
+                "Device is a video source".to_string(),
+            ));
+        }
+        negotiate_audio(&self.capabilities(), constraints).ok_or_else(|| {
+            GStreamerError::DeviceError(format!(
+                "{} has no mode within the constraints {:?}",
+                self.display_name, constraints
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media_device::{AudioCapability, ScreenCapability, VideoCapability};
+
+    fn camera(codec: &str, width: i32, height: i32, framerates: &[i32]) -> MediaCapability {
+        MediaCapability::Video(VideoCapability {
+            width,
+            height,
+            framerates: framerates.to_vec(),
+            codec: codec.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_negotiate_video_picks_closest_mode() {
+        let capabilities = vec![
+            camera("image/jpeg", 1280, 720, &[30, 15]),
+            camera("image/jpeg", 1920, 1080, &[30]),
+            camera("video/x-raw", 1920, 1080, &[5]),
+            camera("video/x-raw", 640, 480, &[30]),
+        ];
+        let mut constraints = VideoConstraints {
+            width: ConstrainRange::ideal(1600),
+            height: ConstrainRange::ideal(900),
+            framerate: ConstrainRange::ideal(30),
+            codecs: vec!["image/jpeg".to_string()],
+        };
+
+        let mode = negotiate_video(&capabilities, &constraints).unwrap();
+        assert_eq!((mode.width, mode.height, mode.framerate), (1920, 1080, 30));
+        assert!(!mode.exact);
+
+        // The preferred codec wins over a closer mode
+        constraints.codecs = vec!["video/x-raw".to_string()];
+        constraints.width = ConstrainRange::ideal(1280);
+        constraints.height = ConstrainRange::ideal(720);
+        let mode = negotiate_video(&capabilities, &constraints).unwrap();
+        assert_eq!(mode.codec, "video/x-raw");
+        assert_eq!((mode.width, mode.height), (640, 480));
+
+        constraints.width.min = Some(1000);
+        constraints.framerate.min = Some(10);
+        let mode = negotiate_video(&capabilities, &constraints).unwrap();
+        assert_eq!(mode.codec, "image/jpeg");
+        assert_eq!((mode.width, mode.height, mode.framerate), (1280, 720, 30));
+
+        constraints.width.min = Some(4000);
+        assert_eq!(negotiate_video(&capabilities, &constraints), None);
+    }
+
+    #[test]
+    fn test_negotiate_screen_scales_down() {
+        let capabilities = vec![MediaCapability::Screen(ScreenCapability {
+            width: 2560,
+            height: 1440,
+            framerates: vec![15, 30],
+            codec: "video/x-raw".to_string(),
+            startx: 0,
+            starty: 0,
+            endx: 2559,
+            endy: 1439,
+        })];
+        let constraints = VideoConstraints {
+            width: ConstrainRange::ideal(1920),
+            height: ConstrainRange::ideal(1080),
+            framerate: ConstrainRange::ideal(30),
+            codecs: vec!["video/x-raw".to_string()],
+        };
+
+        let mode = negotiate_video(&capabilities, &constraints).unwrap();
+        assert_eq!((mode.width, mode.height, mode.framerate), (1920, 1080, 30));
+        assert!(mode.exact);
+    }
+
+    #[test]
+    fn test_negotiate_audio_clamps_framerate() {
+        let capabilities = vec![
+            MediaCapability::Audio(AudioCapability {
+                channels: 2,
+                framerates: (8000, 44100),
+                codec: "audio/x-raw".to_string(),
+            }),
+            MediaCapability::Audio(AudioCapability {
+                channels: 10,
+                framerates: (44100, 96000),
+                codec: "audio/x-raw".to_string(),
+            }),
+        ];
+        let mut constraints = AudioConstraints {
+            channels: ConstrainRange::ideal(1),
+            framerate: ConstrainRange::ideal(48000),
+            codecs: vec!["audio/x-raw".to_string()],
+        };
+
+        let mode = negotiate_audio(&capabilities, &constraints).unwrap();
+        assert_eq!((mode.channels, mode.framerate), (2, 44100));
+        assert!(!mode.exact);
+
+        constraints.channels.min = Some(4);
+        let mode = negotiate_audio(&capabilities, &constraints).unwrap();
+        assert_eq!((mode.channels, mode.framerate), (10, 48000));
+    }
+}
@@ -0,0 +1,382 @@
+use gstreamer::Buffer;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, Notify};
+
+/// What happens to the frames of a subscriber that does not keep up with the stream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+    tag = "policy",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum BackpressurePolicy {
+    /// Only the newest frame is kept, for live consumers such as WebRTC tracks
+    #[default]
+    LatestOnly,
+    /// Up to `capacity` frames are queued, the oldest one is dropped when full
+    Bounded { capacity: usize },
+    /// Up to `capacity` frames are queued, and the pipeline waits for the subscriber
+    /// when full. This stalls the whole pipeline, recordings included, so it is meant
+    /// for analysis consumers that must see every frame.
+    Lossless { capacity: usize },
+}
+
+impl BackpressurePolicy {
+    pub fn capacity(&self) -> usize {
+        match self {
+            BackpressurePolicy::LatestOnly => 1,
+            BackpressurePolicy::Bounded { capacity }
+            | BackpressurePolicy::Lossless { capacity } => (*capacity).max(1),
+        }
+    }
+}
+
+/// How a frame subscriber keeps up with its stream
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriberMetrics {
+    pub id: u64,
+    pub policy: BackpressurePolicy,
+    /// Frames waiting to be received
+    pub queued: usize,
+    pub delivered: u64,
+    /// Frames dropped because the queue was full
+    pub dropped: u64,
+    /// Time the pipeline spent waiting for a `Lossless` subscriber
+    pub blocked_ns: u64,
+}
+
+#[derive(Debug)]
+struct QueueState<T> {
+    frames: VecDeque<T>,
+    /// Dropped since the subscriber last received, reported as `RecvError::Lagged`
+    lagged: u64,
+    delivered: u64,
+    dropped: u64,
+    blocked: Duration,
+    /// Set when the stream stops, a `Lossless` sender no longer waits for space
+    draining: bool,
+    closed: bool,
+}
+
+#[derive(Debug)]
+struct FrameQueue<T> {
+    id: u64,
+    policy: BackpressurePolicy,
+    state: Mutex<QueueState<T>>,
+    /// Wakes a `Lossless` sender once a frame was received
+    space: Condvar,
+    /// Wakes the subscriber once a frame was queued
+    available: Notify,
+}
+
+impl<T> FrameQueue<T> {
+    fn new(id: u64, policy: BackpressurePolicy) -> Self {
+        Self {
+            id,
+            policy,
+            state: Mutex::new(QueueState {
+                frames: VecDeque::with_capacity(policy.capacity()),
+                lagged: 0,
+                delivered: 0,
+                dropped: 0,
+                blocked: Duration::ZERO,
+                draining: false,
+                closed: false,
+            }),
+            space: Condvar::new(),
+            available: Notify::new(),
+        }
+    }
+
+    /// Queues a frame according to the policy, returns false once the queue is closed
+    fn push(&self, frame: T) -> bool {
+        let capacity = self.policy.capacity();
+        let mut state = self.state.lock().unwrap();
+
+        if state.frames.len() >= capacity {
+            match self.policy {
+                BackpressurePolicy::Lossless { .. } => {
+                    let start = Instant::now();
+                    state = self
+                        .space
+                        .wait_while(state, |state| {
+                            state.frames.len() >= capacity && !state.closed && !state.draining
+                        })
+                        .unwrap();
+                    state.blocked += start.elapsed();
+                    // The queued frames are kept, the ones that no longer fit are dropped
+                    if state.draining && !state.closed && state.frames.len() >= capacity {
+                        state.dropped += 1;
+                        state.lagged += 1;
+                        return true;
+                    }
+                }
+                BackpressurePolicy::LatestOnly | BackpressurePolicy::Bounded { .. } => {
+                    state.frames.pop_front();
+                    state.dropped += 1;
+                    state.lagged += 1;
+                }
+            }
+        }
+        if state.closed {
+            return false;
+        }
+
+        state.frames.push_back(frame);
+        drop(state);
+        self.available.notify_one();
+        true
+    }
+
+    fn drain(&self) {
+        self.state.lock().unwrap().draining = true;
+        self.space.notify_all();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.space.notify_all();
+        self.available.notify_one();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    fn metrics(&self) -> SubscriberMetrics {
+        let state = self.state.lock().unwrap();
+        SubscriberMetrics {
+            id: self.id,
+            policy: self.policy,
+            queued: state.frames.len(),
+            delivered: state.delivered,
+            dropped: state.dropped,
+            blocked_ns: state.blocked.as_nanos() as u64,
+        }
+    }
+}
+
+/// Receives the frames of a stream with a `BackpressurePolicy`, created with
+/// `GstMediaStream::subscribe_with`. Errors like a `broadcast::Receiver`.
+#[derive(Debug)]
+pub struct FrameQueueReceiver<T = Arc<Buffer>> {
+    queue: Arc<FrameQueue<T>>,
+}
+
+impl<T> FrameQueueReceiver<T> {
+    /// The next frame, `RecvError::Lagged` once after frames were dropped, and
+    /// `RecvError::Closed` once the stream stopped and the queue is drained
+    pub async fn recv(&mut self) -> Result<T, RecvError> {
+        loop {
+            {
+                let mut state = self.queue.state.lock().unwrap();
+                if state.lagged > 0 {
+                    return Err(RecvError::Lagged(std::mem::take(&mut state.lagged)));
+                }
+                if let Some(frame) = state.frames.pop_front() {
+                    state.delivered += 1;
+                    drop(state);
+                    self.queue.space.notify_one();
+                    return Ok(frame);
+                }
+                if state.closed {
+                    return Err(RecvError::Closed);
+                }
+            }
+            // A frame queued since the check leaves a permit, so it is not missed
+            self.queue.available.notified().await;
+        }
+    }
+
+    pub fn metrics(&self) -> SubscriberMetrics {
+        self.queue.metrics()
+    }
+}
+
+impl<T> Drop for FrameQueueReceiver<T> {
+    fn drop(&mut self) {
+        // Unblocks the pipeline if it waits for this subscriber
+        self.queue.close();
+    }
+}
+
+/// Hands the frames of a pipeline to its subscribers, from the streaming thread
+#[derive(Debug)]
+pub struct FrameSender<T = Arc<Buffer>> {
+    /// Subscribers of `GstMediaStream::subscribe`, which only get the latest frame
+    latest_tx: broadcast::Sender<T>,
+    queues: Mutex<Vec<Arc<FrameQueue<T>>>>,
+    next_id: AtomicU64,
+}
+
+impl<T: Clone> Default for FrameSender<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> FrameSender<T> {
+    pub fn new() -> Self {
+        let (latest_tx, _) = broadcast::channel(1);
+        Self {
+            latest_tx,
+            queues: Mutex::new(vec![]),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<T> {
+        self.latest_tx.subscribe()
+    }
+
+    pub fn subscribe_with(&self, policy: BackpressurePolicy) -> FrameQueueReceiver<T> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let queue = Arc::new(FrameQueue::new(id, policy));
+        self.queues.lock().unwrap().push(queue.clone());
+        FrameQueueReceiver { queue }
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        self.latest_tx.receiver_count() > 0 || !self.queues.lock().unwrap().is_empty()
+    }
+
+    /// Hands a frame to every subscriber. Blocks while a `Lossless` subscriber is full.
+    pub fn send(&self, frame: T) {
+        if self.latest_tx.receiver_count() > 0 {
+            let _ = self.latest_tx.send(frame.clone());
+        }
+
+        // Not locked while sending, so that subscribing does not wait for a full queue
+        let queues = self.queues.lock().unwrap().clone();
+        let mut closed = false;
+        for queue in queues {
+            closed |= !queue.push(frame.clone());
+        }
+        if closed {
+            self.queues
+                .lock()
+                .unwrap()
+                .retain(|queue| !queue.is_closed());
+        }
+    }
+}
+
+impl<T> FrameSender<T> {
+    /// Stops waiting for `Lossless` subscribers that are full, so that a subscriber
+    /// which stopped receiving does not hold up the end of the stream. The frames
+    /// already queued are kept, the ones that no longer fit are dropped.
+    pub fn drain(&self) {
+        for queue in self.queues.lock().unwrap().iter() {
+            queue.drain();
+        }
+    }
+
+    /// Closes the queues of the subscribers, which receive what is left in them
+    /// and then `RecvError::Closed`
+    pub fn close(&self) {
+        for queue in self.queues.lock().unwrap().drain(..) {
+            queue.close();
+        }
+    }
+
+    /// Metrics of the subscribers with a `BackpressurePolicy`, the ones of
+    /// `subscribe` are not tracked
+    pub fn metrics(&self) -> Vec<SubscriberMetrics> {
+        self.queues
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|queue| queue.metrics())
+            .collect()
+    }
+}
+
+impl<T> Drop for FrameSender<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bounded_drops_oldest() {
+        let sender = FrameSender::<u32>::new();
+        let mut latest = sender.subscribe_with(BackpressurePolicy::LatestOnly);
+        let mut bounded = sender.subscribe_with(BackpressurePolicy::Bounded { capacity: 2 });
+
+        for frame in 0..5 {
+            sender.send(frame);
+        }
+
+        assert_eq!(latest.recv().await, Err(RecvError::Lagged(4)));
+        assert_eq!(latest.recv().await, Ok(4));
+        assert_eq!(bounded.recv().await, Err(RecvError::Lagged(3)));
+        assert_eq!(bounded.recv().await, Ok(3));
+        assert_eq!(bounded.recv().await, Ok(4));
+
+        let metrics = bounded.metrics();
+        assert_eq!((metrics.delivered, metrics.dropped), (2, 3));
+
+        // What is queued is still received after closing
+        sender.send(5);
+        sender.close();
+        assert_eq!(bounded.recv().await, Ok(5));
+        assert_eq!(bounded.recv().await, Err(RecvError::Closed));
+    }
+
+    #[tokio::test]
+    async fn test_lossless_waits_for_subscriber() {
+        let sender = Arc::new(FrameSender::<u32>::new());
+        let mut lossless = sender.subscribe_with(BackpressurePolicy::Lossless { capacity: 1 });
+
+        // Sends from a thread, as the streaming thread of a pipeline does
+        let streaming = std::thread::spawn({
+            let sender = sender.clone();
+            move || (0..100).for_each(|frame| sender.send(frame))
+        });
+
+        for frame in 0..100 {
+            assert_eq!(lossless.recv().await, Ok(frame));
+        }
+        streaming.join().unwrap();
+        assert_eq!(lossless.metrics().dropped, 0);
+
+        // A dropped subscriber no longer holds up the pipeline
+        sender.send(100);
+        drop(lossless);
+        sender.send(101);
+        assert!(!sender.has_subscribers());
+    }
+
+    #[tokio::test]
+    async fn test_drain_unblocks_full_lossless() {
+        let sender = Arc::new(FrameSender::<u32>::new());
+        let mut lossless = sender.subscribe_with(BackpressurePolicy::Lossless { capacity: 2 });
+
+        // The subscriber never receives, so the third frame waits for space
+        let streaming = std::thread::spawn({
+            let sender = sender.clone();
+            move || (0..3).for_each(|frame| sender.send(frame))
+        });
+        while lossless.metrics().queued < 2 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        sender.drain();
+        streaming.join().unwrap();
+        sender.close();
+
+        assert_eq!(lossless.recv().await, Err(RecvError::Lagged(1)));
+        assert_eq!(lossless.recv().await, Ok(0));
+        assert_eq!(lossless.recv().await, Ok(1));
+        assert_eq!(lossless.recv().await, Err(RecvError::Closed));
+    }
+}
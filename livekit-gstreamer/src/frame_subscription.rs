@@ -0,0 +1,477 @@
+use crate::audio_framing::AudioSampleFormat;
+use crate::frame_queue::{BackpressurePolicy, FrameQueueReceiver, FrameSender, SubscriberMetrics};
+use crate::stream_state::{stream_ended, StreamState};
+use crate::track_sync::StreamClock;
+use futures::stream::{BoxStream, StreamExt};
+use gstreamer::{prelude::*, Buffer, Caps, ClockTime, Sample};
+use gstreamer_video::{VideoFormat, VideoInfo, VideoMeta};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::{broadcast, watch};
+
+#[derive(Debug, Error)]
+pub enum FrameRecvError {
+    #[error("Lagged behind, {0} frames were dropped")]
+    Lagged(u64),
+    #[error("Invalid frame: {0}")]
+    InvalidFrame(String),
+    /// The stream stopped or failed, no more frames will arrive
+    #[error("Stream ended: {0:?}")]
+    Ended(StreamState),
+}
+
+/// Hands the frames of a pipeline to the subscribers of their buffers, see
+/// `GstMediaStream::subscribe`, and to those of their samples, which keep the caps
+/// each buffer was negotiated with for the typed frame subscriptions
+#[derive(Debug, Default)]
+pub struct StreamFrameSender {
+    buffers: FrameSender,
+    samples: FrameSender<Sample>,
+}
+
+impl StreamFrameSender {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Buffer>> {
+        self.buffers.subscribe()
+    }
+
+    pub fn subscribe_with(&self, policy: BackpressurePolicy) -> FrameQueueReceiver {
+        self.buffers.subscribe_with(policy)
+    }
+
+    pub fn subscribe_samples(&self, policy: BackpressurePolicy) -> FrameQueueReceiver<Sample> {
+        self.samples.subscribe_with(policy)
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        self.buffers.has_subscribers() || self.samples.has_subscribers()
+    }
+
+    /// Hands a sample to every subscriber. Blocks while a `Lossless` subscriber is full.
+    pub fn send(&self, sample: Sample) {
+        if self.buffers.has_subscribers() {
+            if let Some(buffer) = sample.buffer_owned() {
+                self.buffers.send(Arc::new(buffer));
+            }
+        }
+        if self.samples.has_subscribers() {
+            self.samples.send(sample);
+        }
+    }
+
+    /// See `FrameSender::drain`
+    pub fn drain(&self) {
+        self.buffers.drain();
+        self.samples.drain();
+    }
+
+    /// See `FrameSender::close`
+    pub fn close(&self) {
+        self.buffers.close();
+        self.samples.close();
+    }
+
+    pub fn metrics(&self) -> Vec<SubscriberMetrics> {
+        let mut metrics = self.buffers.metrics();
+        metrics.extend(self.samples.metrics());
+        metrics
+    }
+}
+
+/// A raw video frame of a stream, with the layout it was negotiated with
+#[derive(Debug, Clone)]
+pub struct StreamVideoFrame {
+    pub buffer: Arc<Buffer>,
+    pub caps: Caps,
+    pub info: VideoInfo,
+    /// Bytes per row of each plane, which may include padding
+    pub strides: Vec<i32>,
+    /// Byte offset of each plane in the buffer
+    pub offsets: Vec<usize>,
+    pub pts: Option<ClockTime>,
+    pub duration: Option<ClockTime>,
+    /// Wall clock time the frame was captured at, in nanoseconds since the unix epoch
+    pub capture_time_ns: Option<i64>,
+}
+
+impl StreamVideoFrame {
+    fn new(
+        buffer: Arc<Buffer>,
+        caps: Caps,
+        capture_time_ns: Option<i64>,
+    ) -> Result<Self, FrameRecvError> {
+        let info = VideoInfo::from_caps(&caps)
+            .map_err(|e| FrameRecvError::InvalidFrame(format!("Not a raw video frame: {}", e)))?;
+        // Upstream elements may lay out the planes differently than the caps imply
+        let (strides, offsets) = match buffer.meta::<VideoMeta>() {
+            Some(meta) => (meta.stride().to_vec(), meta.offset().to_vec()),
+            None => (info.stride().to_vec(), info.offset().to_vec()),
+        };
+
+        Ok(Self {
+            pts: buffer.pts(),
+            duration: buffer.duration(),
+            buffer,
+            caps,
+            info,
+            strides,
+            offsets,
+            capture_time_ns,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.info.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.info.height()
+    }
+
+    pub fn format(&self) -> VideoFormat {
+        self.info.format()
+    }
+}
+
+/// A buffer of raw audio samples of a stream
+#[derive(Debug, Clone)]
+pub struct StreamAudioFrame {
+    pub buffer: Arc<Buffer>,
+    pub caps: Caps,
+    /// The sample format as named in the caps, e.g. `S16LE`
+    pub format_name: String,
+    /// `None` for formats the audio framing does not handle
+    pub sample_format: Option<AudioSampleFormat>,
+    pub rate: u32,
+    pub channels: u32,
+    pub interleaved: bool,
+    pub samples_per_channel: Option<usize>,
+    pub pts: Option<ClockTime>,
+    pub duration: Option<ClockTime>,
+    /// Wall clock time the first sample was captured at, in nanoseconds since the unix epoch
+    pub capture_time_ns: Option<i64>,
+}
+
+impl StreamAudioFrame {
+    fn new(
+        buffer: Arc<Buffer>,
+        caps: Caps,
+        capture_time_ns: Option<i64>,
+    ) -> Result<Self, FrameRecvError> {
+        let structure = caps
+            .structure(0)
+            .filter(|s| s.has_name("audio/x-raw"))
+            .ok_or_else(|| FrameRecvError::InvalidFrame("Not a raw audio frame".to_string()))?;
+        let format_name = structure
+            .get::<&str>("format")
+            .map_err(invalid_audio_caps)?
+            .to_string();
+        let rate = structure.get::<i32>("rate").map_err(invalid_audio_caps)? as u32;
+        let channels = structure
+            .get::<i32>("channels")
+            .map_err(invalid_audio_caps)? as u32;
+        // Caps without a layout are interleaved
+        let interleaved = !matches!(structure.get::<&str>("layout"), Ok("non-interleaved"));
+
+        let sample_format = AudioSampleFormat::parse(&format_name);
+        let samples_per_channel = sample_format
+            .map(|format| buffer.size() / (format.bytes_per_sample() * channels.max(1) as usize));
+        let duration = buffer.duration().or_else(|| {
+            let samples = samples_per_channel? as u64;
+            (rate > 0).then(|| {
+                ClockTime::from_nseconds(samples * ClockTime::SECOND.nseconds() / rate as u64)
+            })
+        });
+
+        Ok(Self {
+            pts: buffer.pts(),
+            buffer,
+            caps,
+            format_name,
+            sample_format,
+            rate,
+            channels,
+            interleaved,
+            samples_per_channel,
+            duration,
+            capture_time_ns,
+        })
+    }
+}
+
+fn invalid_audio_caps(e: impl std::fmt::Display) -> FrameRecvError {
+    FrameRecvError::InvalidFrame(format!("Invalid audio caps: {}", e))
+}
+
+/// Receives the samples of a stream, each buffer with the caps it was negotiated with
+#[derive(Debug)]
+struct FrameReceiver {
+    frames_rx: FrameQueueReceiver<Sample>,
+    state_rx: watch::Receiver<StreamState>,
+    clock: Option<StreamClock>,
+}
+
+impl FrameReceiver {
+    async fn recv(&mut self) -> Result<(Arc<Buffer>, Caps, Option<i64>), FrameRecvError> {
+        // Frames still queued when the stream ends are received first
+        let frame = tokio::select! {
+            biased;
+            frame = self.frames_rx.recv() => frame,
+            state = stream_ended(&mut self.state_rx) => {
+                return Err(FrameRecvError::Ended(state));
+            }
+        };
+        let sample = match frame {
+            Ok(sample) => sample,
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                return Err(FrameRecvError::Lagged(count))
+            }
+            Err(broadcast::error::RecvError::Closed) => {
+                let state = self.state_rx.borrow().clone();
+                return Err(FrameRecvError::Ended(if state.has_ended() {
+                    state
+                } else {
+                    StreamState::Stopped
+                }));
+            }
+        };
+        let (buffer, caps) = sample_parts(sample)?;
+        let capture_time_ns = self
+            .clock
+            .as_ref()
+            .zip(buffer.pts())
+            .and_then(|(clock, pts)| clock.system_time_ns(pts));
+
+        Ok((buffer, caps, capture_time_ns))
+    }
+}
+
+/// The buffer of a sample along with the caps it was negotiated with, which
+/// stay those of the frame when the stream is renegotiated later
+fn sample_parts(sample: Sample) -> Result<(Arc<Buffer>, Caps), FrameRecvError> {
+    let caps = sample
+        .caps_owned()
+        .ok_or_else(|| FrameRecvError::InvalidFrame("Caps are not negotiated".to_string()))?;
+    let buffer = sample
+        .buffer_owned()
+        .ok_or_else(|| FrameRecvError::InvalidFrame("Sample has no buffer".to_string()))?;
+    Ok((Arc::new(buffer), caps))
+}
+
+/// Receives the frames of a video, screen or composite stream, created with
+/// `GstMediaStream::subscribe_video_frames`
+#[derive(Debug)]
+pub struct VideoFrameReceiver(FrameReceiver);
+
+impl VideoFrameReceiver {
+    pub(crate) fn new(
+        frames_rx: FrameQueueReceiver<Sample>,
+        state_rx: watch::Receiver<StreamState>,
+        clock: Option<StreamClock>,
+    ) -> Self {
+        Self(FrameReceiver {
+            frames_rx,
+            state_rx,
+            clock,
+        })
+    }
+
+    pub async fn recv(&mut self) -> Result<StreamVideoFrame, FrameRecvError> {
+        let (buffer, caps, capture_time_ns) = self.0.recv().await?;
+        StreamVideoFrame::new(buffer, caps, capture_time_ns)
+    }
+
+    pub fn metrics(&self) -> SubscriberMetrics {
+        self.0.frames_rx.metrics()
+    }
+
+    /// The frames as a stream, which ends with the stream. Lagging and invalid
+    /// frames are yielded as errors.
+    pub fn into_stream(self) -> BoxStream<'static, Result<StreamVideoFrame, FrameRecvError>> {
+        futures::stream::unfold(self, |mut receiver| async move {
+            match receiver.recv().await {
+                Err(FrameRecvError::Ended(_)) => None,
+                result => Some((result, receiver)),
+            }
+        })
+        .boxed()
+    }
+}
+
+/// Receives the sample buffers of an audio or mixer stream, created with
+/// `GstMediaStream::subscribe_audio_frames`
+#[derive(Debug)]
+pub struct AudioFrameReceiver(FrameReceiver);
+
+impl AudioFrameReceiver {
+    pub(crate) fn new(
+        frames_rx: FrameQueueReceiver<Sample>,
+        state_rx: watch::Receiver<StreamState>,
+        clock: Option<StreamClock>,
+    ) -> Self {
+        Self(FrameReceiver {
+            frames_rx,
+            state_rx,
+            clock,
+        })
+    }
+
+    pub async fn recv(&mut self) -> Result<StreamAudioFrame, FrameRecvError> {
+        let (buffer, caps, capture_time_ns) = self.0.recv().await?;
+        StreamAudioFrame::new(buffer, caps, capture_time_ns)
+    }
+
+    pub fn metrics(&self) -> SubscriberMetrics {
+        self.0.frames_rx.metrics()
+    }
+
+    /// The frames as a stream, which ends with the stream. Lagging and invalid
+    /// frames are yielded as errors.
+    pub fn into_stream(self) -> BoxStream<'static, Result<StreamAudioFrame, FrameRecvError>> {
+        futures::stream::unfold(self, |mut receiver| async move {
+            match receiver.recv().await {
+                Err(FrameRecvError::Ended(_)) => None,
+                result => Some((result, receiver)),
+            }
+        })
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gstreamer_video::VideoFrameFlags;
+
+    fn video_caps(width: i32, height: i32) -> Caps {
+        Caps::builder("video/x-raw")
+            .field("format", "I420")
+            .field("width", width)
+            .field("height", height)
+            .field("framerate", gstreamer::Fraction::new(30, 1))
+            .build()
+    }
+
+    fn audio_caps(format: &str, channels: i32, layout: &str) -> Caps {
+        Caps::builder("audio/x-raw")
+            .field("format", format)
+            .field("rate", 48000)
+            .field("channels", channels)
+            .field("layout", layout)
+            .build()
+    }
+
+    #[test]
+    fn test_video_frame_layout_from_caps() {
+        gstreamer::init().unwrap();
+        let caps = video_caps(320, 240);
+        let info = VideoInfo::from_caps(&caps).unwrap();
+        let mut buffer = Buffer::with_size(info.size()).unwrap();
+        buffer
+            .get_mut()
+            .unwrap()
+            .set_pts(ClockTime::from_mseconds(40));
+
+        let frame = StreamVideoFrame::new(Arc::new(buffer), caps, Some(1)).unwrap();
+        assert_eq!((frame.width(), frame.height()), (320, 240));
+        assert_eq!(frame.format(), VideoFormat::I420);
+        assert_eq!(frame.strides, info.stride().to_vec());
+        assert_eq!(frame.offsets, info.offset().to_vec());
+        assert_eq!(frame.pts, Some(ClockTime::from_mseconds(40)));
+        assert_eq!(frame.capture_time_ns, Some(1));
+    }
+
+    #[test]
+    fn test_video_frame_layout_from_meta() {
+        gstreamer::init().unwrap();
+        // Rows padded to 384 bytes, as some upstream elements lay them out
+        let strides = [384, 192, 192];
+        let offsets = [0, 384 * 240, 384 * 240 + 192 * 120];
+        let mut buffer = Buffer::with_size(384 * 240 + 2 * 192 * 120).unwrap();
+        gstreamer_video::VideoMeta::add_full(
+            buffer.get_mut().unwrap(),
+            VideoFrameFlags::empty(),
+            VideoFormat::I420,
+            320,
+            240,
+            &offsets,
+            &strides,
+        )
+        .unwrap();
+
+        let frame = StreamVideoFrame::new(Arc::new(buffer), video_caps(320, 240), None).unwrap();
+        assert_eq!(frame.strides, strides.to_vec());
+        assert_eq!(frame.offsets, offsets.to_vec());
+    }
+
+    #[test]
+    fn test_video_frame_rejects_audio_caps() {
+        gstreamer::init().unwrap();
+        let buffer = Buffer::with_size(16).unwrap();
+        let result = StreamVideoFrame::new(
+            Arc::new(buffer),
+            audio_caps("S16LE", 2, "interleaved"),
+            None,
+        );
+        assert!(matches!(result, Err(FrameRecvError::InvalidFrame(_))));
+    }
+
+    #[test]
+    fn test_audio_frame_derives_duration() {
+        gstreamer::init().unwrap();
+        // 10 ms of stereo S16LE at 48 kHz
+        let buffer = Buffer::with_size(480 * 2 * 2).unwrap();
+
+        let frame = StreamAudioFrame::new(
+            Arc::new(buffer),
+            audio_caps("S16LE", 2, "interleaved"),
+            None,
+        )
+        .unwrap();
+        assert_eq!(frame.format_name, "S16LE");
+        assert_eq!(frame.sample_format, Some(AudioSampleFormat::S16LE));
+        assert_eq!((frame.rate, frame.channels), (48000, 2));
+        assert!(frame.interleaved);
+        assert_eq!(frame.samples_per_channel, Some(480));
+        assert_eq!(frame.duration, Some(ClockTime::from_mseconds(10)));
+    }
+
+    #[test]
+    fn test_audio_frame_keeps_buffer_duration() {
+        gstreamer::init().unwrap();
+        let mut buffer = Buffer::with_size(480 * 4).unwrap();
+        buffer
+            .get_mut()
+            .unwrap()
+            .set_duration(ClockTime::from_mseconds(20));
+
+        let frame = StreamAudioFrame::new(
+            Arc::new(buffer),
+            audio_caps("F32LE", 1, "non-interleaved"),
+            None,
+        )
+        .unwrap();
+        assert!(!frame.interleaved);
+        assert_eq!(frame.samples_per_channel, Some(480));
+        assert_eq!(frame.duration, Some(ClockTime::from_mseconds(20)));
+    }
+
+    #[test]
+    fn test_audio_frame_unknown_format() {
+        gstreamer::init().unwrap();
+        let buffer = Buffer::with_size(64).unwrap();
+
+        let frame = StreamAudioFrame::new(
+            Arc::new(buffer),
+            audio_caps("S24_32LE", 2, "interleaved"),
+            None,
+        )
+        .unwrap();
+        assert_eq!(frame.sample_format, None);
+        assert_eq!(frame.samples_per_channel, None);
+        assert_eq!(frame.duration, None);
+    }
+}
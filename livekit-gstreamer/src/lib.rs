@@ -1,13 +1,45 @@
+pub mod audio_framing;
+pub mod audio_mixer;
+pub mod capability_negotiation;
 pub mod devices;
+pub mod frame_queue;
+pub mod frame_subscription;
+pub mod lk_e2ee;
 pub mod lk_participant;
+pub mod lk_resilient;
+pub mod lk_subscriber;
 pub mod media_device;
 pub mod media_stream;
+pub mod pipeline_debug;
+pub mod remote_control;
+pub mod stream_state;
+pub mod track_metadata;
+pub mod track_stats;
+pub mod track_sync;
 pub mod utils;
+pub mod video_compositor;
+pub mod voice_activity;
 
+pub use audio_framing::*;
+pub use audio_mixer::*;
+pub use capability_negotiation::*;
 pub use devices::*;
+pub use frame_queue::*;
+pub use frame_subscription::*;
+pub use lk_e2ee::*;
 pub use lk_participant::*;
+pub use lk_resilient::*;
+pub use lk_subscriber::*;
 pub use media_device::*;
 pub use media_stream::*;
+pub use pipeline_debug::*;
+pub use remote_control::*;
+pub use stream_state::*;
+pub use track_metadata::*;
+pub use track_stats::*;
+pub use track_sync::*;
+pub use video_compositor::*;
+pub use voice_activity::*;
 
 pub fn initialize_gstreamer() {
     gstreamer::init().expect("Failed to initialize GStreamer");
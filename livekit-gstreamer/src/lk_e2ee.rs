@@ -0,0 +1,56 @@
+use crate::lk_participant::LKParticipantError;
+use livekit::e2ee::key_provider::{KeyProvider, KeyProviderOptions};
+use livekit::e2ee::{E2eeOptions, EncryptionType};
+use livekit::RoomOptions;
+use serde::Deserialize;
+use std::fmt::{self, Debug, Formatter};
+
+/// Shared key end-to-end encryption of the published tracks. Frames are encrypted
+/// before they leave the publisher, so the media server only ever forwards frames
+/// it cannot decrypt. Subscribers need the same key.
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LKE2eeOptions {
+    pub shared_key: String,
+}
+
+// The key is left out so that it does not end up in logs
+impl Debug for LKE2eeOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LKE2eeOptions").finish_non_exhaustive()
+    }
+}
+
+impl LKE2eeOptions {
+    pub fn new(shared_key: impl Into<String>) -> Self {
+        Self {
+            shared_key: shared_key.into(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), LKParticipantError> {
+        if self.shared_key.is_empty() {
+            return Err(LKParticipantError::StreamingError(
+                "The E2EE shared key is empty".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn e2ee_options(&self) -> E2eeOptions {
+        E2eeOptions {
+            encryption_type: EncryptionType::Gcm,
+            key_provider: KeyProvider::with_shared_key(
+                KeyProviderOptions::default(),
+                self.shared_key.as_bytes().to_vec(),
+            ),
+        }
+    }
+
+    /// Options to connect to the room with, E2EE can only be set up when joining
+    pub fn room_options(&self) -> RoomOptions {
+        let mut options = RoomOptions::default();
+        options.e2ee = Some(self.e2ee_options());
+        options
+    }
+}
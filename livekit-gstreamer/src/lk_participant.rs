@@ -1,22 +1,45 @@
-use crate::media_device::GStreamerError;
+use crate::audio_framing::{AudioFrameAdapter, AudioSampleFormat};
+use crate::frame_queue::BackpressurePolicy;
+use crate::frame_subscription::{AudioFrameReceiver, FrameRecvError, VideoFrameReceiver};
+use crate::lk_e2ee::LKE2eeOptions;
+use crate::media_device::{GStreamerError, VideoStreamFormat};
 use crate::media_stream::{GstMediaStream, PublishOptions};
-use crate::utils::random_string;
+use crate::stream_state::StreamState;
+use crate::track_metadata::{
+    device_track_name, LKTrackMetadata, HOST_NAME_ATTRIBUTE, TRACK_METADATA_ATTRIBUTE_PREFIX,
+};
+use crate::track_stats::{LKSenderStats, LKTrackStats, TrackCounters};
+use crate::track_sync::{track_sync_task, StreamClock};
 use gstreamer::Buffer;
-use livekit::options::{TrackPublishOptions, VideoCodec};
+use gstreamer_video::{prelude::*, VideoFormat, VideoFrameRef, VideoInfo};
+use livekit::options::{
+    compute_appropriate_encoding, compute_video_encodings, AudioEncoding, TrackPublishOptions,
+    VideoCodec, VideoEncoding,
+};
 use livekit::track::{LocalAudioTrack, LocalTrack, LocalVideoTrack, TrackSource};
 use livekit::webrtc::audio_source::native::NativeAudioSource;
 use livekit::webrtc::prelude::{
-    AudioFrame, I420Buffer, RtcAudioSource, RtcVideoSource, VideoFrame, VideoResolution,
-    VideoRotation,
+    AudioFrame, BoxVideoBuffer, I420Buffer, NV12Buffer, RtcAudioSource, RtcVideoSource, VideoFrame,
+    VideoResolution, VideoRotation,
 };
 use livekit::webrtc::video_source::native::NativeVideoSource;
-use livekit::{Room, RoomError};
+use livekit::{Room, RoomError, RoomEvent, RoomOptions};
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use thiserror::Error;
 use tokio::sync::broadcast;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+/// Audio buffers queued for a track, so that a buffer late by up to about 100 ms is
+/// still sent rather than leaving a gap
+const AUDIO_TRACK_QUEUE_CAPACITY: usize = 10;
 
 #[derive(Error, Debug)]
 pub enum LKParticipantError {
@@ -34,22 +57,334 @@ impl From<RoomError> for LKParticipantError {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LKVideoCodec {
+    VP8,
+    VP9,
+    H264,
+    AV1,
+}
+
+impl From<LKVideoCodec> for VideoCodec {
+    fn from(codec: LKVideoCodec) -> Self {
+        match codec {
+            LKVideoCodec::VP8 => VideoCodec::VP8,
+            LKVideoCodec::VP9 => VideoCodec::VP9,
+            LKVideoCodec::H264 => VideoCodec::H264,
+            LKVideoCodec::AV1 => VideoCodec::AV1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LKTrackSource {
+    Camera,
+    Microphone,
+    Screenshare,
+    ScreenshareAudio,
+}
+
+impl From<LKTrackSource> for TrackSource {
+    fn from(source: LKTrackSource) -> Self {
+        match source {
+            LKTrackSource::Camera => TrackSource::Camera,
+            LKTrackSource::Microphone => TrackSource::Microphone,
+            LKTrackSource::Screenshare => TrackSource::Screenshare,
+            LKTrackSource::ScreenshareAudio => TrackSource::ScreenshareAudio,
+        }
+    }
+}
+
+/// LiveKit side settings for publishing a stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LKPublishSettings {
+    /// Publish video with simulcast layers, ignored for audio. libwebrtc downscales
+    /// the lower layers from the frames produced by the GStreamer pipeline, see
+    /// `video_layers` for their resolutions and bitrate caps.
+    pub simulcast: bool,
+    /// Defaults to VP9
+    pub video_codec: Option<LKVideoCodec>,
+    /// Bitrate cap in bits per second, of the full resolution layer when simulcasting
+    pub max_bitrate: Option<u64>,
+    /// Framerate cap for video, defaults to the stream framerate
+    pub max_framerate: Option<f64>,
+    /// Discontinuous transmission for audio
+    pub dtx: bool,
+    /// Redundant audio encoding
+    pub red: bool,
+    /// Defaults to camera, screen share or microphone depending on the stream
+    pub source: Option<LKTrackSource>,
+    /// How often to publish a `TrackSyncPacket` for the track on the data channel,
+    /// `None` disables the sync packets
+    pub sync_interval_ms: Option<u64>,
+    /// Describe the capture device of the track in the participant attributes, see
+    /// `LKTrackMetadata`
+    pub publish_metadata: bool,
+    /// Host name of the publishing machine, added to the track metadata
+    pub host_name: Option<String>,
+}
+
+impl Default for LKPublishSettings {
+    fn default() -> Self {
+        Self {
+            simulcast: false,
+            video_codec: None,
+            max_bitrate: None,
+            max_framerate: None,
+            dtx: true,
+            red: true,
+            source: None,
+            sync_interval_ms: Some(1000),
+            publish_metadata: true,
+            host_name: None,
+        }
+    }
+}
+
+/// An encoding a video track is published with, one per simulcast layer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LKVideoLayer {
+    /// `f`, `h` and `q` from the highest to the lowest simulcast layer
+    pub rid: String,
+    pub width: u32,
+    pub height: u32,
+    pub max_bitrate: Option<u64>,
+    pub max_framerate: Option<f64>,
+}
+
+/// Default source, format and framerate of the track of a video stream, `None`
+/// for audio streams
+fn video_track_format(details: &PublishOptions) -> Option<(TrackSource, VideoStreamFormat, i32)> {
+    match details {
+        PublishOptions::Video(details) => Some((
+            TrackSource::Camera,
+            details.stream_format(),
+            details.framerate,
+        )),
+        PublishOptions::Screen(details) => Some((
+            TrackSource::Screenshare,
+            details.stream_format(),
+            details.framerate,
+        )),
+        PublishOptions::Composite(details) => Some((
+            TrackSource::Camera,
+            details.stream_format(),
+            details.framerate,
+        )),
+        PublishOptions::Audio(_) | PublishOptions::Mixer(_) => None,
+    }
+}
+
+impl LKPublishSettings {
+    fn video_track_options(
+        &self,
+        source: TrackSource,
+        width: i32,
+        height: i32,
+        framerate: i32,
+    ) -> TrackPublishOptions {
+        let source = self.source.map(TrackSource::from).unwrap_or(source);
+        let video_codec = self
+            .video_codec
+            .map(VideoCodec::from)
+            .unwrap_or(VideoCodec::VP9);
+
+        let video_encoding = match (self.max_bitrate, self.max_framerate) {
+            (None, None) => None,
+            (max_bitrate, max_framerate) => Some(VideoEncoding {
+                max_bitrate: max_bitrate.unwrap_or_else(|| {
+                    compute_appropriate_encoding(
+                        source == TrackSource::Screenshare,
+                        width as u32,
+                        height as u32,
+                        video_codec,
+                    )
+                    .max_bitrate
+                }),
+                max_framerate: max_framerate.unwrap_or(framerate as f64),
+            }),
+        };
+
+        TrackPublishOptions {
+            source,
+            simulcast: self.simulcast,
+            video_codec,
+            video_encoding,
+            ..Default::default()
+        }
+    }
+
+    /// The layers the track of a video stream is published with, highest first, and
+    /// none for audio streams. LiveKit computes the encodings in `publish_track` and
+    /// does not take custom ones, so only the full resolution layer follows
+    /// `max_bitrate` and `max_framerate`. The lower layers, if any, use the LiveKit
+    /// simulcast presets for the aspect ratio of the stream, depending on its size.
+    pub fn video_layers(&self, details: &PublishOptions) -> Vec<LKVideoLayer> {
+        let (source, stream_format, framerate) = match video_track_format(details) {
+            Some(format) => format,
+            None => return vec![],
+        };
+        let options =
+            self.video_track_options(source, stream_format.width, stream_format.height, framerate);
+        let (width, height) = (stream_format.width as u32, stream_format.height as u32);
+
+        compute_video_encodings(width, height, &options)
+            .into_iter()
+            .map(|encoding| {
+                let scale = encoding.scale_resolution_down_by.unwrap_or(1.0);
+                LKVideoLayer {
+                    rid: encoding.rid,
+                    width: (width as f64 / scale) as u32,
+                    height: (height as f64 / scale) as u32,
+                    max_bitrate: encoding.max_bitrate,
+                    max_framerate: encoding.max_framerate,
+                }
+            })
+            .collect()
+    }
+
+    fn audio_track_options(&self, source: TrackSource) -> TrackPublishOptions {
+        TrackPublishOptions {
+            source: self.source.map(TrackSource::from).unwrap_or(source),
+            audio_encoding: self
+                .max_bitrate
+                .map(|max_bitrate| AudioEncoding { max_bitrate }),
+            dtx: self.dtx,
+            red: self.red,
+            ..Default::default()
+        }
+    }
+}
+
 pub struct LKParticipant {
     room: Arc<Room>,
     published_tracks: HashMap<String, TrackHandle>,
+    events_tx: broadcast::Sender<LKTrackEvent>,
+    /// Tracks that would not be end-to-end encrypted are refused when set
+    encrypted: bool,
 }
 
 struct TrackHandle {
     track: LocalTrack,
+    rtc_source: TrackRtcSource,
+    details: PublishOptions,
+    settings: LKPublishSettings,
+    /// Set for streams published with `publish_owned_stream`
+    stream: Option<GstMediaStream>,
     task: tokio::task::JoinHandle<()>,
+    sync_task: Option<tokio::task::JoinHandle<()>>,
+    /// Frames are dropped instead of forwarded to WebRTC while set
+    paused: Arc<AtomicBool>,
+    counters: Arc<TrackCounters>,
+}
+
+enum TrackRtcSource {
+    Video(NativeVideoSource),
+    Audio(NativeAudioSource),
+}
+
+/// A track published by `LKParticipant`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LKPublishedTrack {
+    /// The LiveKit track SID
+    pub sid: String,
+    pub name: String,
+    /// Options of the stream currently forwarded on the track
+    pub options: PublishOptions,
+    pub settings: LKPublishSettings,
+    pub muted: bool,
+    /// Whether the participant owns the stream and stops it when unpublishing
+    pub owns_stream: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum LKTrackEvent {
+    /// The stream forwarded on a published track ended, because it was stopped or
+    /// its pipeline finished or failed. The track stays published, without frames,
+    /// until it is unpublished or its stream is replaced.
+    StreamEnded {
+        track_sid: String,
+        track_name: String,
+        /// `Stopped`, or `Failed` with the reason the pipeline failed
+        state: StreamState,
+    },
+}
+
+/// Sample rate and number of channels of an audio stream, `None` for video streams
+fn audio_stream_format(details: &PublishOptions) -> Option<(i32, i32)> {
+    match details {
+        PublishOptions::Audio(details) => Some((
+            details.framerate,
+            match details.selected_channel {
+                Some(_) => 1,
+                None => details.channels,
+            },
+        )),
+        PublishOptions::Mixer(details) => Some((details.framerate, details.channels)),
+        PublishOptions::Video(_) | PublishOptions::Screen(_) | PublishOptions::Composite(_) => None,
+    }
 }
 
 impl LKParticipant {
     pub fn new(room: Arc<Room>) -> Self {
+        let (events_tx, _) = broadcast::channel(16);
         Self {
             room,
             published_tracks: HashMap::new(),
+            events_tx,
+            encrypted: false,
+        }
+    }
+
+    /// A participant for a room joined with E2EE, see `LKE2eeOptions::room_options`.
+    /// Publishing fails for any track the room would not encrypt.
+    pub fn with_e2ee(room: Arc<Room>) -> Result<Self, LKParticipantError> {
+        if !room.e2ee_manager().enabled() {
+            return Err(LKParticipantError::StreamingError(
+                "The room was not joined with E2EE".to_string(),
+            ));
         }
+        let mut participant = Self::new(room);
+        participant.encrypted = true;
+        Ok(participant)
+    }
+
+    /// Joins the room, with every published track end-to-end encrypted when `e2ee`
+    /// is given
+    pub async fn connect(
+        url: &str,
+        token: &str,
+        e2ee: Option<&LKE2eeOptions>,
+    ) -> Result<(Self, UnboundedReceiver<RoomEvent>), LKParticipantError> {
+        let options = match e2ee {
+            Some(e2ee) => {
+                e2ee.validate()?;
+                e2ee.room_options()
+            }
+            None => RoomOptions::default(),
+        };
+        let (room, room_rx) = Room::connect(url, token, options).await?;
+        let room = Arc::new(room);
+        let participant = match e2ee {
+            Some(_) => Self::with_e2ee(room)?,
+            None => Self::new(room),
+        };
+        Ok((participant, room_rx))
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    pub fn room(&self) -> Arc<Room> {
+        self.room.clone()
+    }
+
+    pub fn subscribe_track_events(&self) -> broadcast::Receiver<LKTrackEvent> {
+        self.events_tx.subscribe()
     }
 
     pub async fn publish_stream(
@@ -57,241 +392,746 @@ impl LKParticipant {
         stream: &mut GstMediaStream,
         track_name: Option<String>,
     ) -> Result<String, LKParticipantError> {
-        if !stream.has_started() {
+        self.publish_stream_with_settings(stream, track_name, LKPublishSettings::default())
+            .await
+    }
+
+    /// Publishes the stream and returns the LiveKit SID of its track
+    pub async fn publish_stream_with_settings(
+        &mut self,
+        stream: &mut GstMediaStream,
+        track_name: Option<String>,
+        settings: LKPublishSettings,
+    ) -> Result<String, LKParticipantError> {
+        if !stream.state().is_active() {
             stream.start().await?;
         }
         // This unwrap is safe because we know the stream has started
-        let (frames_rx, close_rx) = stream.subscribe().unwrap();
         let details = stream.details().unwrap();
-        let track_name = track_name.unwrap_or(stream.get_device_name().unwrap());
+        let track_name = track_name.unwrap_or(device_track_name(stream).unwrap());
 
-        match details {
+        let (track, rtc_source) = match &details {
             PublishOptions::Video(details) => {
-                // let rtc_source = NativeVideoSource::new(VideoResolution {
-                //     width: details.width as u32,
-                //     height: details.height as u32,
-                // });
-                let rtc_source = NativeVideoSource::new(VideoResolution {
-                    width: 640,
-                    height: 480,
-                });
-
-                let track = LocalVideoTrack::create_video_track(
+                let stream_format = details.stream_format();
+                let options = settings.video_track_options(
+                    TrackSource::Camera,
+                    stream_format.width,
+                    stream_format.height,
+                    details.framerate,
+                );
+                self.publish_video_track(&track_name, stream_format, options)
+                    .await
+            }
+            PublishOptions::Audio(details) => {
+                let num_channels = match details.selected_channel {
+                    Some(_) => 1,
+                    None => details.channels,
+                };
+                self.publish_audio_track(
                     &track_name,
-                    RtcVideoSource::Native(rtc_source.clone()),
+                    details.framerate,
+                    num_channels,
+                    settings.audio_track_options(TrackSource::Microphone),
+                )
+                .await
+            }
+            PublishOptions::Mixer(details) => {
+                self.publish_audio_track(
+                    &track_name,
+                    details.framerate,
+                    details.channels,
+                    settings.audio_track_options(TrackSource::Microphone),
+                )
+                .await
+            }
+            PublishOptions::Screen(details) => {
+                let stream_format = details.stream_format();
+                let options = settings.video_track_options(
+                    TrackSource::Screenshare,
+                    stream_format.width,
+                    stream_format.height,
+                    details.framerate,
                 );
+                self.publish_video_track(&track_name, stream_format, options)
+                    .await
+            }
+            PublishOptions::Composite(details) => {
+                let stream_format = details.stream_format();
+                let options = settings.video_track_options(
+                    TrackSource::Camera,
+                    stream_format.width,
+                    stream_format.height,
+                    details.framerate,
+                );
+                self.publish_video_track(&track_name, stream_format, options)
+                    .await
+            }
+        }?;
 
-                let track_sid = random_string("video-track");
+        let paused = Arc::new(AtomicBool::new(false));
+        let counters = Arc::new(TrackCounters::default());
+        let task = match self.check_encrypted(&track).and_then(|()| {
+            Self::spawn_forwarding(
+                self.events_tx.clone(),
+                &track,
+                &rtc_source,
+                stream,
+                paused.clone(),
+                counters.clone(),
+            )
+        }) {
+            Ok(task) => task,
+            Err(e) => {
+                let _ = self
+                    .room
+                    .local_participant()
+                    .unpublish_track(&track.sid())
+                    .await;
+                return Err(e);
+            }
+        };
+        let sync_task =
+            Self::spawn_sync_task(self.room.clone(), stream, &track, settings.sync_interval_ms);
 
-                let task = tokio::spawn(Self::video_track_task(
-                    close_rx,
-                    frames_rx,
-                    rtc_source.clone(),
-                ));
+        let track_sid = String::from(track.sid());
+        self.published_tracks.insert(
+            track_sid.clone(),
+            TrackHandle {
+                track,
+                rtc_source,
+                details,
+                settings,
+                stream: None,
+                task,
+                sync_task,
+                paused,
+                counters,
+            },
+        );
+        self.publish_track_metadata(&track_sid, stream).await;
 
-                self.room
-                    .local_participant()
-                    .publish_track(
-                        LocalTrack::Video(track.clone()),
-                        TrackPublishOptions {
-                            source: TrackSource::Camera,
-                            simulcast: false,
-                            video_codec: VideoCodec::VP9,
-                            ..Default::default()
-                        },
-                    )
-                    .await?;
-
-                self.published_tracks.insert(
-                    track_sid.clone(),
-                    TrackHandle {
-                        track: LocalTrack::Video(track),
-                        task,
-                    },
-                );
+        Ok(track_sid)
+    }
 
-                Ok(track_sid)
+    /// Makes sure a frame cryptor was attached to the track when it was published,
+    /// before any of its frames are forwarded
+    fn check_encrypted(&self, track: &LocalTrack) -> Result<(), LKParticipantError> {
+        if !self.encrypted {
+            return Ok(());
+        }
+        let key = (self.room.local_participant().identity(), track.sid());
+        match self.room.e2ee_manager().frame_cryptors().get(&key) {
+            Some(cryptor) if cryptor.enabled() => Ok(()),
+            _ => Err(LKParticipantError::StreamingError(format!(
+                "Track {} is not end-to-end encrypted",
+                track.sid()
+            ))),
+        }
+    }
+
+    /// Describes the device of the track in the participant attributes. Failing to
+    /// do so does not affect the track, so errors are only logged.
+    async fn publish_track_metadata(&self, track_sid: &str, stream: &GstMediaStream) {
+        let Some(handle) = self.published_tracks.get(track_sid) else {
+            return;
+        };
+        if !handle.settings.publish_metadata {
+            return;
+        }
+        let Some(metadata) = LKTrackMetadata::from_stream(
+            stream,
+            track_sid,
+            &handle.track.name(),
+            handle.settings.host_name.clone(),
+        ) else {
+            return;
+        };
+
+        let mut attributes = HashMap::new();
+        if let Some(host_name) = &metadata.host_name {
+            attributes.insert(HOST_NAME_ATTRIBUTE.to_string(), host_name.clone());
+        }
+        match serde_json::to_string(&metadata) {
+            Ok(value) => {
+                attributes.insert(metadata.attribute_key(), value);
             }
-            PublishOptions::Audio(details) => {
-                let rtc_source = match details.selected_channel {
-                    Some(_) => NativeAudioSource::new(
-                        Default::default(),
-                        details.framerate as u32,
-                        1,
-                        2000,
-                    ),
-                    None => NativeAudioSource::new(
-                        Default::default(),
-                        details.framerate as u32,
-                        details.channels as u32,
-                        2000,
-                    ),
-                };
+            Err(e) => {
+                eprintln!("Failed to serialize metadata of track {}: {}", track_sid, e);
+                return;
+            }
+        }
 
-                let track = LocalAudioTrack::create_audio_track(
-                    &track_name,
-                    RtcAudioSource::Native(rtc_source.clone()),
-                );
+        if let Err(e) = self
+            .room
+            .local_participant()
+            .set_attributes(attributes)
+            .await
+        {
+            eprintln!("Failed to publish metadata of track {}: {}", track_sid, e);
+        }
+    }
 
-                let track_sid = random_string("audio-track");
+    /// Publishes a stream the participant takes ownership of. The stream is stopped,
+    /// finishing its recording, when the track is unpublished.
+    pub async fn publish_owned_stream(
+        &mut self,
+        mut stream: GstMediaStream,
+        track_name: Option<String>,
+        settings: LKPublishSettings,
+    ) -> Result<String, LKParticipantError> {
+        let result = self
+            .publish_stream_with_settings(&mut stream, track_name, settings)
+            .await;
+        match result {
+            Ok(track_sid) => {
+                // This unwrap is safe because the track was just published
+                self.published_tracks.get_mut(&track_sid).unwrap().stream = Some(stream);
+                Ok(track_sid)
+            }
+            Err(e) => {
+                stream.stop().await?;
+                Err(e)
+            }
+        }
+    }
 
-                let task = tokio::spawn(Self::audio_track_task(
-                    close_rx,
+    /// Spawns the task forwarding the frames of `stream` to the WebRTC source of a
+    /// track, which emits `LKTrackEvent::StreamEnded` once the stream ends
+    fn spawn_forwarding(
+        events_tx: broadcast::Sender<LKTrackEvent>,
+        track: &LocalTrack,
+        rtc_source: &TrackRtcSource,
+        stream: &GstMediaStream,
+        paused: Arc<AtomicBool>,
+        counters: Arc<TrackCounters>,
+    ) -> Result<tokio::task::JoinHandle<()>, LKParticipantError> {
+        let details = stream.details().ok_or_else(|| {
+            LKParticipantError::StreamingError("Stream has not started".to_string())
+        })?;
+        let clock = stream.clock();
+
+        let forwarding: Pin<Box<dyn Future<Output = StreamState> + Send>> = match rtc_source {
+            TrackRtcSource::Video(rtc_source) => {
+                let frames_rx = stream
+                    .subscribe_video_frames(BackpressurePolicy::LatestOnly)
+                    .ok_or_else(|| {
+                        LKParticipantError::StreamingError(
+                            "Cannot forward an audio stream to a video track".to_string(),
+                        )
+                    })?;
+                Box::pin(Self::video_track_task(
                     frames_rx,
                     rtc_source.clone(),
-                ));
-
-                self.room
-                    .local_participant()
-                    .publish_track(
-                        LocalTrack::Audio(track.clone()),
-                        TrackPublishOptions {
-                            source: TrackSource::Microphone,
-                            ..Default::default()
-                        },
+                    paused,
+                    counters,
+                    clock,
+                ))
+            }
+            TrackRtcSource::Audio(rtc_source) => {
+                let (framerate, num_channels) = audio_stream_format(&details).ok_or_else(|| {
+                    LKParticipantError::StreamingError(
+                        "Cannot forward a video stream to an audio track".to_string(),
                     )
-                    .await?;
-
-                self.published_tracks.insert(
-                    track_sid.clone(),
-                    TrackHandle {
-                        track: LocalTrack::Audio(track),
-                        task,
-                    },
-                );
-
-                Ok(track_sid)
+                })?;
+                let frames_rx = stream
+                    .subscribe_audio_frames(BackpressurePolicy::Bounded {
+                        capacity: AUDIO_TRACK_QUEUE_CAPACITY,
+                    })
+                    .ok_or_else(|| {
+                        LKParticipantError::StreamingError(
+                            "Stream does not hand out audio frames".to_string(),
+                        )
+                    })?;
+                if framerate as u32 != rtc_source.sample_rate()
+                    || num_channels as u32 != rtc_source.num_channels()
+                {
+                    return Err(LKParticipantError::StreamingError(format!(
+                        "Audio stream with {} channels at {} Hz does not match the track",
+                        num_channels, framerate
+                    )));
+                }
+                Box::pin(Self::audio_track_task(
+                    frames_rx,
+                    rtc_source.clone(),
+                    paused,
+                    counters,
+                    clock,
+                ))
             }
-            PublishOptions::Screen(details) => {
-                // let rtc_source = NativeVideoSource::new(VideoResolution {
-                //     width: details.width as u32,
-                //     height: details.height as u32,
-                // });
+        };
 
-                let rtc_source = NativeVideoSource::new(VideoResolution {
-                    width: 640,
-                    height: 480,
-                });
+        let track_sid = String::from(track.sid());
+        let track_name = track.name();
+        Ok(tokio::spawn(async move {
+            let state = forwarding.await;
+            let _ = events_tx.send(LKTrackEvent::StreamEnded {
+                track_sid,
+                track_name,
+                state,
+            });
+        }))
+    }
 
-                let track = LocalVideoTrack::create_video_track(
-                    &track_name,
-                    RtcVideoSource::Native(rtc_source.clone()),
-                );
+    fn spawn_sync_task(
+        room: Arc<Room>,
+        stream: &GstMediaStream,
+        track: &LocalTrack,
+        sync_interval_ms: Option<u64>,
+    ) -> Option<tokio::task::JoinHandle<()>> {
+        let interval_ms = sync_interval_ms?;
+        let clock = stream.clock()?;
+        let (frames_rx, state_rx) = stream.subscribe()?;
+        Some(tokio::spawn(track_sync_task(
+            room,
+            clock,
+            String::from(track.sid()),
+            track.name(),
+            Duration::from_millis(interval_ms),
+            frames_rx,
+            state_rx,
+        )))
+    }
 
-                let track_sid = random_string("screen-track");
+    async fn publish_video_track(
+        &self,
+        track_name: &str,
+        stream_format: VideoStreamFormat,
+        options: TrackPublishOptions,
+    ) -> Result<(LocalTrack, TrackRtcSource), LKParticipantError> {
+        let rtc_source = NativeVideoSource::new(VideoResolution {
+            width: stream_format.width as u32,
+            height: stream_format.height as u32,
+        });
 
-                let task = tokio::spawn(Self::video_track_task(
-                    close_rx,
-                    frames_rx,
-                    rtc_source.clone(),
-                ));
+        let track = LocalTrack::Video(LocalVideoTrack::create_video_track(
+            track_name,
+            RtcVideoSource::Native(rtc_source.clone()),
+        ));
 
-                self.room
-                    .local_participant()
-                    .publish_track(
-                        LocalTrack::Video(track.clone()),
-                        TrackPublishOptions {
-                            source: TrackSource::Screenshare,
-                            simulcast: false,
-                            video_codec: VideoCodec::VP9,
-                            ..Default::default()
-                        },
-                    )
-                    .await?;
-
-                self.published_tracks.insert(
-                    track_sid.clone(),
-                    TrackHandle {
-                        track: LocalTrack::Video(track),
-                        task,
-                    },
-                );
+        self.room
+            .local_participant()
+            .publish_track(track.clone(), options)
+            .await?;
 
-                Ok(track_sid)
-            }
+        Ok((track, TrackRtcSource::Video(rtc_source)))
+    }
+
+    async fn publish_audio_track(
+        &self,
+        track_name: &str,
+        framerate: i32,
+        num_channels: i32,
+        options: TrackPublishOptions,
+    ) -> Result<(LocalTrack, TrackRtcSource), LKParticipantError> {
+        let rtc_source = NativeAudioSource::new(
+            Default::default(),
+            framerate as u32,
+            num_channels as u32,
+            2000,
+        );
+
+        let track = LocalTrack::Audio(LocalAudioTrack::create_audio_track(
+            track_name,
+            RtcAudioSource::Native(rtc_source.clone()),
+        ));
+
+        self.room
+            .local_participant()
+            .publish_track(track.clone(), options)
+            .await?;
+
+        Ok((track, TrackRtcSource::Audio(rtc_source)))
+    }
+
+    /// The published tracks, with their LiveKit SIDs
+    pub fn tracks(&self) -> Vec<LKPublishedTrack> {
+        self.published_tracks
+            .iter()
+            .map(|(track_sid, handle)| LKPublishedTrack {
+                sid: track_sid.clone(),
+                name: handle.track.name(),
+                options: handle.details.clone(),
+                settings: handle.settings.clone(),
+                muted: handle.paused.load(Ordering::Relaxed),
+                owns_stream: handle.stream.is_some(),
+            })
+            .collect()
+    }
+
+    /// The stream of a track published with `publish_owned_stream`
+    pub fn stream(&self, track_sid: &str) -> Option<&GstMediaStream> {
+        self.published_tracks.get(track_sid)?.stream.as_ref()
+    }
+
+    pub fn stream_mut(&mut self, track_sid: &str) -> Option<&mut GstMediaStream> {
+        self.published_tracks.get_mut(track_sid)?.stream.as_mut()
+    }
+
+    /// Takes the owned stream of a track out while it is operated on, together
+    /// with the participant. It has to be put back with `restore_owned_stream`.
+    pub(crate) fn take_owned_stream(&mut self, track_sid: &str) -> Option<GstMediaStream> {
+        self.published_tracks.get_mut(track_sid)?.stream.take()
+    }
+
+    pub(crate) fn restore_owned_stream(&mut self, track_sid: &str, stream: GstMediaStream) {
+        if let Some(handle) = self.published_tracks.get_mut(track_sid) {
+            handle.stream = Some(stream);
         }
     }
 
+    /// Unpublishes the track and stops forwarding to it, stopping its stream if the
+    /// participant owns it. The track is forgotten even if LiveKit fails to unpublish it.
     pub async fn unpublish_track(&mut self, track_sid: &str) -> Result<(), LKParticipantError> {
-        if let Some(handle) = self.published_tracks.get(track_sid) {
-            self.room
+        let handle = self.published_tracks.remove(track_sid).ok_or_else(|| {
+            LKParticipantError::StreamingError(format!("No published track {}", track_sid))
+        })?;
+        handle.task.abort();
+        if let Some(sync_task) = handle.sync_task {
+            sync_task.abort();
+        }
+        if handle.settings.publish_metadata {
+            // An empty value removes the attribute
+            let attributes = HashMap::from([(
+                format!("{}{}", TRACK_METADATA_ATTRIBUTE_PREFIX, track_sid),
+                String::new(),
+            )]);
+            if let Err(e) = self
+                .room
                 .local_participant()
-                .unpublish_track(&handle.track.sid())
-                .await?;
-            handle.task.abort();
+                .set_attributes(attributes)
+                .await
+            {
+                eprintln!("Failed to remove metadata of track {}: {}", track_sid, e);
+            }
+        }
+        let unpublished = self
+            .room
+            .local_participant()
+            .unpublish_track(&handle.track.sid())
+            .await;
+        if let Some(mut stream) = handle.stream {
+            stream.stop().await?;
+        }
+        unpublished?;
+        Ok(())
+    }
+
+    /// Unpublishes every track, carrying on past failures and returning the first one
+    pub async fn unpublish_all(&mut self) -> Result<(), LKParticipantError> {
+        let track_sids = self.published_tracks.keys().cloned().collect::<Vec<_>>();
+        let mut result = Ok(());
+        for track_sid in track_sids {
+            if let Err(e) = self.unpublish_track(&track_sid).await {
+                eprintln!("Failed to unpublish track {}: {}", track_sid, e);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Unpublishes every track and stops the owned streams. The room is left
+    /// connected, it is shared with whoever created the participant.
+    pub async fn close(&mut self) -> Result<(), LKParticipantError> {
+        self.unpublish_all().await
+    }
+
+    /// Stops forwarding frames to every published track and forgets them, for
+    /// when the room connection is gone. The streams keep running, the owned ones
+    /// are handed back.
+    pub fn stop_forwarding(&mut self) -> Vec<GstMediaStream> {
+        self.published_tracks
+            .drain()
+            .filter_map(|(_, handle)| {
+                handle.task.abort();
+                if let Some(sync_task) = handle.sync_task {
+                    sync_task.abort();
+                }
+                handle.stream
+            })
+            .collect()
+    }
+
+    /// Mutes the track for remote participants and stops forwarding its frames.
+    /// The stream, and with it the local recording, keeps running.
+    pub fn mute_track(&self, track_sid: &str) -> Result<(), LKParticipantError> {
+        let handle = self.track_handle(track_sid)?;
+        handle.paused.store(true, Ordering::Relaxed);
+        handle.track.mute();
+        Ok(())
+    }
+
+    pub fn unmute_track(&self, track_sid: &str) -> Result<(), LKParticipantError> {
+        let handle = self.track_handle(track_sid)?;
+        handle.paused.store(false, Ordering::Relaxed);
+        handle.track.unmute();
+        Ok(())
+    }
+
+    /// Forwards the frames of `stream` on an already published track instead of
+    /// the frames of the stream it was published with, without remote participants
+    /// seeing the track go away. The previous stream is left running, stop it to
+    /// finish its recording. An owned previous stream stays owned by the track.
+    /// Audio streams have to match the sample rate and number of channels of the track.
+    pub async fn replace_stream(
+        &mut self,
+        track_sid: &str,
+        stream: &mut GstMediaStream,
+    ) -> Result<(), LKParticipantError> {
+        if !stream.state().is_active() {
+            stream.start().await?;
         }
+        let room = self.room.clone();
+        let events_tx = self.events_tx.clone();
+
+        let handle = self.published_tracks.get_mut(track_sid).ok_or_else(|| {
+            LKParticipantError::StreamingError(format!("No published track {}", track_sid))
+        })?;
+
+        let task = Self::spawn_forwarding(
+            events_tx,
+            &handle.track,
+            &handle.rtc_source,
+            stream,
+            handle.paused.clone(),
+            handle.counters.clone(),
+        )?;
+
+        handle.task.abort();
+        handle.task = task;
+        if let Some(sync_task) = handle.sync_task.take() {
+            sync_task.abort();
+        }
+        handle.sync_task = Self::spawn_sync_task(
+            room,
+            stream,
+            &handle.track,
+            handle.settings.sync_interval_ms,
+        );
+        // This unwrap is safe because we know the stream has started
+        handle.details = stream.details().unwrap();
+        self.publish_track_metadata(track_sid, stream).await;
+
         Ok(())
     }
 
+    /// Forwarding and WebRTC sender statistics of a published track
+    pub async fn track_stats(&self, track_sid: &str) -> Result<LKTrackStats, LKParticipantError> {
+        let handle = self.track_handle(track_sid)?;
+        let rtc_stats = handle.track.get_stats().await?;
+        Ok(LKTrackStats {
+            track_sid: track_sid.to_string(),
+            track_name: handle.track.name(),
+            muted: handle.paused.load(Ordering::Relaxed),
+            forwarding: handle.counters.snapshot(),
+            senders: LKSenderStats::from_rtc_stats(&rtc_stats),
+        })
+    }
+
+    /// Statistics of every published track
+    pub async fn stats(&self) -> Result<Vec<LKTrackStats>, LKParticipantError> {
+        let mut stats = vec![];
+        for track_sid in self.published_tracks.keys() {
+            stats.push(self.track_stats(track_sid).await?);
+        }
+        Ok(stats)
+    }
+
+    fn track_handle(&self, track_sid: &str) -> Result<&TrackHandle, LKParticipantError> {
+        self.published_tracks.get(track_sid).ok_or_else(|| {
+            LKParticipantError::StreamingError(format!("No published track {}", track_sid))
+        })
+    }
+
     async fn video_track_task(
-        mut close_rx: broadcast::Receiver<()>,
-        mut frames_rx: broadcast::Receiver<Arc<Buffer>>,
+        mut frames_rx: VideoFrameReceiver,
         rtc_source: NativeVideoSource,
-    ) {
+        paused: Arc<AtomicBool>,
+        counters: Arc<TrackCounters>,
+        clock: Option<StreamClock>,
+    ) -> StreamState {
         loop {
-            tokio::select! {
-                _ = close_rx.recv() => {
-                    break;
+            let frame = match frames_rx.recv().await {
+                Ok(frame) => frame,
+                Err(FrameRecvError::Lagged(count)) => {
+                    counters.frames_lagged(count);
+                    continue;
+                }
+                Err(FrameRecvError::InvalidFrame(e)) => {
+                    counters.frame_failed();
+                    eprintln!("Dropping video frame: {}", e);
+                    continue;
                 }
-                frame = frames_rx.recv() => {
-                    if let Ok(frame) = frame {
-                        let map = frame.map_readable().unwrap();
-                        let data = map.as_slice();
-                        let timestamp_us = frame.pts().unwrap_or_default().useconds() as i64;
-                        let res = rtc_source.video_resolution();
-                        let width = res.width;
-                        let height = res.height;
-                        let mut wrtc_video_buffer = I420Buffer::new(width, height);
-                        let (data_y, data_u, data_v) = wrtc_video_buffer.data_mut();
-
-                        let y_plane_size = (width * height) as usize;
-                        let uv_plane_size = (width * height / 4) as usize;
-
-                        data_y.copy_from_slice(&data[0..y_plane_size]);
-                        data_u.copy_from_slice(&data[y_plane_size..y_plane_size + uv_plane_size]);
-                        data_v.copy_from_slice(
-                            &data[y_plane_size + uv_plane_size..y_plane_size + 2 * uv_plane_size],
-                        );
-
-                        let video_frame = VideoFrame {
-                            buffer: wrtc_video_buffer,
-                            rotation: VideoRotation::VideoRotation0,
-                            timestamp_us,
-                        };
-                        rtc_source.capture_frame(&video_frame);
-                    }
+                Err(FrameRecvError::Ended(state)) => break state,
+            };
+            if paused.load(Ordering::Relaxed) {
+                continue;
+            }
+            counters.frame_received();
+            let timestamp_us = frame.pts.unwrap_or_default().useconds() as i64;
+            // The frames are copied with the layout they were negotiated with
+            match Self::to_webrtc_buffer(&frame.buffer, &frame.info) {
+                Ok(buffer) => {
+                    let video_frame = VideoFrame {
+                        buffer,
+                        rotation: VideoRotation::VideoRotation0,
+                        timestamp_us,
+                    };
+                    rtc_source.capture_frame(&video_frame);
+                    counters.frame_delivered(capture_latency(&clock, &frame.buffer));
+                }
+                Err(e) => {
+                    counters.frame_failed();
+                    eprintln!("Dropping video frame: {}", e);
                 }
             }
         }
     }
 
+    /// Copies a GStreamer frame into a WebRTC buffer that uses the same strides, so
+    /// every plane is a single copy regardless of the row padding GStreamer chose.
+    /// libwebrtc only captures buffers it allocated itself and keeps them for the
+    /// encoder without telling when they are released, so a new one is allocated
+    /// for every frame rather than reused.
+    fn to_webrtc_buffer(
+        buffer: &gstreamer::BufferRef,
+        video_info: &VideoInfo,
+    ) -> Result<BoxVideoBuffer, GStreamerError> {
+        let frame = VideoFrameRef::from_buffer_ref_readable(buffer, video_info)
+            .map_err(|e| GStreamerError::PipelineError(e.to_string()))?;
+        let strides = frame.plane_stride();
+
+        match frame.format() {
+            VideoFormat::I420 => {
+                let mut webrtc_buffer = I420Buffer::with_strides(
+                    frame.width(),
+                    frame.height(),
+                    strides[0] as u32,
+                    strides[1] as u32,
+                    strides[2] as u32,
+                );
+                let (data_y, data_u, data_v) = webrtc_buffer.data_mut();
+                copy_plane(plane_data(&frame, 0)?, data_y);
+                copy_plane(plane_data(&frame, 1)?, data_u);
+                copy_plane(plane_data(&frame, 2)?, data_v);
+                Ok(Box::new(webrtc_buffer))
+            }
+            VideoFormat::Nv12 => {
+                let mut webrtc_buffer = NV12Buffer::with_strides(
+                    frame.width(),
+                    frame.height(),
+                    strides[0] as u32,
+                    strides[1] as u32,
+                );
+                let (data_y, data_uv) = webrtc_buffer.data_mut();
+                copy_plane(plane_data(&frame, 0)?, data_y);
+                copy_plane(plane_data(&frame, 1)?, data_uv);
+                Ok(Box::new(webrtc_buffer))
+            }
+            format => Err(GStreamerError::PipelineError(format!(
+                "Unsupported frame format {:?}",
+                format
+            ))),
+        }
+    }
+
     async fn audio_track_task(
-        mut close_rx: broadcast::Receiver<()>,
-        mut frames_rx: broadcast::Receiver<Arc<Buffer>>,
+        mut frames_rx: AudioFrameReceiver,
         rtc_source: NativeAudioSource,
-    ) {
+        paused: Arc<AtomicBool>,
+        counters: Arc<TrackCounters>,
+        clock: Option<StreamClock>,
+    ) -> StreamState {
+        let mut sample_format = AudioSampleFormat::default();
+        let mut adapter = AudioFrameAdapter::new(
+            sample_format,
+            rtc_source.sample_rate(),
+            rtc_source.num_channels(),
+        );
         loop {
-            tokio::select! {
-                    _ = close_rx.recv() => {
-                        break;
-                    }
-                    frame = frames_rx.recv() => {
-                        if let Ok(frame) = frame {
-                            let map = frame.map_readable().unwrap();
-                            let audio_data: &[i16] = unsafe {
-                                std::slice::from_raw_parts(map.as_ptr() as *const i16, map.size() / 2)
-                            };
-                            let samples_per_channel = audio_data.len() as u32 / rtc_source.num_channels();
-                            let audio_frame = AudioFrame {
-                                data: Cow::Borrowed(audio_data),
-                                sample_rate: rtc_source.sample_rate(),
-                                num_channels: rtc_source.num_channels(),
-                                samples_per_channel,
-                            };
-                            rtc_source.capture_frame(&audio_frame).await.unwrap();
-                    }
+            let frame = match frames_rx.recv().await {
+                Ok(frame) => frame,
+                Err(FrameRecvError::Lagged(count)) => {
+                    counters.frames_lagged(count);
+                    continue;
+                }
+                Err(FrameRecvError::InvalidFrame(e)) => {
+                    counters.frame_failed();
+                    eprintln!("Dropping audio buffer: {}", e);
+                    continue;
                 }
+                Err(FrameRecvError::Ended(state)) => break state,
+            };
+            if paused.load(Ordering::Relaxed) {
+                // Resume with fresh samples rather than the ones from before muting
+                adapter.clear();
+                continue;
+            }
+            counters.frame_received();
+            let format = match frame.sample_format {
+                Some(format) if frame.interleaved => format,
+                _ => {
+                    counters.frame_failed();
+                    eprintln!("Dropping audio buffer in {} format", frame.format_name);
+                    continue;
+                }
+            };
+            if format != sample_format {
+                sample_format = format;
+                adapter = AudioFrameAdapter::new(
+                    sample_format,
+                    rtc_source.sample_rate(),
+                    rtc_source.num_channels(),
+                );
+            }
+            match frame.buffer.map_readable() {
+                Ok(map) => adapter.push(map.as_slice()),
+                Err(e) => {
+                    counters.frame_failed();
+                    eprintln!("Dropping audio buffer: {}", e);
+                    continue;
+                }
+            }
+
+            // Samples short of a full 10 ms frame wait for the next buffer
+            let mut failed = false;
+            while let Some(samples) = adapter.pop_frame() {
+                let audio_frame = AudioFrame {
+                    samples_per_channel: samples.len() as u32 / rtc_source.num_channels(),
+                    data: Cow::Owned(samples),
+                    sample_rate: rtc_source.sample_rate(),
+                    num_channels: rtc_source.num_channels(),
+                };
+                if let Err(e) = rtc_source.capture_frame(&audio_frame).await {
+                    failed = true;
+                    eprintln!("Dropping audio frame: {}", e);
+                }
+            }
+            if failed {
+                counters.frame_failed();
+            } else {
+                counters.frame_delivered(capture_latency(&clock, &frame.buffer));
             }
         }
     }
 }
+
+fn plane_data<'a>(
+    frame: &'a VideoFrameRef<&gstreamer::BufferRef>,
+    plane: u32,
+) -> Result<&'a [u8], GStreamerError> {
+    frame
+        .plane_data(plane)
+        .map_err(|e| GStreamerError::PipelineError(e.to_string()))
+}
+
+fn capture_latency(clock: &Option<StreamClock>, frame: &Buffer) -> Option<Duration> {
+    clock.as_ref()?.capture_latency(frame.pts()?)
+}
+
+/// GStreamer may leave the padding after the last row out of a plane
+fn copy_plane(src: &[u8], dst: &mut [u8]) {
+    let len = src.len().min(dst.len());
+    dst[..len].copy_from_slice(&src[..len]);
+}
@@ -0,0 +1,412 @@
+use crate::lk_e2ee::LKE2eeOptions;
+use crate::lk_participant::{LKParticipant, LKParticipantError, LKPublishSettings};
+use crate::media_stream::GstMediaStream;
+use crate::remote_control::{
+    register_remote_control, unregister_remote_control, LKRemoteCommand, LKRemoteControlOptions,
+    LKRemoteRequest,
+};
+use crate::track_stats::LKTrackStats;
+use livekit::{DisconnectReason, Room, RoomEvent};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+/// Server url and token to join a room with
+#[derive(Debug, Clone)]
+pub struct LKConnectionInfo {
+    pub url: String,
+    pub token: String,
+    /// End-to-end encrypts every track published after joining
+    pub e2ee: Option<LKE2eeOptions>,
+}
+
+pub type LKConnectionInfoFuture =
+    Pin<Box<dyn Future<Output = Result<LKConnectionInfo, LKParticipantError>> + Send>>;
+
+/// Provides the connection info every time `LKResilientParticipant` (re)joins the
+/// room, so that an expired token can be replaced by a fresh one
+pub trait LKTokenProvider: Send + Sync {
+    fn connection_info(&self) -> LKConnectionInfoFuture;
+}
+
+impl<F, Fut> LKTokenProvider for F
+where
+    F: Fn() -> Fut + Send + Sync,
+    Fut: Future<Output = Result<LKConnectionInfo, LKParticipantError>> + Send + 'static,
+{
+    fn connection_info(&self) -> LKConnectionInfoFuture {
+        Box::pin(self())
+    }
+}
+
+/// Backoff between reconnection attempts, doubling from `initial_backoff_ms` up
+/// to `max_backoff_ms`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LKReconnectOptions {
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Give up after this many failed attempts in a row, `None` retries forever
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for LKReconnectOptions {
+    fn default() -> Self {
+        Self {
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 30000,
+            max_attempts: None,
+        }
+    }
+}
+
+impl LKReconnectOptions {
+    /// How long to wait before the given attempt, starting at 1
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u64 << attempt.saturating_sub(1).min(32);
+        Duration::from_millis(
+            self.initial_backoff_ms
+                .saturating_mul(factor)
+                .min(self.max_backoff_ms),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum LKResilientEvent {
+    Room(RoomEvent),
+    /// The connection dropped, or the previous attempt failed, and the given
+    /// attempt is about to be made. The streams keep recording meanwhile.
+    Reconnecting {
+        attempt: u32,
+    },
+    /// Rejoined the room and republished the streams that are running, the others
+    /// are left unpublished, see `LKResilientParticipant::republish`
+    Reconnected,
+    /// Gave up after `max_attempts`, the streams are still running
+    ReconnectFailed(String),
+    /// Ran a remote control command, see `enable_remote_control`
+    RemoteCommand {
+        caller_identity: String,
+        command: LKRemoteCommand,
+        /// Why the command failed, also sent back to the caller
+        error: Option<String>,
+    },
+}
+
+struct PublishedStream {
+    stream: GstMediaStream,
+    track_name: String,
+    settings: LKPublishSettings,
+    /// SID of the track in the current room, `None` while it is not published
+    track_sid: Option<String>,
+}
+
+struct RemoteControl {
+    options: LKRemoteControlOptions,
+    requests_tx: mpsc::Sender<LKRemoteRequest>,
+    requests_rx: mpsc::Receiver<LKRemoteRequest>,
+}
+
+/// A participant that rejoins the room when the connection drops and republishes
+/// its streams under the same track names. The streams, and their local
+/// recordings, run independently of the connection.
+pub struct LKResilientParticipant {
+    token_provider: Arc<dyn LKTokenProvider>,
+    options: LKReconnectOptions,
+    room: Arc<Room>,
+    room_rx: UnboundedReceiver<RoomEvent>,
+    participant: LKParticipant,
+    streams: Vec<PublishedStream>,
+    remote_control: Option<RemoteControl>,
+    reconnect_attempt: Option<u32>,
+    closed: bool,
+}
+
+/// Disconnects after which the room or the participant is gone for good
+fn should_reconnect(reason: DisconnectReason) -> bool {
+    !matches!(
+        reason,
+        DisconnectReason::ClientInitiated
+            | DisconnectReason::DuplicateIdentity
+            | DisconnectReason::ParticipantRemoved
+            | DisconnectReason::RoomDeleted
+            | DisconnectReason::RoomClosed
+    )
+}
+
+impl LKResilientParticipant {
+    pub async fn connect(
+        token_provider: impl LKTokenProvider + 'static,
+        options: LKReconnectOptions,
+    ) -> Result<Self, LKParticipantError> {
+        let token_provider: Arc<dyn LKTokenProvider> = Arc::new(token_provider);
+        let (participant, room_rx) = Self::join(token_provider.as_ref()).await?;
+
+        Ok(Self {
+            token_provider,
+            options,
+            room: participant.room(),
+            participant,
+            room_rx,
+            streams: vec![],
+            remote_control: None,
+            reconnect_attempt: None,
+            closed: false,
+        })
+    }
+
+    async fn join(
+        token_provider: &dyn LKTokenProvider,
+    ) -> Result<(LKParticipant, UnboundedReceiver<RoomEvent>), LKParticipantError> {
+        let info = token_provider.connection_info().await?;
+        LKParticipant::connect(&info.url, &info.token, info.e2ee.as_ref()).await
+    }
+
+    pub fn room(&self) -> Arc<Room> {
+        self.room.clone()
+    }
+
+    pub fn streams(&self) -> impl Iterator<Item = &GstMediaStream> {
+        self.streams.iter().map(|s| &s.stream)
+    }
+
+    pub fn streams_mut(&mut self) -> impl Iterator<Item = &mut GstMediaStream> {
+        self.streams.iter_mut().map(|s| &mut s.stream)
+    }
+
+    /// Statistics of the tracks published since the last (re)connection
+    pub async fn stats(&self) -> Result<Vec<LKTrackStats>, LKParticipantError> {
+        self.participant.stats().await
+    }
+
+    /// Publishes the stream and keeps it for republishing after a reconnection.
+    /// The stream is kept even if publishing fails, to be retried with `republish`
+    /// and stopped by `close`.
+    pub async fn publish_stream(
+        &mut self,
+        stream: GstMediaStream,
+        track_name: String,
+        settings: LKPublishSettings,
+    ) -> Result<String, LKParticipantError> {
+        self.streams.push(PublishedStream {
+            stream,
+            track_name,
+            settings,
+            track_sid: None,
+        });
+        // This unwrap is safe because we just pushed the stream
+        let published = self.streams.last_mut().unwrap();
+        let result = self
+            .participant
+            .publish_stream_with_settings(
+                &mut published.stream,
+                Some(published.track_name.clone()),
+                published.settings.clone(),
+            )
+            .await;
+        published.track_sid = result.as_ref().ok().cloned();
+        result
+    }
+
+    /// Publishes a kept stream that is not published in the current room, such as
+    /// one that failed or was stopped, restarting it if it is not running
+    pub async fn republish(&mut self, track_name: &str) -> Result<String, LKParticipantError> {
+        let published = self
+            .streams
+            .iter_mut()
+            .find(|published| published.track_name == track_name)
+            .ok_or_else(|| {
+                LKParticipantError::StreamingError(format!("No stream named {}", track_name))
+            })?;
+        if let Some(track_sid) = &published.track_sid {
+            return Ok(track_sid.clone());
+        }
+        let track_sid = self
+            .participant
+            .publish_stream_with_settings(
+                &mut published.stream,
+                Some(published.track_name.clone()),
+                published.settings.clone(),
+            )
+            .await?;
+        published.track_sid = Some(track_sid.clone());
+        Ok(track_sid)
+    }
+
+    /// Lets the `allowed_identities` control the published streams over RPC, see
+    /// `LKRemoteCommand`. The commands are run by `recv`, and the methods are
+    /// registered again on every rejoined room.
+    pub fn enable_remote_control(&mut self, options: LKRemoteControlOptions) {
+        let (requests_tx, requests_rx) = mpsc::channel(8);
+        register_remote_control(&self.room, options.clone(), requests_tx.clone());
+        self.remote_control = Some(RemoteControl {
+            options,
+            requests_tx,
+            requests_rx,
+        });
+    }
+
+    pub fn disable_remote_control(&mut self) {
+        if self.remote_control.take().is_some() {
+            unregister_remote_control(&self.room);
+        }
+    }
+
+    /// Runs a remote control request on the stream of the track it targets and
+    /// responds to the caller
+    async fn handle_remote_request(&mut self, request: LKRemoteRequest) -> LKResilientEvent {
+        let stream = request.command.track_sid().and_then(|track_sid| {
+            self.streams
+                .iter_mut()
+                .find(|published| published.track_sid.as_deref() == Some(track_sid))
+                .map(|published| &mut published.stream)
+        });
+        let result = self
+            .participant
+            .run_remote_command(&request.command, stream)
+            .await;
+
+        let event = LKResilientEvent::RemoteCommand {
+            caller_identity: request.caller_identity.clone(),
+            command: request.command.clone(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        request.respond(result);
+        event
+    }
+
+    /// Waits for the next room event, reconnecting in between when the connection
+    /// dropped, and runs the remote control commands meanwhile. Returns `None` once
+    /// the participant is closed or has given up.
+    pub async fn recv(&mut self) -> Option<LKResilientEvent> {
+        if self.closed {
+            return None;
+        }
+
+        if let Some(attempt) = self.reconnect_attempt {
+            tokio::time::sleep(self.options.backoff(attempt)).await;
+            return Some(match self.rejoin().await {
+                Ok(()) => {
+                    self.reconnect_attempt = None;
+                    LKResilientEvent::Reconnected
+                }
+                Err(e) => {
+                    eprintln!("Reconnection attempt {} failed: {}", attempt, e);
+                    if self.options.max_attempts.is_some_and(|max| attempt >= max) {
+                        self.closed = true;
+                        LKResilientEvent::ReconnectFailed(e.to_string())
+                    } else {
+                        self.reconnect_attempt = Some(attempt + 1);
+                        LKResilientEvent::Reconnecting {
+                            attempt: attempt + 1,
+                        }
+                    }
+                }
+            });
+        }
+
+        let event = match self.remote_control.as_mut() {
+            Some(remote_control) => tokio::select! {
+                event = self.room_rx.recv() => event,
+                Some(request) = remote_control.requests_rx.recv() => {
+                    return Some(self.handle_remote_request(request).await);
+                }
+            },
+            None => self.room_rx.recv().await,
+        };
+
+        match event {
+            Some(RoomEvent::Disconnected { reason }) if !should_reconnect(reason) => {
+                self.closed = true;
+                Some(LKResilientEvent::Room(RoomEvent::Disconnected { reason }))
+            }
+            Some(RoomEvent::Disconnected { .. }) | None => {
+                self.participant.stop_forwarding();
+                for published in self.streams.iter_mut() {
+                    published.track_sid = None;
+                }
+                self.reconnect_attempt = Some(1);
+                Some(LKResilientEvent::Reconnecting { attempt: 1 })
+            }
+            Some(event) => Some(LKResilientEvent::Room(event)),
+        }
+    }
+
+    async fn rejoin(&mut self) -> Result<(), LKParticipantError> {
+        let (mut participant, room_rx) = Self::join(self.token_provider.as_ref()).await?;
+        let room = participant.room();
+
+        for published in self.streams.iter_mut() {
+            // A stream that failed or was stopped over remote control is not
+            // restarted, it stays unpublished until `republish`
+            if !published.stream.state().is_active() {
+                continue;
+            }
+            let result = participant
+                .publish_stream_with_settings(
+                    &mut published.stream,
+                    Some(published.track_name.clone()),
+                    published.settings.clone(),
+                )
+                .await;
+            match result {
+                Ok(track_sid) => published.track_sid = Some(track_sid),
+                Err(e) => eprintln!("Failed to republish {}: {}", published.track_name, e),
+            }
+        }
+
+        // The RPC methods were registered on the previous room
+        if let Some(remote_control) = &self.remote_control {
+            register_remote_control(
+                &room,
+                remote_control.options.clone(),
+                remote_control.requests_tx.clone(),
+            );
+        }
+        self.room = room;
+        self.room_rx = room_rx;
+        self.participant = participant;
+        Ok(())
+    }
+
+    /// Stops every stream, finishing their recordings, and leaves the room. The
+    /// room is left even if a stream fails to stop, the first error is returned.
+    pub async fn close(&mut self) -> Result<(), LKParticipantError> {
+        self.closed = true;
+        self.participant.stop_forwarding();
+        let mut result = Ok(());
+        for published in self.streams.iter_mut() {
+            if let Err(e) = published.stream.stop().await {
+                eprintln!("Failed to stop stream: {}", e);
+                if result.is_ok() {
+                    result = Err(e.into());
+                }
+            }
+        }
+        if let Err(e) = self.room.close().await {
+            if result.is_ok() {
+                result = Err(e.into());
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let options = LKReconnectOptions::default();
+        assert_eq!(options.backoff(1), Duration::from_millis(1000));
+        assert_eq!(options.backoff(2), Duration::from_millis(2000));
+        assert_eq!(options.backoff(5), Duration::from_millis(16000));
+        assert_eq!(options.backoff(6), Duration::from_millis(30000));
+        assert_eq!(options.backoff(100), Duration::from_millis(30000));
+    }
+}
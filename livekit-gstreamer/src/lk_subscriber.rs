@@ -0,0 +1,504 @@
+use crate::media_device::{run_pipeline, AudioLevels, GStreamerError, GstMediaDevice};
+use crate::media_stream::{create_dir, strict_sanitize_filename, LocalFileSaveOptions};
+use crate::stream_state::{StreamEvent, StreamState};
+use crate::utils::{make_element, random_string};
+use crate::RecordingMetadata;
+use futures::StreamExt;
+use gstreamer::{prelude::*, Buffer};
+use gstreamer_app::AppSrc;
+use gstreamer_video::{prelude::*, VideoFormat, VideoFrameRef, VideoInfo};
+use livekit::track::{RemoteAudioTrack, RemoteTrack, RemoteVideoTrack};
+use livekit::webrtc::audio_stream::native::NativeAudioStream;
+use livekit::webrtc::prelude::{BoxVideoFrame, I420Buffer, VideoBuffer};
+use livekit::webrtc::video_stream::native::NativeVideoStream;
+use livekit::RoomEvent;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path;
+use std::sync::Arc;
+use tokio::sync::{broadcast, watch};
+
+pub const REMOTE_TRACK_DEVICE_CLASS: &str = "LiveKit/RemoteTrack";
+
+/// Options for recording the remote tracks of a room
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LKSubscriberOptions {
+    pub local_file_save_options: LocalFileSaveOptions,
+    /// Sample rate remote audio is resampled to by WebRTC before recording
+    pub audio_framerate: i32,
+    pub audio_channels: i32,
+}
+
+impl Default for LKSubscriberOptions {
+    fn default() -> Self {
+        Self {
+            local_file_save_options: LocalFileSaveOptions::default(),
+            audio_framerate: 48000,
+            audio_channels: 1,
+        }
+    }
+}
+
+/// Records the remote audio and video tracks of a room to local files, the
+/// subscriber side counterpart to `LKParticipant`
+pub struct LKSubscriber {
+    options: LKSubscriberOptions,
+    recordings: HashMap<String, TrackRecording>,
+    /// Recordings of unsubscribed tracks whose files are still being finalized
+    finalizing: Vec<(String, tokio::task::JoinHandle<Result<(), GStreamerError>>)>,
+}
+
+struct TrackRecording {
+    close_tx: broadcast::Sender<()>,
+    task: tokio::task::JoinHandle<Result<(), GStreamerError>>,
+}
+
+impl LKSubscriber {
+    pub fn new(options: LKSubscriberOptions) -> Self {
+        Self {
+            options,
+            recordings: HashMap::new(),
+            finalizing: vec![],
+        }
+    }
+
+    /// Track sids of the tracks currently being recorded
+    pub fn recorded_tracks(&self) -> Vec<String> {
+        self.recordings.keys().cloned().collect()
+    }
+
+    /// Starts and stops recordings as tracks are published and subscribed in the
+    /// room. Feed it every event received from `Room::connect`.
+    pub async fn handle_event(&mut self, event: &RoomEvent) -> Result<(), GStreamerError> {
+        match event {
+            RoomEvent::TrackPublished { publication, .. } => {
+                // Needed when the room was joined without auto subscribe
+                publication.set_subscribed(true);
+            }
+            RoomEvent::TrackSubscribed {
+                track, participant, ..
+            } => {
+                let device = GstMediaDevice::remote_track(
+                    &String::from(participant.identity()),
+                    &track.name(),
+                    &String::from(track.sid()),
+                );
+                self.record_track(track.clone(), device).await?;
+            }
+            RoomEvent::TrackUnsubscribed { track, .. } => {
+                // Finalizing the file takes a while, other events are not held up
+                self.close_track(&String::from(track.sid()));
+            }
+            RoomEvent::Disconnected { .. } => {
+                self.stop().await?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub async fn record_track(
+        &mut self,
+        track: RemoteTrack,
+        device: GstMediaDevice,
+    ) -> Result<(), GStreamerError> {
+        let track_sid = String::from(track.sid());
+        if self.recordings.contains_key(&track_sid) {
+            return Ok(());
+        }
+
+        let op_dir = create_dir(&self.options.local_file_save_options).await?;
+        let parent_dir = path::absolute(&op_dir)
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        let (media_type, codec, extension) = match track {
+            RemoteTrack::Audio(_) => ("audio", "audio/x-raw", "m4a"),
+            RemoteTrack::Video(_) => ("video", "video/x-raw", "mp4"),
+        };
+        let filename_str = format!(
+            "{}-{}-{}.{}",
+            media_type,
+            strict_sanitize_filename(&device.display_name),
+            chrono::Local::now().format("%Y-%m-%d-%H-%M-%S"),
+            extension
+        );
+        let mut metadata = RecordingMetadata::new(
+            filename_str.clone(),
+            parent_dir,
+            "livekit".into(),
+            media_type.into(),
+            codec.into(),
+            None,
+            Some(device.display_name.clone()),
+        );
+        metadata.set_duration_tolerance_ns(
+            self.options
+                .local_file_save_options
+                .recording_duration_tolerance_ns,
+        );
+        let filename = op_dir.join(filename_str).to_string_lossy().to_string();
+
+        let (close_tx, close_rx) = broadcast::channel::<()>(1);
+        let task = match track {
+            RemoteTrack::Audio(track) => tokio::spawn(record_audio_track(
+                device,
+                track,
+                self.options.audio_framerate,
+                self.options.audio_channels,
+                filename,
+                metadata,
+                close_rx,
+            )),
+            RemoteTrack::Video(track) => tokio::spawn(record_video_track(
+                device, track, filename, metadata, close_rx,
+            )),
+        };
+
+        self.recordings
+            .insert(track_sid, TrackRecording { close_tx, task });
+
+        Ok(())
+    }
+
+    /// Ends the recording of a track, waiting for its file to be finalized
+    pub async fn stop_track(&mut self, track_sid: &str) -> Result<(), GStreamerError> {
+        if let Some(recording) = self.recordings.remove(track_sid) {
+            let _ = recording.close_tx.send(());
+            recording
+                .task
+                .await
+                .map_err(|e| GStreamerError::PipelineError(e.to_string()))??;
+        }
+        Ok(())
+    }
+
+    /// Ends the recording of a track without waiting for its file to be finalized,
+    /// `stop` waits for it and returns its error
+    pub fn close_track(&mut self, track_sid: &str) {
+        if let Some(recording) = self.recordings.remove(track_sid) {
+            let _ = recording.close_tx.send(());
+            self.finalizing
+                .push((track_sid.to_string(), recording.task));
+        }
+    }
+
+    /// Ends every recording and waits for all the files to be finalized, including
+    /// the ones of tracks unsubscribed before. Returns the first error, once all
+    /// of them are done.
+    pub async fn stop(&mut self) -> Result<(), GStreamerError> {
+        for track_sid in self.recorded_tracks() {
+            self.close_track(&track_sid);
+        }
+
+        let mut result = Ok(());
+        for (track_sid, task) in std::mem::take(&mut self.finalizing) {
+            let finalized = task
+                .await
+                .map_err(|e| GStreamerError::PipelineError(e.to_string()))
+                .and_then(|result| result);
+            if let Err(e) = finalized {
+                eprintln!("Failed to finalize recording of track {}: {}", track_sid, e);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+}
+
+async fn record_video_track(
+    device: GstMediaDevice,
+    track: RemoteVideoTrack,
+    filename: String,
+    metadata: RecordingMetadata,
+    mut close_rx: broadcast::Receiver<()>,
+) -> Result<(), GStreamerError> {
+    let mut stream = NativeVideoStream::new(track.rtc_track());
+
+    // The first frame decides the resolution of the recording
+    let first_frame = tokio::select! {
+        _ = close_rx.recv() => return Ok(()),
+        frame = stream.next() => match frame {
+            Some(frame) => frame,
+            None => return Ok(()),
+        },
+    };
+
+    let width = first_frame.buffer.width();
+    let height = first_frame.buffer.height();
+    let (pipeline, appsrc) =
+        device.remote_video_pipeline(width as i32, height as i32, &filename)?;
+    let mut video_info = VideoInfo::builder(VideoFormat::I420, width, height)
+        .build()
+        .map_err(|e| GStreamerError::PipelineError(format!("Invalid video format: {}", e)))?;
+
+    let (state_tx, _) = watch::channel(StreamState::Starting);
+    let (events_tx, _) = broadcast::channel::<StreamEvent>(1);
+    let (levels_tx, _) = broadcast::channel::<AudioLevels>(1);
+    let pipeline_task = tokio::spawn(run_pipeline(
+        pipeline,
+        Arc::new(state_tx),
+        events_tx,
+        levels_tx,
+        vec![metadata],
+    ));
+
+    let mut frame = Some(first_frame);
+    while let Some(current) = frame {
+        match video_frame_buffer(&appsrc, &mut video_info, &current) {
+            Ok(buffer) => {
+                // The recording pipeline stopped, its error is returned below
+                if appsrc.push_buffer(buffer).is_err() {
+                    break;
+                }
+            }
+            Err(e) => eprintln!("Dropping remote video frame: {}", e),
+        }
+        frame = tokio::select! {
+            _ = close_rx.recv() => None,
+            next = stream.next() => next,
+        };
+    }
+
+    let _ = appsrc.end_of_stream();
+    pipeline_task
+        .await
+        .map_err(|e| GStreamerError::PipelineError(e.to_string()))?
+}
+
+async fn record_audio_track(
+    device: GstMediaDevice,
+    track: RemoteAudioTrack,
+    framerate: i32,
+    channels: i32,
+    filename: String,
+    metadata: RecordingMetadata,
+    mut close_rx: broadcast::Receiver<()>,
+) -> Result<(), GStreamerError> {
+    let mut stream = NativeAudioStream::new(track.rtc_track(), framerate, channels);
+    let (pipeline, appsrc) = device.remote_audio_pipeline(framerate, channels, &filename)?;
+
+    let (state_tx, _) = watch::channel(StreamState::Starting);
+    let (events_tx, _) = broadcast::channel::<StreamEvent>(1);
+    let (levels_tx, _) = broadcast::channel::<AudioLevels>(1);
+    let pipeline_task = tokio::spawn(run_pipeline(
+        pipeline,
+        Arc::new(state_tx),
+        events_tx,
+        levels_tx,
+        vec![metadata],
+    ));
+
+    loop {
+        tokio::select! {
+            _ = close_rx.recv() => {
+                break;
+            }
+            frame = stream.next() => {
+                match frame {
+                    Some(frame) => {
+                        let data = frame
+                            .data
+                            .iter()
+                            .flat_map(|sample| sample.to_le_bytes())
+                            .collect::<Vec<u8>>();
+                        if appsrc.push_buffer(Buffer::from_mut_slice(data)).is_err() {
+                            break;
+                        }
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+
+    let _ = appsrc.end_of_stream();
+    pipeline_task
+        .await
+        .map_err(|e| GStreamerError::PipelineError(e.to_string()))?
+}
+
+/// Copies a remote frame into a buffer for the recording pipeline, renegotiating
+/// the source caps when the sender changed its resolution
+fn video_frame_buffer(
+    appsrc: &AppSrc,
+    video_info: &mut VideoInfo,
+    frame: &BoxVideoFrame,
+) -> Result<Buffer, GStreamerError> {
+    let i420 = frame.buffer.to_i420();
+    if i420.width() != video_info.width() || i420.height() != video_info.height() {
+        *video_info = VideoInfo::builder(VideoFormat::I420, i420.width(), i420.height())
+            .build()
+            .map_err(|e| GStreamerError::PipelineError(format!("Invalid video format: {}", e)))?;
+        let caps = video_info
+            .to_caps()
+            .map_err(|e| GStreamerError::PipelineError(e.to_string()))?;
+        appsrc.set_caps(Some(&caps));
+    }
+
+    i420_to_buffer(&i420, video_info)
+}
+
+fn i420_to_buffer(i420: &I420Buffer, video_info: &VideoInfo) -> Result<Buffer, GStreamerError> {
+    let mut buffer = Buffer::with_size(video_info.size())
+        .map_err(|e| GStreamerError::PipelineError(e.to_string()))?;
+    {
+        let buffer = buffer.get_mut().unwrap();
+        let mut frame = VideoFrameRef::from_buffer_ref_writable(buffer, video_info)
+            .map_err(|e| GStreamerError::PipelineError(e.to_string()))?;
+
+        let (data_y, data_u, data_v) = i420.data();
+        let (stride_y, stride_u, stride_v) = i420.strides();
+        let width = i420.width() as usize;
+        let height = i420.height() as usize;
+        let chroma_width = width.div_ceil(2);
+        let chroma_height = height.div_ceil(2);
+
+        let planes = [
+            (data_y, stride_y, width, height),
+            (data_u, stride_u, chroma_width, chroma_height),
+            (data_v, stride_v, chroma_width, chroma_height),
+        ];
+        for (plane, (src, src_stride, row_bytes, rows)) in planes.into_iter().enumerate() {
+            let dst_stride = frame.plane_stride()[plane] as usize;
+            let dst = frame
+                .plane_data_mut(plane as u32)
+                .map_err(|e| GStreamerError::PipelineError(e.to_string()))?;
+            copy_rows(src, src_stride as usize, dst, dst_stride, row_bytes, rows);
+        }
+    }
+    Ok(buffer)
+}
+
+/// Copies `rows` rows of `row_bytes` between two planes with different strides
+fn copy_rows(
+    src: &[u8],
+    src_stride: usize,
+    dst: &mut [u8],
+    dst_stride: usize,
+    row_bytes: usize,
+    rows: usize,
+) {
+    for row in 0..rows {
+        let src_start = row * src_stride;
+        let dst_start = row * dst_stride;
+        if src_start + row_bytes > src.len() || dst_start + row_bytes > dst.len() {
+            break;
+        }
+        dst[dst_start..dst_start + row_bytes]
+            .copy_from_slice(&src[src_start..src_start + row_bytes]);
+    }
+}
+
+impl GstMediaDevice {
+    /// A virtual device standing in for a track of a remote participant
+    pub fn remote_track(participant_identity: &str, track_name: &str, track_sid: &str) -> Self {
+        GstMediaDevice {
+            display_name: format!("{}-{}", participant_identity, track_name),
+            device_class: REMOTE_TRACK_DEVICE_CLASS.to_string(),
+            device_path: track_sid.to_string(),
+        }
+    }
+
+    /// Records raw I420 frames pushed into the returned `appsrc` to `filename`,
+    /// scaled to `width`x`height` if the sender changes its resolution
+    pub fn remote_video_pipeline(
+        &self,
+        width: i32,
+        height: i32,
+        filename: &str,
+    ) -> Result<(gstreamer::Pipeline, AppSrc), GStreamerError> {
+        let pipeline = gstreamer::Pipeline::with_name(&random_string("remote-video"));
+
+        let caps = gstreamer::Caps::builder("video/x-raw")
+            .field("format", "I420")
+            .field("width", width)
+            .field("height", height)
+            .field("framerate", gstreamer::Fraction::new(0, 1))
+            .build();
+        let appsrc = self.remote_appsrc(&caps);
+
+        let convert = make_element("videoconvert")?;
+        let scale = make_element("videoscale")?;
+        let capsfilter = make_element("capsfilter")?;
+        capsfilter.set_property(
+            "caps",
+            gstreamer::Caps::builder("video/x-raw")
+                .field("width", width)
+                .field("height", height)
+                .build(),
+        );
+        let tee = make_element("tee")?;
+
+        pipeline
+            .add_many([appsrc.upcast_ref(), &convert, &scale, &capsfilter, &tee])
+            .map_err(|_| {
+                GStreamerError::PipelineError("Failed to add elements to pipeline".to_string())
+            })?;
+
+        gstreamer::Element::link_many([appsrc.upcast_ref(), &convert, &scale, &capsfilter, &tee])
+            .map_err(|_| GStreamerError::PipelineError("Failed to link elements".to_string()))?;
+
+        self.add_video_file_branch(&pipeline, &tee, filename)?;
+
+        Ok((pipeline, appsrc))
+    }
+
+    /// Records interleaved S16LE samples pushed into the returned `appsrc` to `filename`
+    pub fn remote_audio_pipeline(
+        &self,
+        framerate: i32,
+        channels: i32,
+        filename: &str,
+    ) -> Result<(gstreamer::Pipeline, AppSrc), GStreamerError> {
+        let pipeline = gstreamer::Pipeline::with_name(&random_string("remote-audio"));
+
+        let caps = gstreamer::Caps::builder("audio/x-raw")
+            .field("format", "S16LE")
+            .field("layout", "interleaved")
+            .field("rate", framerate)
+            .field("channels", channels)
+            .build();
+        let appsrc = self.remote_appsrc(&caps);
+        let tee = make_element("tee")?;
+
+        pipeline
+            .add_many([appsrc.upcast_ref(), &tee])
+            .map_err(|_| {
+                GStreamerError::PipelineError("Failed to add elements to pipeline".to_string())
+            })?;
+
+        gstreamer::Element::link_many([appsrc.upcast_ref(), &tee])
+            .map_err(|_| GStreamerError::PipelineError("Failed to link elements".to_string()))?;
+
+        self.add_audio_file_branch(&pipeline, &tee, filename)?;
+
+        Ok((pipeline, appsrc))
+    }
+
+    fn remote_appsrc(&self, caps: &gstreamer::Caps) -> AppSrc {
+        AppSrc::builder()
+            .name(random_string("remote-appsrc"))
+            .caps(caps)
+            .format(gstreamer::Format::Time)
+            .is_live(true)
+            .do_timestamp(true)
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_rows_between_strides() {
+        let src = [1, 2, 0, 0, 3, 4, 0, 0];
+        let mut dst = [0; 6];
+        copy_rows(&src, 4, &mut dst, 3, 2, 2);
+        assert_eq!(dst, [1, 2, 0, 3, 4, 0]);
+    }
+}
@@ -1,4 +1,4 @@
-use gstreamer::{prelude::*, Buffer};
+use gstreamer::prelude::*;
 use gstreamer_app::AppSink;
 use serde::de;
 use serde::{Deserialize, Serialize};
@@ -6,26 +6,83 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex;
 use thiserror::Error;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, watch};
 
+use crate::frame_subscription::StreamFrameSender;
 use crate::get_device_capabilities;
-use crate::utils::random_string;
+use crate::media_stream::AudioProcessingOptions;
+use crate::pipeline_debug::pipeline_dot;
+use crate::stream_state::{StreamEvent, StreamState};
 use crate::utils::system_time_nanos;
+use crate::utils::{make_element, random_string};
 use crate::{get_gst_device, get_monitor};
 
 #[cfg(target_os = "macos")]
-const SUPPORTED_VIDEO_CODECS: [&str; 3] = ["video/x-h264", "image/jpeg", "video/x-raw"];
+pub(crate) const SUPPORTED_VIDEO_CODECS: [&str; 3] = ["video/x-h264", "image/jpeg", "video/x-raw"];
 
 #[cfg(not(target_os = "macos"))]
-const SUPPORTED_VIDEO_CODECS: [&str; 3] = ["video/x-h264", "image/jpeg", "video/x-raw"];
+pub(crate) const SUPPORTED_VIDEO_CODECS: [&str; 3] = ["video/x-h264", "image/jpeg", "video/x-raw"];
 
-const SUPPORTED_AUDIO_CODECS: [&str; 1] = ["audio/x-raw"];
+pub(crate) const SUPPORTED_AUDIO_CODECS: [&str; 1] = ["audio/x-raw"];
 const VIDEO_FRAME_FORMAT: &str = "I420";
 
+/// Default allowed difference between the discovered duration of a recording
+/// and the wall clock time between its first and last written buffers.
+pub const DEFAULT_RECORDING_DURATION_TOLERANCE_NS: i64 = 2_000_000_000;
+const DISCOVERER_TIMEOUT_SECS: u64 = 10;
+const LEVEL_INTERVAL_NS: u64 = 100_000_000;
+const AUDIO_PROCESSING_RATE: i32 = 48000;
+const HIGH_PASS_CUTOFF_HZ: f32 = 80.0;
+
+/// Pixel layout of the raw frames handed to subscribers and LiveKit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VideoFrameFormat {
+    #[default]
+    I420,
+    NV12,
+}
+
+impl VideoFrameFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VideoFrameFormat::I420 => "I420",
+            VideoFrameFormat::NV12 => "NV12",
+        }
+    }
+
+    pub fn video_format(&self) -> gstreamer_video::VideoFormat {
+        match self {
+            VideoFrameFormat::I420 => gstreamer_video::VideoFormat::I420,
+            VideoFrameFormat::NV12 => gstreamer_video::VideoFormat::Nv12,
+        }
+    }
+}
+
+/// Size and layout of the raw frames a video pipeline hands to its subscribers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoStreamFormat {
+    pub width: i32,
+    pub height: i32,
+    pub format: VideoFrameFormat,
+}
+
+impl VideoStreamFormat {
+    pub fn video_info(&self) -> Result<gstreamer_video::VideoInfo, GStreamerError> {
+        gstreamer_video::VideoInfo::builder(
+            self.format.video_format(),
+            self.width as u32,
+            self.height as u32,
+        )
+        .build()
+        .map_err(|e| GStreamerError::PipelineError(format!("Invalid video format: {}", e)))
+    }
+}
+
+/// Wall clock times of the first and last buffers written by a filesink
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-struct FileSinkTiming {
-    start_time: Option<i64>,
-    end_time: Option<i64>,
+pub(crate) struct FileSinkTiming {
+    pub(crate) start_time: Option<i64>,
+    pub(crate) end_time: Option<i64>,
 }
 
 /// A struct representing a GStreamer device
@@ -49,6 +106,85 @@ pub struct RecordingMetadata {
     pub codec: String,
     pub audio_channel: Option<i32>,
     pub device_name: Option<String>,
+    pub validation: Option<RecordingValidation>,
+    #[serde(skip, default = "default_duration_tolerance_ns")]
+    duration_tolerance_ns: i64,
+}
+
+fn default_duration_tolerance_ns() -> i64 {
+    DEFAULT_RECORDING_DURATION_TOLERANCE_NS
+}
+
+/// Level of a single audio channel, as reported by the `level` element (in dB)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelLevel {
+    pub rms_db: f64,
+    pub peak_db: f64,
+    pub decay_db: f64,
+}
+
+/// Per-channel audio levels for one metering interval of an audio stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioLevels {
+    pub channels: Vec<ChannelLevel>,
+    pub running_time_ns: Option<u64>,
+    pub system_time_ns: i64,
+}
+
+impl AudioLevels {
+    fn from_structure(s: &gstreamer::StructureRef) -> Option<Self> {
+        if s.name() != "level" {
+            return None;
+        }
+
+        let values = |field: &str| -> Vec<f64> {
+            s.get::<gstreamer::glib::ValueArray>(field)
+                .map(|arr| arr.iter().filter_map(|v| v.get::<f64>().ok()).collect())
+                .unwrap_or_default()
+        };
+
+        let rms = values("rms");
+        let peak = values("peak");
+        let decay = values("decay");
+
+        let channels = rms
+            .iter()
+            .zip(peak.iter())
+            .zip(decay.iter())
+            .map(|((rms_db, peak_db), decay_db)| ChannelLevel {
+                rms_db: *rms_db,
+                peak_db: *peak_db,
+                decay_db: *decay_db,
+            })
+            .collect();
+
+        Some(AudioLevels {
+            channels,
+            running_time_ns: s
+                .get::<gstreamer::ClockTime>("running-time")
+                .ok()
+                .map(|t| t.nseconds()),
+            system_time_ns: system_time_nanos(),
+        })
+    }
+}
+
+/// Result of running a `GstDiscoverer` pass over a finished recording
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordingValidation {
+    pub playable: bool,
+    pub duration_ns: Option<u64>,
+    pub expected_duration_ns: Option<i64>,
+    pub tolerance_ns: i64,
+    pub error: Option<String>,
+}
+
+impl RecordingValidation {
+    pub fn is_valid(&self) -> bool {
+        self.playable && self.error.is_none()
+    }
 }
 
 impl RecordingMetadata {
@@ -71,9 +207,16 @@ impl RecordingMetadata {
             codec,
             audio_channel,
             device_name: device_name,
+            validation: None,
+            duration_tolerance_ns: DEFAULT_RECORDING_DURATION_TOLERANCE_NS,
         }
     }
 
+    /// Sets how far the duration of the recording may be off when it is validated
+    pub fn set_duration_tolerance_ns(&mut self, tolerance_ns: i64) {
+        self.duration_tolerance_ns = tolerance_ns;
+    }
+
     pub fn set_start_time(&mut self, time: i64) {
         self.start_time = Some(time);
     }
@@ -90,6 +233,63 @@ impl RecordingMetadata {
         self.end_time
     }
 
+    pub fn file_path(&self) -> PathBuf {
+        PathBuf::from(&self.parent_dir).join(&self.filename)
+    }
+
+    /// Runs a discoverer pass over the recorded file and checks that it is playable
+    /// and that its duration matches `end_time - start_time` within the duration
+    /// tolerance. The result is stored on the metadata and returned.
+    pub fn validate(&mut self) -> RecordingValidation {
+        let tolerance_ns = self.duration_tolerance_ns;
+        let expected_duration_ns = match (self.start_time, self.end_time) {
+            (Some(start), Some(end)) => Some(end - start),
+            _ => None,
+        };
+
+        let mut validation = RecordingValidation {
+            playable: false,
+            duration_ns: None,
+            expected_duration_ns,
+            tolerance_ns,
+            error: None,
+        };
+
+        match discover_file(&self.file_path()) {
+            Ok(info) => {
+                let has_streams =
+                    !info.audio_streams().is_empty() || !info.video_streams().is_empty();
+                let duration = info.duration().map(|d| d.nseconds());
+
+                validation.playable =
+                    info.result() == gstreamer_pbutils::DiscovererResult::Ok && has_streams;
+                validation.duration_ns = duration;
+
+                validation.error = match (duration, expected_duration_ns) {
+                    _ if !validation.playable => {
+                        Some(format!("Recording is not playable: {:?}", info.result()))
+                    }
+                    (None, _) | (Some(0), _) => Some("Recording has zero duration".to_string()),
+                    (Some(duration), Some(expected))
+                        if (duration as i64 - expected).abs() > tolerance_ns =>
+                    {
+                        Some(format!(
+                            "Recording duration {}ns differs from expected {}ns",
+                            duration, expected
+                        ))
+                    }
+                    _ => None,
+                };
+            }
+            Err(e) => {
+                validation.error = Some(e.to_string());
+            }
+        }
+
+        self.validation = Some(validation.clone());
+        validation
+    }
+
     pub fn write_success(&self) -> Result<bool, GStreamerError> {
         let parent_dir = PathBuf::from(&self.parent_dir);
 
@@ -117,6 +317,7 @@ impl RecordingMetadata {
             "media_type": self.media_type,
             "codec": self.codec,
             "audio_channel": self.audio_channel,
+            "validation": self.validation,
         });
 
         let string_content = serde_json::to_string(&error_object).map_err(|e| {
@@ -131,23 +332,105 @@ impl RecordingMetadata {
 
         Ok(true)
     }
+
+    /// Writes the DOT graph of the pipeline that failed next to the error metadata
+    pub fn write_error_graph(&self, dot: &str) -> Result<bool, GStreamerError> {
+        let parent_dir = PathBuf::from(&self.parent_dir);
+        let graph_file = format!("{}.error.dot", self.filename);
+
+        std::fs::write(parent_dir.join(graph_file), dot).map_err(|e| {
+            GStreamerError::PipelineError(format!("Failed to write error graph: {}", e))
+        })?;
+
+        Ok(true)
+    }
 }
 
+/// Plays the pipeline until it reaches EOS or fails, moving `state_tx` to `Playing`
+/// and then to `Stopped` or `Failed`. Bus warnings, errors, EOS and latency
+/// messages are sent on `events_tx`.
 pub async fn run_pipeline(
     pipeline: gstreamer::Pipeline,
-    tx: broadcast::Sender<()>,
-    mut recording_metadata: Option<RecordingMetadata>,
+    state_tx: Arc<watch::Sender<StreamState>>,
+    events_tx: broadcast::Sender<StreamEvent>,
+    levels_tx: broadcast::Sender<AudioLevels>,
+    recording_metadata: Vec<RecordingMetadata>,
 ) -> Result<(), GStreamerError> {
-    let timing = Arc::new(Mutex::new(FileSinkTiming::default()));
+    let timings = recording_metadata
+        .iter()
+        .map(|_| Arc::new(Mutex::new(FileSinkTiming::default())))
+        .collect::<Vec<_>>();
+
+    run_pipeline_with_timings(
+        pipeline,
+        state_tx,
+        events_tx,
+        levels_tx,
+        recording_metadata,
+        timings,
+        Arc::new(Mutex::new(None)),
+    )
+    .await
+}
 
+/// Same as `run_pipeline`, with the filesink timings of each recording shared
+/// with the caller while the pipeline runs, and the DOT graph of the pipeline
+/// stored in `error_graph` if it fails
+pub(crate) async fn run_pipeline_with_timings(
+    pipeline: gstreamer::Pipeline,
+    state_tx: Arc<watch::Sender<StreamState>>,
+    events_tx: broadcast::Sender<StreamEvent>,
+    levels_tx: broadcast::Sender<AudioLevels>,
+    recording_metadata: Vec<RecordingMetadata>,
+    timings: Vec<Arc<Mutex<FileSinkTiming>>>,
+    error_graph: Arc<Mutex<Option<String>>>,
+) -> Result<(), GStreamerError> {
+    let result = play_pipeline(
+        &pipeline,
+        &state_tx,
+        &events_tx,
+        &levels_tx,
+        recording_metadata,
+        timings,
+        &error_graph,
+    );
+    state_tx.send_replace(match &result {
+        Ok(None) => StreamState::Stopped,
+        Ok(Some(reason)) => StreamState::Failed(reason.clone()),
+        Err(e) => StreamState::Failed(e.to_string()),
+    });
+    result.map(|_| ())
+}
+
+fn message_source(msg: &gstreamer::Message) -> Option<String> {
+    msg.src().map(|src| src.path_string().to_string())
+}
+
+/// Runs the pipeline to the end, returning the error it failed with, if any
+fn play_pipeline(
+    pipeline: &gstreamer::Pipeline,
+    state_tx: &watch::Sender<StreamState>,
+    events_tx: &broadcast::Sender<StreamEvent>,
+    levels_tx: &broadcast::Sender<AudioLevels>,
+    mut recording_metadata: Vec<RecordingMetadata>,
+    timings: Vec<Arc<Mutex<FileSinkTiming>>>,
+    error_graph: &Mutex<Option<String>>,
+) -> Result<Option<String>, GStreamerError> {
     let master_clock = gstreamer::SystemClock::obtain();
     pipeline.set_clock(Some(&master_clock));
 
 
-    if recording_metadata.is_some() {
+    for (metadata, timing) in recording_metadata.iter().zip(timings.iter()) {
+        // Match each recording to the filesink writing it
         let filesink = pipeline.iterate_elements().find(|e| {
             let factory = e.factory();
             factory.map(|f| f.name() == *"filesink").unwrap_or(false)
+                && e.property::<Option<String>>("location")
+                    .map(|location| {
+                        PathBuf::from(location).file_name()
+                            == Some(std::ffi::OsStr::new(&metadata.filename))
+                    })
+                    .unwrap_or(false)
         });
 
         if let Some(filesink) = filesink {
@@ -179,11 +462,14 @@ pub async fn run_pipeline(
             GStreamerError::PipelineError("Failed to set pipeline to Playing state".to_string())
         })?;
     let bus = pipeline.bus().unwrap();
+    let mut reached_eos = false;
+    let mut failure = None;
     for msg in bus.iter_timed(gstreamer::ClockTime::NONE) {
         use gstreamer::MessageView;
         match msg.view() {
             MessageView::Eos(..) => {
-                if let Some(metadata) = recording_metadata.as_mut() {
+                let _ = events_tx.send(StreamEvent::Eos);
+                for (metadata, timing) in recording_metadata.iter_mut().zip(timings.iter()) {
                     metadata.set_end_time(system_time_nanos());
                     // Get more reliable timestamps from the Filesink
                     if let Some(start_time) = timing.lock().unwrap().start_time {
@@ -192,23 +478,79 @@ pub async fn run_pipeline(
                     if let Some(end_time) = timing.lock().unwrap().end_time {
                         metadata.set_end_time(end_time);
                     }
-                    let _ = metadata.write_success();
                 }
+                reached_eos = true;
                 break;
             }
             MessageView::Error(err) => {
-                if let Some(metadata) = recording_metadata.as_mut() {
-                    let _ =
-                        metadata.write_error(&format!("Pipeline error: {}", err.error().message()));
+                let message = err.error().message().to_string();
+                // Dump the graph before the pipeline is torn down, while the caps
+                // are still negotiated
+                let graph = pipeline_dot(pipeline);
+                // Only written when GST_DEBUG_DUMP_DOT_DIR is set
+                gstreamer::debug_bin_to_dot_file_with_ts(
+                    pipeline,
+                    gstreamer::DebugGraphDetails::all(),
+                    "syncflow-error",
+                );
+                for metadata in recording_metadata.iter_mut() {
+                    let _ = metadata.write_error(&format!("Pipeline error: {}", message));
+                    let _ = metadata.write_error_graph(&graph);
                 }
+                *error_graph.lock().unwrap() = Some(graph);
+                let _ = events_tx.send(StreamEvent::Error {
+                    source: message_source(&msg),
+                    message: message.clone(),
+                    debug: err.debug().map(|debug| debug.to_string()),
+                });
+                failure = Some(message);
                 break;
             }
+            MessageView::Warning(warning) => {
+                let _ = events_tx.send(StreamEvent::Warning {
+                    source: message_source(&msg),
+                    message: warning.error().message().to_string(),
+                    debug: warning.debug().map(|debug| debug.to_string()),
+                });
+            }
+            MessageView::Latency(..) => {
+                let _ = pipeline.recalculate_latency();
+                let mut query = gstreamer::query::Latency::new();
+                if pipeline.query(&mut query) {
+                    let (live, min, max) = query.result();
+                    let _ = events_tx.send(StreamEvent::Latency {
+                        live,
+                        min_ns: min.nseconds(),
+                        max_ns: max.map(|max| max.nseconds()),
+                    });
+                }
+            }
+            MessageView::Element(e) => {
+                if let Some(levels) = e.structure().and_then(AudioLevels::from_structure) {
+                    if levels_tx.receiver_count() > 0 {
+                        let _ = levels_tx.send(levels);
+                    }
+                }
+            }
             MessageView::StateChanged(e) => {
-                if let Some(metadata) = recording_metadata.as_mut() {
+                for metadata in recording_metadata.iter_mut() {
                     if e.current() == gstreamer::State::Playing {
                         metadata.set_start_time(system_time_nanos());
                     }
                 }
+                if e.current() == gstreamer::State::Playing
+                    && msg.src() == Some(pipeline.upcast_ref::<gstreamer::Object>())
+                {
+                    // A stop may have been requested while prerolling
+                    state_tx.send_if_modified(|state| {
+                        let starting =
+                            matches!(state, StreamState::Starting | StreamState::Recovering);
+                        if starting {
+                            *state = StreamState::Playing;
+                        }
+                        starting
+                    });
+                }
                 if e.current() == gstreamer::State::Null {
                     break;
                 }
@@ -219,9 +561,40 @@ pub async fn run_pipeline(
     pipeline.set_state(gstreamer::State::Null).map_err(|_| {
         GStreamerError::PipelineError("Failed to set pipeline to Null state".to_string())
     })?;
-    tx.send(())
-        .map_err(|_| GStreamerError::PipelineError("Failed to send signal".to_string()))?;
-    Ok(())
+
+    // Validate only once the pipeline is in Null, so that the filesink has closed the file
+    if reached_eos {
+        for metadata in recording_metadata.iter_mut() {
+            let validation = metadata.validate();
+            match validation.error {
+                None => {
+                    let _ = metadata.write_success();
+                }
+                Some(error) => {
+                    let _ = metadata.write_error(&format!("Validation error: {}", error));
+                }
+            }
+        }
+    }
+
+    Ok(failure)
+}
+
+fn discover_file(
+    path: &std::path::Path,
+) -> Result<gstreamer_pbutils::DiscovererInfo, GStreamerError> {
+    let uri = gstreamer::glib::filename_to_uri(path, None).map_err(|e| {
+        GStreamerError::PipelineError(format!("Failed to create uri for recording: {}", e))
+    })?;
+
+    let discoverer = gstreamer_pbutils::Discoverer::new(gstreamer::ClockTime::from_seconds(
+        DISCOVERER_TIMEOUT_SECS,
+    ))
+    .map_err(|e| GStreamerError::PipelineError(format!("Failed to create discoverer: {}", e)))?;
+
+    discoverer
+        .discover_uri(&uri)
+        .map_err(|e| GStreamerError::PipelineError(format!("Failed to discover recording: {}", e)))
 }
 
 impl GstMediaDevice {
@@ -297,13 +670,17 @@ impl GstMediaDevice {
         get_device_capabilities(&device)
     }
 
+    /// Captures the screen at `width`x`height` and scales the stream handed to
+    /// `tx` to `stream_format`. The recording keeps the capture resolution.
+    #[allow(clippy::too_many_arguments)]
     pub fn screen_share_pipeline(
         &self,
         codec: &str,
         width: i32,
         height: i32,
         framerate: i32,
-        tx: Arc<broadcast::Sender<Arc<Buffer>>>,
+        stream_format: VideoStreamFormat,
+        tx: Arc<StreamFrameSender>,
         filename: Option<String>,
     ) -> Result<gstreamer::Pipeline, GStreamerError> {
         if self.device_class != "Screen/Source" {
@@ -377,12 +754,7 @@ impl GstMediaDevice {
                 GStreamerError::PipelineError("Failed to create stream videoscale".to_string())
             })?;
 
-        let stream_caps = gstreamer::Caps::builder("video/x-raw")
-            .field("width", 640)
-            .field("height", 480)
-            .field("framerate", gstreamer::Fraction::new(framerate, 1))
-            .field("format", VIDEO_FRAME_FORMAT)
-            .build();
+        let stream_caps = self.stream_caps(stream_format, framerate);
 
         let stream_capsfilter = gstreamer::ElementFactory::make("capsfilter")
             .name(random_string("stream-capsfilter"))
@@ -454,13 +826,17 @@ impl GstMediaDevice {
         Ok(pipeline)
     }
 
+    /// Captures the device at `width`x`height` and scales the stream handed to
+    /// `tx` to `stream_format`. The recording keeps the capture resolution.
+    #[allow(clippy::too_many_arguments)]
     pub fn video_pipeline(
         &self,
         codec: &str,
         width: i32,
         height: i32,
         framerate: i32,
-        tx: Arc<broadcast::Sender<Arc<Buffer>>>,
+        stream_format: VideoStreamFormat,
+        tx: Arc<StreamFrameSender>,
         filename: Option<String>,
     ) -> Result<gstreamer::Pipeline, GStreamerError> {
         if self.device_class == "Audio/Source" {
@@ -483,11 +859,18 @@ impl GstMediaDevice {
             ));
         }
         if codec == "video/x-raw" {
-            return self.video_xraw_pipeline(width, height, framerate, tx, filename);
+            return self.video_xraw_pipeline(width, height, framerate, stream_format, tx, filename);
         } else if codec == "video/x-h264" {
-            return self.video_xh264_pipeline(width, height, framerate, tx, filename);
+            return self.video_xh264_pipeline(
+                width,
+                height,
+                framerate,
+                stream_format,
+                tx,
+                filename,
+            );
         } else if codec == "image/jpeg" {
-            return self.image_jpeg_pipeline(width, height, framerate, tx, filename);
+            return self.image_jpeg_pipeline(width, height, framerate, stream_format, tx, filename);
         }
 
         Err(GStreamerError::PipelineError(
@@ -500,8 +883,9 @@ impl GstMediaDevice {
         codec: &str,
         channels: i32,
         framerate: i32,
-        tx: Arc<broadcast::Sender<Arc<Buffer>>>,
+        tx: Arc<StreamFrameSender>,
         filename: Option<String>,
+        processing: Option<&AudioProcessingOptions>,
     ) -> Result<gstreamer::Pipeline, GStreamerError> {
         if self.device_class == "Video/Source" {
             return Err(GStreamerError::PipelineError(
@@ -522,17 +906,19 @@ impl GstMediaDevice {
                 "Device does not support requested configuration".to_string(),
             ));
         }
-        self.audio_xraw_pipeline(channels, framerate, tx, filename)
+        self.audio_xraw_pipeline(channels, framerate, tx, filename, processing)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn deinterleaved_audio_pipeline(
         &self,
         codec: &str,
         channels: i32,
         selected_channel: i32,
         framerate: i32,
-        tx: Arc<broadcast::Sender<Arc<Buffer>>>,
+        tx: Arc<StreamFrameSender>,
         filename: Option<String>,
+        processing: Option<&AudioProcessingOptions>,
     ) -> Result<gstreamer::Pipeline, GStreamerError> {
         if self.device_class == "Video/Source" {
             return Err(GStreamerError::PipelineError(
@@ -554,7 +940,14 @@ impl GstMediaDevice {
             ));
         }
 
-        self.audio_deinterleaved_pipeline(selected_channel, channels, framerate, tx, filename)
+        self.audio_deinterleaved_pipeline(
+            selected_channel,
+            channels,
+            framerate,
+            tx,
+            filename,
+            processing,
+        )
     }
 
     fn audio_deinterleaved_pipeline(
@@ -562,8 +955,9 @@ impl GstMediaDevice {
         selected_channel: i32,
         channels: i32,
         framerate: i32,
-        tx: Arc<broadcast::Sender<Arc<Buffer>>>,
+        tx: Arc<StreamFrameSender>,
         filename: Option<String>,
+        processing: Option<&AudioProcessingOptions>,
     ) -> Result<gstreamer::Pipeline, GStreamerError> {
         let audio_el = self.get_audio_element()?;
         let convert = gstreamer::ElementFactory::make("audioconvert")
@@ -601,6 +995,8 @@ impl GstMediaDevice {
             .build()
             .map_err(|_| GStreamerError::PipelineError("Failed to create queue".to_string()))?;
 
+        let level = self.level_element()?;
+
         let tee = gstreamer::ElementFactory::make("tee")
             .name(random_string("tee"))
             .build()
@@ -613,6 +1009,13 @@ impl GstMediaDevice {
 
         let broadcast_appsink = self.broadcast_appsink(tx, None)?;
 
+        // Deinterleaving leaves a single channel for the rest of the pipeline
+        let processing_elements = match processing {
+            Some(options) => self.audio_processing_elements(options, 1, framerate)?,
+            None => vec![],
+        };
+        let apply_to_recording = processing.map(|p| p.apply_to_recording).unwrap_or(false);
+
         let pipeline = gstreamer::Pipeline::with_name(&random_string("deinterleaved-audio-xraw"));
 
         pipeline
@@ -622,6 +1025,7 @@ impl GstMediaDevice {
                 &caps_element,
                 &deinterleave_element,
                 &queue,
+                &level,
                 &tee,
                 &queue_appsink,
                 (broadcast_appsink.upcast_ref()),
@@ -630,6 +1034,10 @@ impl GstMediaDevice {
                 GStreamerError::PipelineError("Failed to add elements to pipeline".to_string())
             })?;
 
+        pipeline.add_many(&processing_elements).map_err(|_| {
+            GStreamerError::PipelineError("Failed to add audio processing elements".to_string())
+        })?;
+
         gstreamer::Element::link_many([&audio_el, &convert, &caps_element, &deinterleave_element])
             .map_err(|_| GStreamerError::PipelineError("Failed to link elements".to_string()))?;
 
@@ -646,7 +1054,17 @@ impl GstMediaDevice {
             }
         });
 
-        gstreamer::Element::link_many([&queue, &tee]).map_err(|_| {
+        let mut pre_tee = vec![queue.clone(), level.clone()];
+        let mut live_branch = vec![queue_appsink.clone()];
+        if apply_to_recording {
+            pre_tee.extend(processing_elements);
+        } else {
+            live_branch.extend(processing_elements);
+        }
+        pre_tee.push(tee.clone());
+        live_branch.push(broadcast_appsink.clone().upcast());
+
+        gstreamer::Element::link_many(&pre_tee).map_err(|_| {
             GStreamerError::PipelineError("Failed to link queue and tee".to_string())
         })?;
 
@@ -662,7 +1080,7 @@ impl GstMediaDevice {
             GStreamerError::PipelineError("Failed to link tee to appsink queue".into())
         })?;
 
-        gstreamer::Element::link_many([&queue_appsink, broadcast_appsink.upcast_ref()])
+        gstreamer::Element::link_many(&live_branch)
             .map_err(|_| GStreamerError::PipelineError("Failed to link appsink".to_string()))?;
 
         if let Some(ref path) = filename {
@@ -676,8 +1094,9 @@ impl GstMediaDevice {
         &self,
         channels: i32,
         framerate: i32,
-        tx: Arc<broadcast::Sender<Arc<Buffer>>>,
+        tx: Arc<StreamFrameSender>,
         filename: Option<String>,
+        processing: Option<&AudioProcessingOptions>,
     ) -> Result<gstreamer::Pipeline, GStreamerError> {
         let audio_el = self.get_audio_element()?;
         let convert = gstreamer::ElementFactory::make("audioconvert")
@@ -719,6 +1138,8 @@ impl GstMediaDevice {
         audiorate.set_property("tolerance", 40000000u64);
         audiorate.set_property("skip-to-first", true);
 
+        let level = self.level_element()?;
+
         let tee = gstreamer::ElementFactory::make("tee")
             .name(random_string("tee"))
             .build()
@@ -731,21 +1152,42 @@ impl GstMediaDevice {
 
         let broadcast_appsink = self.broadcast_appsink(tx, None)?;
 
+        let processing_elements = match processing {
+            Some(options) => self.audio_processing_elements(options, channels, framerate)?,
+            None => vec![],
+        };
+        let apply_to_recording = processing.map(|p| p.apply_to_recording).unwrap_or(false);
+
+        let mut pre_tee = vec![
+            audio_el.clone(),
+            convert.clone(),
+            resample.clone(),
+            caps_element.clone(),
+            audiorate.clone(),
+            level.clone(),
+        ];
+        let mut live_branch = vec![queue_appsink.clone()];
+        if apply_to_recording {
+            pre_tee.extend(processing_elements);
+        } else {
+            live_branch.extend(processing_elements);
+        }
+        pre_tee.push(tee.clone());
+        live_branch.push(broadcast_appsink.clone().upcast());
+
         let pipeline = gstreamer::Pipeline::with_name(&random_string("stream-audio-xraw"));
 
-        pipeline
-            .add_many([&audio_el, &convert, &resample, &caps_element, &audiorate, &tee])
-            .map_err(|_| {
-                GStreamerError::PipelineError("Failed to add elements to pipeline".to_string())
-            })?;
+        pipeline.add_many(&pre_tee).map_err(|_| {
+            GStreamerError::PipelineError("Failed to add elements to pipeline".to_string())
+        })?;
 
-        gstreamer::Element::link_many([&audio_el, &convert, &resample, &caps_element, &audiorate, &tee])
+        gstreamer::Element::link_many(&pre_tee)
             .map_err(|_| GStreamerError::PipelineError("Failed to link elements".to_string()))?;
 
         pipeline
-            .add_many([&queue_appsink, broadcast_appsink.upcast_ref()])
+            .add_many(&live_branch)
             .map_err(|_| GStreamerError::PipelineError("Failed to add appsink".to_string()))?;
-        gstreamer::Element::link_many([&queue_appsink, broadcast_appsink.upcast_ref()])
+        gstreamer::Element::link_many(&live_branch)
             .map_err(|_| GStreamerError::PipelineError("Failed to link appsink".to_string()))?;
 
         let tee_appsink_pad = tee.request_pad_simple("src_%u").ok_or_else(|| {
@@ -854,7 +1296,8 @@ impl GstMediaDevice {
         width: i32,
         height: i32,
         framerate: i32,
-        tx: Arc<broadcast::Sender<Arc<Buffer>>>,
+        stream_format: VideoStreamFormat,
+        tx: Arc<StreamFrameSender>,
         filename: Option<String>,
     ) -> Result<gstreamer::Pipeline, GStreamerError> {
         let input = self.get_video_element()?;
@@ -917,12 +1360,7 @@ impl GstMediaDevice {
                 GStreamerError::PipelineError("Failed to create videoscale".to_string())
             })?;
 
-        let stream_caps = gstreamer::Caps::builder("video/x-raw")
-            .field("width", 640)
-            .field("height", 480)
-            .field("framerate", gstreamer::Fraction::new(framerate, 1))
-            .field("format", VIDEO_FRAME_FORMAT)
-            .build();
+        let stream_caps = self.stream_caps(stream_format, framerate);
 
         let stream_capsfilter = gstreamer::ElementFactory::make("capsfilter")
             .name(random_string("capsfilter"))
@@ -998,7 +1436,8 @@ impl GstMediaDevice {
         width: i32,
         height: i32,
         framerate: i32,
-        tx: Arc<broadcast::Sender<Arc<Buffer>>>,
+        stream_format: VideoStreamFormat,
+        tx: Arc<StreamFrameSender>,
         filename: Option<String>,
     ) -> Result<gstreamer::Pipeline, GStreamerError> {
         if filename.is_some() {
@@ -1033,10 +1472,31 @@ impl GstMediaDevice {
                 GStreamerError::PipelineError("Failed to create avdec_h264".to_string())
             })?;
 
-        let i420_caps = gstreamer::Caps::builder("video/x-raw")
-            .field("format", "I420")
-            .build();
-        let appsink = self.broadcast_appsink(tx, Some(&i420_caps))?;
+        let stream_convert = gstreamer::ElementFactory::make("videoconvert")
+            .name(random_string("videoconvert"))
+            .build()
+            .map_err(|_| {
+                GStreamerError::PipelineError("Failed to create videoconvert".to_string())
+            })?;
+
+        let stream_scale = gstreamer::ElementFactory::make("videoscale")
+            .name(random_string("videoscale"))
+            .build()
+            .map_err(|_| {
+                GStreamerError::PipelineError("Failed to create videoscale".to_string())
+            })?;
+
+        let stream_caps = self.stream_caps(stream_format, framerate);
+
+        let stream_capsfilter = gstreamer::ElementFactory::make("capsfilter")
+            .name(random_string("capsfilter"))
+            .build()
+            .map_err(|_| {
+                GStreamerError::PipelineError("Failed to create capsfilter".to_string())
+            })?;
+        stream_capsfilter.set_property("caps", &stream_caps);
+
+        let appsink = self.broadcast_appsink(tx, Some(&stream_caps))?;
 
         let pipeline = gstreamer::Pipeline::with_name(&random_string("stream-h264"));
 
@@ -1046,6 +1506,9 @@ impl GstMediaDevice {
                 &caps_element,
                 &h264parse,
                 &avdec_h264,
+                &stream_convert,
+                &stream_scale,
+                &stream_capsfilter,
                 appsink.upcast_ref(),
             ])
             .map_err(|_| {
@@ -1057,6 +1520,9 @@ impl GstMediaDevice {
             &caps_element,
             &h264parse,
             &avdec_h264,
+            &stream_convert,
+            &stream_scale,
+            &stream_capsfilter,
             appsink.upcast_ref(),
         ])
         .map_err(|_| GStreamerError::PipelineError("Failed to link elements".to_string()))?;
@@ -1069,7 +1535,8 @@ impl GstMediaDevice {
         width: i32,
         height: i32,
         framerate: i32,
-        tx: Arc<broadcast::Sender<Arc<Buffer>>>,
+        stream_format: VideoStreamFormat,
+        tx: Arc<StreamFrameSender>,
         filename: Option<String>,
     ) -> Result<gstreamer::Pipeline, GStreamerError> {
         let input = self.get_video_element()?;
@@ -1135,12 +1602,7 @@ impl GstMediaDevice {
                 GStreamerError::PipelineError("Failed to create videoscale".to_string())
             })?;
 
-        let stream_caps = gstreamer::Caps::builder("video/x-raw")
-            .field("width", 640)
-            .field("height", 480)
-            .field("framerate", gstreamer::Fraction::new(framerate, 1))
-            .field("format", VIDEO_FRAME_FORMAT)
-            .build();
+        let stream_caps = self.stream_caps(stream_format, framerate);
 
         let stream_capsfilter = gstreamer::ElementFactory::make("capsfilter")
             .name(random_string("capsfilter"))
@@ -1203,7 +1665,7 @@ impl GstMediaDevice {
     }
 
     #[cfg(target_os = "windows")]
-    fn get_screen_element(&self) -> Result<gstreamer::Element, GStreamerError> {
+    pub(crate) fn get_screen_element(&self) -> Result<gstreamer::Element, GStreamerError> {
         let (_, idx) = get_monitor(&self.device_path)
             .ok_or_else(|| GStreamerError::DeviceError("No screen found".to_string()))?;
 
@@ -1228,7 +1690,7 @@ impl GstMediaDevice {
     }
 
     #[cfg(target_os = "macos")]
-    fn get_screen_element(&self) -> Result<gstreamer::Element, GStreamerError> {
+    pub(crate) fn get_screen_element(&self) -> Result<gstreamer::Element, GStreamerError> {
         let monitor = get_monitor(&self.device_path).ok_or_else(|| {
             GStreamerError::DeviceError(format!("No screen found {}", self.device_path))
         })?;
@@ -1257,7 +1719,7 @@ impl GstMediaDevice {
     }
 
     #[cfg(target_os = "linux")]
-    fn get_screen_element(&self) -> Result<gstreamer::Element, GStreamerError> {
+    pub(crate) fn get_screen_element(&self) -> Result<gstreamer::Element, GStreamerError> {
         let monitor = get_monitor(&self.device_path)
             .ok_or_else(|| GStreamerError::DeviceError("No screen found".to_string()))?;
 
@@ -1282,7 +1744,7 @@ impl GstMediaDevice {
         Ok(element)
     }
 
-    fn get_video_element(&self) -> Result<gstreamer::Element, GStreamerError> {
+    pub(crate) fn get_video_element(&self) -> Result<gstreamer::Element, GStreamerError> {
         let device = get_gst_device(&self.device_path).unwrap();
         let random_source_name = random_string("source");
         let element = device
@@ -1291,7 +1753,7 @@ impl GstMediaDevice {
         Ok(element)
     }
 
-    fn get_audio_element(&self) -> Result<gstreamer::Element, GStreamerError> {
+    pub(crate) fn get_audio_element(&self) -> Result<gstreamer::Element, GStreamerError> {
         let device = get_gst_device(&self.device_path).unwrap();
         println!("Device: {:?}", device);
         println!("Device props: {:?}", device.caps());
@@ -1302,9 +1764,111 @@ impl GstMediaDevice {
         Ok(element)
     }
 
-    fn broadcast_appsink(
+    /// Builds the noise suppression / gain control / high-pass chain. `webrtcdsp` is used
+    /// when available; otherwise gain control and the high-pass filter fall back to
+    /// `audiodynamic` and `audiocheblimit`. The chain outputs S16LE at `framerate`.
+    fn audio_processing_elements(
         &self,
-        tx: Arc<broadcast::Sender<Arc<Buffer>>>,
+        options: &AudioProcessingOptions,
+        channels: i32,
+        framerate: i32,
+    ) -> Result<Vec<gstreamer::Element>, GStreamerError> {
+        let output_caps = gstreamer::Caps::builder("audio/x-raw")
+            .field("format", "S16LE")
+            .field("channels", channels)
+            .field("rate", framerate)
+            .build();
+
+        let mut elements = vec![];
+
+        if gstreamer::ElementFactory::find("webrtcdsp").is_some() {
+            // webrtcdsp only accepts 8, 16, 32 and 48 kHz
+            let dsp_caps = gstreamer::Caps::builder("audio/x-raw")
+                .field("format", "S16LE")
+                .field("rate", AUDIO_PROCESSING_RATE)
+                .build();
+            let dsp_capsfilter = make_element("capsfilter")?;
+            dsp_capsfilter.set_property("caps", &dsp_caps);
+
+            let dsp = make_element("webrtcdsp")?;
+            dsp.set_property("noise-suppression", options.noise_suppression);
+            dsp.set_property("gain-control", options.gain_control);
+            dsp.set_property("high-pass-filter", options.high_pass_filter);
+            dsp.set_property("echo-cancel", options.echo_cancellation);
+
+            elements.extend([
+                make_element("audioconvert")?,
+                make_element("audioresample")?,
+                dsp_capsfilter,
+                dsp,
+            ]);
+        } else {
+            if options.noise_suppression || options.echo_cancellation {
+                return Err(GStreamerError::PipelineError(
+                    "webrtcdsp is required for noise suppression and echo cancellation".to_string(),
+                ));
+            }
+
+            elements.push(make_element("audioconvert")?);
+
+            if options.high_pass_filter {
+                let high_pass = make_element("audiocheblimit")?;
+                high_pass.set_property_from_str("mode", "high-pass");
+                high_pass.set_property("cutoff", HIGH_PASS_CUTOFF_HZ);
+                elements.push(high_pass);
+            }
+
+            if options.gain_control {
+                let dynamic = make_element("audiodynamic")?;
+                dynamic.set_property_from_str("mode", "compressor");
+                dynamic.set_property_from_str("characteristics", "soft-knee");
+                dynamic.set_property("threshold", 0.3f32);
+                dynamic.set_property("ratio", 0.5f32);
+                elements.push(dynamic);
+            }
+        }
+
+        let output_capsfilter = make_element("capsfilter")?;
+        output_capsfilter.set_property("caps", &output_caps);
+        elements.extend([
+            make_element("audioconvert")?,
+            make_element("audioresample")?,
+            output_capsfilter,
+        ]);
+
+        Ok(elements)
+    }
+
+    pub(crate) fn level_element(&self) -> Result<gstreamer::Element, GStreamerError> {
+        let level = gstreamer::ElementFactory::make("level")
+            .name(random_string("level"))
+            .build()
+            .map_err(|_| GStreamerError::PipelineError("Failed to create level".to_string()))?;
+
+        level.set_property("interval", LEVEL_INTERVAL_NS);
+        level.set_property("post-messages", true);
+
+        Ok(level)
+    }
+
+    /// Caps of the raw frames handed to subscribers and LiveKit
+    pub(crate) fn stream_caps(
+        &self,
+        stream_format: VideoStreamFormat,
+        framerate: i32,
+    ) -> gstreamer::Caps {
+        gstreamer::Caps::builder("video/x-raw")
+            .field("width", stream_format.width)
+            .field("height", stream_format.height)
+            .field("framerate", gstreamer::Fraction::new(framerate, 1))
+            .field("format", stream_format.format.as_str())
+            .field("pixel-aspect-ratio", gstreamer::Fraction::new(1, 1))
+            .build()
+    }
+
+    pub(crate) fn broadcast_appsink(
+        &self,
+        tx: Arc<StreamFrameSender>,
         caps: Option<&gstreamer::Caps>,
     ) -> Result<AppSink, GStreamerError> {
         let appsink = gstreamer::ElementFactory::make("appsink")
@@ -1317,6 +1881,8 @@ impl GstMediaDevice {
 
         // appsink.set_property("sync", &false);
         appsink.set_property("emit-signals", true);
+        // Samples are pulled as they arrive, how frames are dropped for slow
+        // subscribers is up to their `BackpressurePolicy`
         appsink.set_property("drop", true);
         appsink.set_property("max-buffers", 1u32);
 
@@ -1328,10 +1894,11 @@ impl GstMediaDevice {
                         Err(_) => return Err(gstreamer::FlowError::Eos),
                     };
 
-                    let buffer = sample.buffer().ok_or(gstreamer::FlowError::Error)?;
-
-                    if tx.receiver_count() > 0 {
-                        let _ = tx.send(Arc::new(buffer.copy()));
+                    if tx.has_subscribers() {
+                        // The sample keeps the caps each buffer was negotiated with, and
+                        // only holds a reference, the memory goes back to the upstream
+                        // buffer pool once every subscriber dropped the frame
+                        tx.send(sample);
                     }
                     Ok(gstreamer::FlowSuccess::Ok)
                 })
@@ -1344,7 +1911,7 @@ impl GstMediaDevice {
         Ok(appsink)
     }
 
-    fn add_video_file_branch(
+    pub(crate) fn add_video_file_branch(
         &self,
         pipeline: &gstreamer::Pipeline,
         tee: &gstreamer::Element,
@@ -1430,7 +1997,7 @@ impl GstMediaDevice {
         Ok(())
     }
 
-    fn add_audio_file_branch(
+    pub(crate) fn add_audio_file_branch(
         &self,
         pipeline: &gstreamer::Pipeline,
         tee: &gstreamer::Element,
@@ -1,29 +1,70 @@
 use crate::{
-    media_device::{run_pipeline, GStreamerError, GstMediaDevice},
-    utils::random_string,
+    capability_negotiation::{
+        AudioConstraints, ConstrainRange, NegotiatedAudioMode, NegotiatedMode, NegotiatedVideoMode,
+        VideoConstraints,
+    },
+    frame_queue::{BackpressurePolicy, FrameQueueReceiver, SubscriberMetrics},
+    frame_subscription::{AudioFrameReceiver, StreamFrameSender, VideoFrameReceiver},
+    media_device::{
+        run_pipeline_with_timings, AudioLevels, FileSinkTiming, GStreamerError, GstMediaDevice,
+        VideoFrameFormat, VideoStreamFormat, DEFAULT_RECORDING_DURATION_TOLERANCE_NS,
+    },
+    pipeline_debug::{inspect_pipeline, pipeline_dot, write_pipeline_dump, PipelineInfo},
+    stream_state::{stream_ended, StreamEvent, StreamState},
+    track_sync::StreamClock,
+    utils::{fit_resolution, random_string},
+    video_compositor::{CompositeLayout, CompositeRect},
+    voice_activity::{
+        write_voice_activity_sidecar, VoiceActivityDetector, VoiceActivityEvent,
+        VoiceActivityOptions,
+    },
     RecordingMetadata,
 };
-use gstreamer::{prelude::*, Buffer, Pipeline};
+use gstreamer::{prelude::*, Buffer, Pipeline, Sample};
 use serde::{Deserialize, Serialize};
 use std::{
     path::{self, PathBuf},
-    sync::Arc,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    fs,
+    sync::{broadcast, watch},
 };
-use tokio::{fs, sync::broadcast};
 
 #[derive(Debug)]
 struct StreamHandle {
-    close_tx: broadcast::Sender<()>,
-    frame_tx: broadcast::Sender<Arc<Buffer>>,
+    events_tx: broadcast::Sender<StreamEvent>,
+    frame_tx: Arc<StreamFrameSender>,
+    levels_tx: broadcast::Sender<AudioLevels>,
+    voice_activity_tx: broadcast::Sender<VoiceActivityEvent>,
     task: tokio::task::JoinHandle<Result<(), GStreamerError>>,
+    voice_activity_task: Option<tokio::task::JoinHandle<()>>,
     pipeline: Pipeline,
     device: GstMediaDevice,
+    recording_timings: Vec<(String, Arc<Mutex<FileSinkTiming>>)>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LocalFileSaveOptions {
     pub output_dir: String,
+    /// How far the duration of a finished recording may differ from the time it
+    /// was written for before it is reported as invalid
+    #[serde(default = "default_recording_duration_tolerance_ns")]
+    pub recording_duration_tolerance_ns: i64,
+}
+
+impl Default for LocalFileSaveOptions {
+    fn default() -> Self {
+        Self {
+            output_dir: "recordings".to_string(),
+            recording_duration_tolerance_ns: DEFAULT_RECORDING_DURATION_TOLERANCE_NS,
+        }
+    }
+}
+
+fn default_recording_duration_tolerance_ns() -> i64 {
+    DEFAULT_RECORDING_DURATION_TOLERANCE_NS
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,15 +75,82 @@ pub struct LocalSaveFileMetadata {
     pub started_at: String,
 }
 
+/// Upper bound for the resolution of a published track. The capture is scaled
+/// down to fit, keeping its aspect ratio, and never scaled up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishResolution {
+    pub width: i32,
+    pub height: i32,
+}
+
+/// The size frames are handed to LiveKit at for a `width`x`height` capture,
+/// rounded down to even dimensions for the subsampled chroma planes
+pub fn published_size(width: i32, height: i32, max: Option<PublishResolution>) -> (i32, i32) {
+    let (width, height) = match max {
+        Some(max) if width > max.width || height > max.height => {
+            fit_resolution(width, height, max.width, max.height)
+        }
+        _ => (width, height),
+    };
+    (width.max(2) & !1, height.max(2) & !1)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct VideoPublishOptions {
     pub codec: String,
     pub device_id: String,
+    /// Capture resolution requested from the device
     pub width: i32,
     pub height: i32,
     pub framerate: i32,
     pub local_file_save_options: Option<LocalFileSaveOptions>,
+    /// Defaults to the capture resolution
+    #[serde(default)]
+    pub publish_resolution: Option<PublishResolution>,
+    #[serde(default)]
+    pub frame_format: VideoFrameFormat,
+}
+
+impl VideoPublishOptions {
+    pub fn published_size(&self) -> (i32, i32) {
+        published_size(self.width, self.height, self.publish_resolution)
+    }
+
+    pub fn stream_format(&self) -> VideoStreamFormat {
+        let (width, height) = self.published_size();
+        VideoStreamFormat {
+            width,
+            height,
+            format: self.frame_format,
+        }
+    }
+}
+
+/// Processing applied to the live (LiveKit) branch of an audio stream, and
+/// optionally to the local recording as well
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AudioProcessingOptions {
+    pub noise_suppression: bool,
+    pub gain_control: bool,
+    pub high_pass_filter: bool,
+    /// Requires a `webrtcechoprobe` on the playback path of the same process
+    pub echo_cancellation: bool,
+    pub apply_to_recording: bool,
+}
+
+impl Default for AudioProcessingOptions {
+    fn default() -> Self {
+        Self {
+            noise_suppression: true,
+            gain_control: true,
+            high_pass_filter: true,
+            echo_cancellation: false,
+            apply_to_recording: false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -54,6 +162,10 @@ pub struct AudioPublishOptions {
     pub channels: i32,
     pub selected_channel: Option<i32>,
     pub local_file_save_options: Option<LocalFileSaveOptions>,
+    #[serde(default)]
+    pub voice_activity: Option<VoiceActivityOptions>,
+    #[serde(default)]
+    pub processing: Option<AudioProcessingOptions>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -61,10 +173,159 @@ pub struct AudioPublishOptions {
 pub struct ScreenPublishOptions {
     pub codec: String,
     pub screen_id_or_name: String,
+    /// Capture resolution of the screen
+    pub width: i32,
+    pub height: i32,
+    pub framerate: i32,
+    pub local_file_save_options: Option<LocalFileSaveOptions>,
+    /// Defaults to the capture resolution
+    #[serde(default)]
+    pub publish_resolution: Option<PublishResolution>,
+    #[serde(default)]
+    pub frame_format: VideoFrameFormat,
+}
+
+impl ScreenPublishOptions {
+    pub fn published_size(&self) -> (i32, i32) {
+        published_size(self.width, self.height, self.publish_resolution)
+    }
+
+    pub fn stream_format(&self) -> VideoStreamFormat {
+        let (width, height) = self.published_size();
+        VideoStreamFormat {
+            width,
+            height,
+            format: self.frame_format,
+        }
+    }
+}
+
+fn default_gain() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioMixerInput {
+    pub device_id: String,
+    pub channels: i32,
+    pub selected_channel: Option<i32>,
+    #[serde(default = "default_gain")]
+    pub gain: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioMixerPublishOptions {
+    pub codec: String,
+    pub framerate: i32,
+    pub channels: i32,
+    pub inputs: Vec<AudioMixerInput>,
+    /// Record every input to its own file next to the recording of the mix
+    #[serde(default)]
+    pub record_inputs: bool,
+    pub local_file_save_options: Option<LocalFileSaveOptions>,
+}
+
+impl AudioMixerPublishOptions {
+    /// The inputs of the mixer as if they were published on their own
+    pub fn input_options(&self) -> Vec<AudioPublishOptions> {
+        self.inputs
+            .iter()
+            .map(|input| AudioPublishOptions {
+                codec: self.codec.clone(),
+                device_id: input.device_id.clone(),
+                framerate: self.framerate,
+                channels: input.channels,
+                selected_channel: input.selected_channel,
+                local_file_save_options: None,
+                voice_activity: None,
+                processing: None,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompositeInputKind {
+    Camera,
+    Screen,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompositeInput {
+    pub kind: CompositeInputKind,
+    /// Device path for cameras, screen id or name for screens
+    pub device_id: String,
+    pub codec: String,
     pub width: i32,
     pub height: i32,
     pub framerate: i32,
+    /// Placement in the composite, only used by `CompositeLayout::Custom`
+    #[serde(default)]
+    pub rect: Option<CompositeRect>,
+}
+
+impl CompositeInput {
+    fn device(&self) -> Result<GstMediaDevice, GStreamerError> {
+        match self.kind {
+            CompositeInputKind::Camera => GstMediaDevice::from_device_path(&self.device_id),
+            CompositeInputKind::Screen => GstMediaDevice::from_screen_id_or_name(&self.device_id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompositePublishOptions {
+    pub width: i32,
+    pub height: i32,
+    pub framerate: i32,
+    pub inputs: Vec<CompositeInput>,
+    pub layout: CompositeLayout,
     pub local_file_save_options: Option<LocalFileSaveOptions>,
+    #[serde(default)]
+    pub frame_format: VideoFrameFormat,
+}
+
+impl CompositePublishOptions {
+    pub fn stream_format(&self) -> VideoStreamFormat {
+        VideoStreamFormat {
+            width: self.width,
+            height: self.height,
+            format: self.frame_format,
+        }
+    }
+
+    /// The inputs of the compositor as if they were published on their own
+    pub fn input_options(&self) -> Vec<PublishOptions> {
+        self.inputs
+            .iter()
+            .map(|input| match input.kind {
+                CompositeInputKind::Camera => PublishOptions::Video(VideoPublishOptions {
+                    codec: input.codec.clone(),
+                    device_id: input.device_id.clone(),
+                    width: input.width,
+                    height: input.height,
+                    framerate: input.framerate,
+                    local_file_save_options: None,
+                    publish_resolution: None,
+                    frame_format: VideoFrameFormat::default(),
+                }),
+                CompositeInputKind::Screen => PublishOptions::Screen(ScreenPublishOptions {
+                    codec: input.codec.clone(),
+                    screen_id_or_name: input.device_id.clone(),
+                    width: input.width,
+                    height: input.height,
+                    framerate: input.framerate,
+                    local_file_save_options: None,
+                    publish_resolution: None,
+                    frame_format: VideoFrameFormat::default(),
+                }),
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -73,12 +334,41 @@ pub enum PublishOptions {
     Video(VideoPublishOptions),
     Audio(AudioPublishOptions),
     Screen(ScreenPublishOptions),
+    Mixer(AudioMixerPublishOptions),
+    Composite(CompositePublishOptions),
+}
+
+impl PublishOptions {
+    pub fn local_file_save_options(&self) -> Option<&LocalFileSaveOptions> {
+        match self {
+            PublishOptions::Video(options) => options.local_file_save_options.as_ref(),
+            PublishOptions::Audio(options) => options.local_file_save_options.as_ref(),
+            PublishOptions::Screen(options) => options.local_file_save_options.as_ref(),
+            PublishOptions::Mixer(options) => options.local_file_save_options.as_ref(),
+            PublishOptions::Composite(options) => options.local_file_save_options.as_ref(),
+        }
+    }
+}
+
+/// A frame of a video stream encoded as a JPEG
+#[derive(Debug, Clone)]
+pub struct VideoSnapshot {
+    pub jpeg: Vec<u8>,
+    pub width: i32,
+    pub height: i32,
 }
 
+/// How long to wait for the frame of a snapshot, and then for its encoding
+const SNAPSHOT_TIMEOUT_SECS: u64 = 2;
+
 #[derive(Debug)]
 pub struct GstMediaStream {
     handle: Option<StreamHandle>,
     publish_options: PublishOptions,
+    /// Shared with the task running the pipeline, and kept across restarts
+    state_tx: Arc<watch::Sender<StreamState>>,
+    /// DOT graph of the pipeline when it last failed, kept across restarts
+    error_graph: Arc<Mutex<Option<String>>>,
 }
 
 pub async fn create_dir(options: &LocalFileSaveOptions) -> Result<PathBuf, GStreamerError> {
@@ -89,7 +379,7 @@ pub async fn create_dir(options: &LocalFileSaveOptions) -> Result<PathBuf, GStre
     Ok(output_dir)
 }
 
-fn strict_sanitize_filename<S: AsRef<str>>(filename: S) -> String {
+pub(crate) fn strict_sanitize_filename<S: AsRef<str>>(filename: S) -> String {
     let s = filename
         .as_ref()
         .chars()
@@ -111,16 +401,63 @@ fn strict_sanitize_filename<S: AsRef<str>>(filename: S) -> String {
     }
 }
 
+async fn voice_activity_task(
+    mut levels_rx: broadcast::Receiver<AudioLevels>,
+    mut state_rx: watch::Receiver<StreamState>,
+    voice_activity_tx: broadcast::Sender<VoiceActivityEvent>,
+    options: VoiceActivityOptions,
+    sidecar: Option<RecordingMetadata>,
+) {
+    let mut detector = VoiceActivityDetector::new(options);
+    let mut events = vec![];
+
+    loop {
+        tokio::select! {
+            _ = stream_ended(&mut state_rx) => {
+                break;
+            }
+            levels = levels_rx.recv() => {
+                match levels {
+                    Ok(levels) => {
+                        if let Some(event) = detector.process(&levels) {
+                            let _ = voice_activity_tx.send(event.clone());
+                            events.push(event);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }
+    }
+
+    if let Some(event) = detector.finish() {
+        let _ = voice_activity_tx.send(event.clone());
+        events.push(event);
+    }
+
+    if let Some(metadata) = sidecar {
+        let _ = write_voice_activity_sidecar(&metadata.parent_dir, &metadata.filename, &events);
+    }
+}
+
 impl GstMediaStream {
     pub fn new(publish_options: PublishOptions) -> Self {
         Self {
             handle: None,
             publish_options,
+            state_tx: Arc::new(watch::channel(StreamState::Idle).0),
+            error_graph: Arc::new(Mutex::new(None)),
         }
     }
 
-    pub fn has_started(&self) -> bool {
-        self.handle.is_some()
+    pub fn state(&self) -> StreamState {
+        self.state_tx.borrow().clone()
+    }
+
+    /// Watches the state of the stream, across restarts
+    pub fn watch_state(&self) -> watch::Receiver<StreamState> {
+        self.state_tx.subscribe()
     }
 
     pub fn kind(&self) -> &str {
@@ -128,23 +465,71 @@ impl GstMediaStream {
             PublishOptions::Video(_) => "Video",
             PublishOptions::Audio(_) => "Audio",
             PublishOptions::Screen(_) => "Screen",
+            PublishOptions::Mixer(_) => "Mixer",
+            PublishOptions::Composite(_) => "Composite",
         }
     }
 
     pub async fn stop(&mut self) -> Result<(), GStreamerError> {
         if let Some(handle) = self.handle.take() {
+            self.state_tx.send_if_modified(|state| {
+                let active = state.is_active();
+                if active {
+                    *state = StreamState::Stopping;
+                }
+                active
+            });
+            // A `Lossless` subscriber that stopped receiving would keep the EOS
+            // from reaching the sinks, the frames it already queued are kept
+            handle.frame_tx.drain();
             handle.pipeline.send_event(gstreamer::event::Eos::new());
             let _ = handle.task.await;
+            // The task did not get to end the stream if it panicked
+            self.state_tx.send_if_modified(|state| {
+                let ended = state.has_ended();
+                if !ended {
+                    *state = StreamState::Stopped;
+                }
+                !ended
+            });
+            // Subscribers receive what is left in their queue, then that it closed
+            handle.frame_tx.close();
+            if let Some(voice_activity_task) = handle.voice_activity_task {
+                // Dropping the sender lets the detector finish even if the pipeline
+                // ended without posting levels
+                drop(handle.levels_tx);
+                let _ = voice_activity_task.await;
+            }
         }
         self.handle = None;
         Ok(())
     }
 
+    /// Builds and plays the pipeline of the stream, restarting it if it was running.
+    /// A stream that failed is `Recovering` rather than `Starting` until it plays.
     pub async fn start(&mut self) -> Result<(), GStreamerError> {
         self.stop().await?;
 
-        let (frame_tx, _) = broadcast::channel::<Arc<Buffer>>(1);
-        let (close_tx, _) = broadcast::channel::<()>(1);
+        let recovering = matches!(*self.state_tx.borrow(), StreamState::Failed(_));
+        self.state_tx.send_replace(if recovering {
+            StreamState::Recovering
+        } else {
+            StreamState::Starting
+        });
+
+        let result = self.start_pipeline().await;
+        if let Err(e) = &result {
+            self.state_tx
+                .send_replace(StreamState::Failed(e.to_string()));
+        }
+        result
+    }
+
+    async fn start_pipeline(&mut self) -> Result<(), GStreamerError> {
+        let frame_tx = Arc::new(StreamFrameSender::new());
+        let (events_tx, _) = broadcast::channel::<StreamEvent>(16);
+        let (levels_tx, _) = broadcast::channel::<AudioLevels>(16);
+        let (voice_activity_tx, _) = broadcast::channel::<VoiceActivityEvent>(16);
 
         let device = match &self.publish_options {
             PublishOptions::Video(video_options) => {
@@ -156,10 +541,25 @@ impl GstMediaStream {
             PublishOptions::Screen(screen_options) => {
                 GstMediaDevice::from_screen_id_or_name(&screen_options.screen_id_or_name)?
             }
+            PublishOptions::Mixer(mixer_options) => {
+                let devices = mixer_options
+                    .inputs
+                    .iter()
+                    .map(|input| GstMediaDevice::from_device_path(&input.device_id))
+                    .collect::<Result<Vec<_>, _>>()?;
+                GstMediaDevice::audio_mixer(&devices)
+            }
+            PublishOptions::Composite(composite_options) => {
+                let devices = composite_options
+                    .inputs
+                    .iter()
+                    .map(|input| input.device())
+                    .collect::<Result<Vec<_>, _>>()?;
+                GstMediaDevice::video_compositor(&devices)
+            }
         };
 
-        let frame_tx_arc = Arc::new(frame_tx.clone());
-        let mut metadata = None;
+        let mut metadata = vec![];
 
         let pipeline = match &self.publish_options {
             PublishOptions::Video(video_options) => {
@@ -174,7 +574,7 @@ impl GstMediaStream {
                         chrono::Local::now().format("%Y-%m-%d-%H-%M-%S")
                     );
 
-                    metadata = Some(RecordingMetadata::new(
+                    metadata.push(RecordingMetadata::new(
                         filename_str.clone(),
                         path::absolute(&op_dir)
                             .unwrap()
@@ -194,7 +594,8 @@ impl GstMediaStream {
                     video_options.width,
                     video_options.height,
                     video_options.framerate,
-                    frame_tx_arc.clone(),
+                    video_options.stream_format(),
+                    frame_tx.clone(),
                     filename,
                 )?
             }
@@ -217,7 +618,7 @@ impl GstMediaStream {
                         chrono::Local::now().format("%Y-%m-%d-%H-%M-%S")
                     );
 
-                    metadata = Some(RecordingMetadata::new(
+                    metadata.push(RecordingMetadata::new(
                         filename_str.clone(),
                         path::absolute(&op_dir)
                             .unwrap()
@@ -238,15 +639,17 @@ impl GstMediaStream {
                         audio_options.channels,
                         selected_channel,
                         audio_options.framerate,
-                        frame_tx_arc.clone(),
+                        frame_tx.clone(),
                         filename,
+                        audio_options.processing.as_ref(),
                     )?,
                     None => device.audio_pipeline(
                         &audio_options.codec,
                         audio_options.channels,
                         audio_options.framerate,
-                        frame_tx_arc.clone(),
+                        frame_tx.clone(),
                         filename,
+                        audio_options.processing.as_ref(),
                     )?,
                 }
             }
@@ -261,7 +664,7 @@ impl GstMediaStream {
                         chrono::Local::now().format("%Y-%m-%d-%H-%M-%S")
                     );
 
-                    metadata = Some(RecordingMetadata::new(
+                    metadata.push(RecordingMetadata::new(
                         filename_str.clone(),
                         path::absolute(&op_dir)
                             .unwrap()
@@ -280,34 +683,478 @@ impl GstMediaStream {
                     screen_options.width,
                     screen_options.height,
                     screen_options.framerate,
-                    frame_tx_arc.clone(),
+                    screen_options.stream_format(),
+                    frame_tx.clone(),
+                    filename,
+                )?
+            }
+            PublishOptions::Mixer(mixer_options) => {
+                let timestamp = chrono::Local::now().format("%Y-%m-%d-%H-%M-%S");
+                let mut filename = None;
+                let mut inputs = vec![];
+                let op_dir = match &mixer_options.local_file_save_options {
+                    Some(local_file_save_options) => {
+                        Some(create_dir(local_file_save_options).await?)
+                    }
+                    None => None,
+                };
+                let parent_dir = op_dir.as_ref().map(|op_dir| {
+                    path::absolute(op_dir)
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                });
+
+                for (idx, input) in mixer_options.inputs.iter().enumerate() {
+                    let input_device = GstMediaDevice::from_device_path(&input.device_id)?;
+                    let mut input_filename = None;
+                    if let (Some(op_dir), Some(parent_dir), true) =
+                        (&op_dir, &parent_dir, mixer_options.record_inputs)
+                    {
+                        let filename_str = format!(
+                            "{}-{}-{}-{}.m4a",
+                            "audio-mix-input",
+                            idx,
+                            match input.selected_channel {
+                                Some(channel) => format!(
+                                    "{}-{}",
+                                    strict_sanitize_filename(&input_device.display_name),
+                                    channel
+                                ),
+                                None => strict_sanitize_filename(&input_device.display_name),
+                            },
+                            timestamp
+                        );
+
+                        metadata.push(RecordingMetadata::new(
+                            filename_str.clone(),
+                            parent_dir.clone(),
+                            "microphone".into(),
+                            "audio".into(),
+                            mixer_options.codec.clone(),
+                            input.selected_channel,
+                            Some(input_device.display_name.clone()),
+                        ));
+
+                        input_filename =
+                            Some(op_dir.join(filename_str).to_string_lossy().to_string());
+                    }
+                    inputs.push((input_device, input.clone(), input_filename));
+                }
+
+                if let (Some(op_dir), Some(parent_dir)) = (&op_dir, &parent_dir) {
+                    let filename_str = format!("{}-{}.m4a", "audio-mix", timestamp);
+
+                    metadata.insert(
+                        0,
+                        RecordingMetadata::new(
+                            filename_str.clone(),
+                            parent_dir.clone(),
+                            "microphone-mix".into(),
+                            "audio".into(),
+                            mixer_options.codec.clone(),
+                            None,
+                            Some(device.display_name.clone()),
+                        ),
+                    );
+                    filename = Some(op_dir.join(filename_str).to_string_lossy().to_string());
+                }
+
+                device.audio_mixer_pipeline(
+                    &mixer_options.codec,
+                    &inputs,
+                    mixer_options.channels,
+                    mixer_options.framerate,
+                    frame_tx.clone(),
                     filename,
                 )?
             }
+            PublishOptions::Composite(composite_options) => {
+                let mut filename = None;
+                if let Some(local_file_save_options) = &composite_options.local_file_save_options {
+                    let op_dir = create_dir(local_file_save_options).await?;
+                    let filename_str = format!(
+                        "{}-{}.mp4",
+                        "composite",
+                        chrono::Local::now().format("%Y-%m-%d-%H-%M-%S")
+                    );
+
+                    metadata.push(RecordingMetadata::new(
+                        filename_str.clone(),
+                        path::absolute(&op_dir)
+                            .unwrap()
+                            .to_string_lossy()
+                            .to_string(),
+                        "composite".into(),
+                        "video".into(),
+                        "video/x-raw".into(),
+                        None,
+                        Some(device.display_name.clone()),
+                    ));
+                    filename = Some(op_dir.join(filename_str).to_string_lossy().to_string());
+                }
+
+                let rects = composite_options.layout.rects(
+                    composite_options.width,
+                    composite_options.height,
+                    &composite_options.inputs,
+                )?;
+                let inputs = composite_options
+                    .inputs
+                    .iter()
+                    .zip(rects)
+                    .map(|(input, rect)| Ok((input.device()?, input.clone(), rect)))
+                    .collect::<Result<Vec<_>, GStreamerError>>()?;
+
+                device.video_compositor_pipeline(
+                    &inputs,
+                    composite_options.stream_format(),
+                    composite_options.framerate,
+                    frame_tx.clone(),
+                    filename,
+                )?
+            }
+        };
+
+        if let Some(local_file_save_options) = self.publish_options.local_file_save_options() {
+            for recording in metadata.iter_mut() {
+                recording.set_duration_tolerance_ns(
+                    local_file_save_options.recording_duration_tolerance_ns,
+                );
+            }
+        }
+
+        let voice_activity_task = match &self.publish_options {
+            PublishOptions::Audio(AudioPublishOptions {
+                voice_activity: Some(options),
+                ..
+            }) => Some(tokio::spawn(voice_activity_task(
+                levels_tx.subscribe(),
+                self.state_tx.subscribe(),
+                voice_activity_tx.clone(),
+                options.clone(),
+                metadata.first().cloned().filter(|_| options.write_sidecar),
+            ))),
+            _ => None,
         };
 
-        let pipline_task = tokio::spawn(run_pipeline(
+        let recording_timings = metadata
+            .iter()
+            .map(|m| {
+                (
+                    m.filename.clone(),
+                    Arc::new(Mutex::new(FileSinkTiming::default())),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let pipline_task = tokio::spawn(run_pipeline_with_timings(
             pipeline.clone(),
-            close_tx.clone(),
+            self.state_tx.clone(),
+            events_tx.clone(),
+            levels_tx.clone(),
             metadata.clone(),
+            recording_timings
+                .iter()
+                .map(|(_, timing)| timing.clone())
+                .collect(),
+            self.error_graph.clone(),
         ));
 
         let handle = StreamHandle {
-            close_tx,
+            events_tx,
             frame_tx,
+            levels_tx,
+            voice_activity_tx,
             task: pipline_task,
+            voice_activity_task,
             pipeline,
             device,
+            recording_timings,
         };
         self.handle = Some(handle);
 
         Ok(())
     }
 
-    pub fn subscribe(&self) -> Option<(broadcast::Receiver<Arc<Buffer>>, broadcast::Receiver<()>)> {
+    /// Subscribe to the frames of a started stream, along with its state to tell
+    /// when it ended, see `stream_ended`
+    pub fn subscribe(
+        &self,
+    ) -> Option<(
+        broadcast::Receiver<Arc<Buffer>>,
+        watch::Receiver<StreamState>,
+    )> {
+        self.handle
+            .as_ref()
+            .map(|h| (h.frame_tx.subscribe(), self.state_tx.subscribe()))
+    }
+
+    /// Subscribe to the frames of a started stream with a `BackpressurePolicy`, along
+    /// with its state to tell when it ended. `subscribe` is `BackpressurePolicy::LatestOnly`.
+    pub fn subscribe_with(
+        &self,
+        policy: BackpressurePolicy,
+    ) -> Option<(FrameQueueReceiver, watch::Receiver<StreamState>)> {
         self.handle
             .as_ref()
-            .map(|h| (h.frame_tx.subscribe(), h.close_tx.subscribe()))
+            .map(|h| (h.frame_tx.subscribe_with(policy), self.state_tx.subscribe()))
+    }
+
+    /// Subscribe to the samples of a started stream with a `BackpressurePolicy`, which
+    /// carry the caps each buffer was negotiated with, along with its state to tell
+    /// when it ended
+    pub fn subscribe_samples(
+        &self,
+        policy: BackpressurePolicy,
+    ) -> Option<(FrameQueueReceiver<Sample>, watch::Receiver<StreamState>)> {
+        self.handle.as_ref().map(|h| {
+            (
+                h.frame_tx.subscribe_samples(policy),
+                self.state_tx.subscribe(),
+            )
+        })
+    }
+
+    /// How the subscribers with a `BackpressurePolicy` keep up with the running stream
+    pub fn subscriber_metrics(&self) -> Vec<SubscriberMetrics> {
+        self.handle
+            .as_ref()
+            .map(|h| h.frame_tx.metrics())
+            .unwrap_or_default()
+    }
+
+    /// Subscribe to the frames of a started video, screen or composite stream,
+    /// described by the caps they were negotiated with
+    pub fn subscribe_video_frames(&self, policy: BackpressurePolicy) -> Option<VideoFrameReceiver> {
+        match self.publish_options {
+            PublishOptions::Video(_) | PublishOptions::Screen(_) | PublishOptions::Composite(_) => {
+                let (frames_rx, state_rx) = self.subscribe_samples(policy)?;
+                Some(VideoFrameReceiver::new(frames_rx, state_rx, self.clock()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Subscribe to the sample buffers of a started audio or mixer stream,
+    /// described by the caps they were negotiated with
+    pub fn subscribe_audio_frames(&self, policy: BackpressurePolicy) -> Option<AudioFrameReceiver> {
+        match self.publish_options {
+            PublishOptions::Audio(_) | PublishOptions::Mixer(_) => {
+                let (frames_rx, state_rx) = self.subscribe_samples(policy)?;
+                Some(AudioFrameReceiver::new(frames_rx, state_rx, self.clock()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Subscribe to the warnings, errors, EOS and latency changes posted by the
+    /// pipeline of a started stream
+    pub fn subscribe_events(&self) -> Option<broadcast::Receiver<StreamEvent>> {
+        self.handle.as_ref().map(|h| h.events_tx.subscribe())
+    }
+
+    /// Subscribe to per-channel RMS/peak levels of an audio stream. Levels are
+    /// only produced for `PublishOptions::Audio` and `PublishOptions::Mixer` streams.
+    pub fn subscribe_levels(&self) -> Option<broadcast::Receiver<AudioLevels>> {
+        match self.publish_options {
+            PublishOptions::Audio(_) | PublishOptions::Mixer(_) => {
+                self.handle.as_ref().map(|h| h.levels_tx.subscribe())
+            }
+            _ => None,
+        }
+    }
+
+    /// Subscribe to speech-start/speech-end events of an audio stream that was
+    /// configured with `voice_activity` options.
+    pub fn subscribe_voice_activity(&self) -> Option<broadcast::Receiver<VoiceActivityEvent>> {
+        match self.publish_options {
+            PublishOptions::Audio(AudioPublishOptions {
+                voice_activity: Some(_),
+                ..
+            }) => self
+                .handle
+                .as_ref()
+                .map(|h| h.voice_activity_tx.subscribe()),
+            _ => None,
+        }
+    }
+
+    /// Maps buffer timestamps of the running stream to the wall clock and to
+    /// offsets into its local recordings
+    pub fn clock(&self) -> Option<StreamClock> {
+        self.handle
+            .as_ref()
+            .map(|h| StreamClock::new(h.pipeline.clone(), h.recording_timings.clone()))
+    }
+
+    /// The options the stream was created with, whether it is running or not
+    pub fn options(&self) -> &PublishOptions {
+        &self.publish_options
+    }
+
+    /// Switches the stream to the supported mode closest to its configured codec,
+    /// resolution, framerate and channels, so that it still starts on a device
+    /// lacking that exact mode. Takes effect on the next start.
+    pub fn negotiate(&mut self) -> Result<NegotiatedMode, GStreamerError> {
+        match &self.publish_options {
+            PublishOptions::Video(details) => {
+                let constraints = VideoConstraints {
+                    width: ConstrainRange::ideal(details.width),
+                    height: ConstrainRange::ideal(details.height),
+                    framerate: ConstrainRange::ideal(details.framerate),
+                    codecs: vec![details.codec.clone()],
+                };
+                self.negotiate_video(&constraints)
+                    .map(NegotiatedMode::Video)
+            }
+            PublishOptions::Screen(details) => {
+                let constraints = VideoConstraints {
+                    width: ConstrainRange::ideal(details.width),
+                    height: ConstrainRange::ideal(details.height),
+                    framerate: ConstrainRange::ideal(details.framerate),
+                    codecs: vec![details.codec.clone()],
+                };
+                self.negotiate_video(&constraints)
+                    .map(NegotiatedMode::Video)
+            }
+            PublishOptions::Audio(details) => {
+                let constraints = AudioConstraints {
+                    channels: ConstrainRange {
+                        // The selected channel has to exist on the device
+                        min: details.selected_channel,
+                        ideal: Some(details.channels),
+                        max: None,
+                    },
+                    framerate: ConstrainRange::ideal(details.framerate),
+                    codecs: vec![details.codec.clone()],
+                };
+                self.negotiate_audio(&constraints)
+                    .map(NegotiatedMode::Audio)
+            }
+            PublishOptions::Mixer(_) | PublishOptions::Composite(_) => {
+                Err(GStreamerError::PipelineError(
+                    "Mixed and composited streams cannot be negotiated".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Switches a camera or screen stream to its supported mode closest to the
+    /// constraints. Takes effect on the next start.
+    pub fn negotiate_video(
+        &mut self,
+        constraints: &VideoConstraints,
+    ) -> Result<NegotiatedVideoMode, GStreamerError> {
+        match &mut self.publish_options {
+            PublishOptions::Video(details) => {
+                let device = GstMediaDevice::from_device_path(&details.device_id)?;
+                let mode = device.negotiate_video(constraints)?;
+                details.codec = mode.codec.clone();
+                details.width = mode.width;
+                details.height = mode.height;
+                details.framerate = mode.framerate;
+                Ok(mode)
+            }
+            PublishOptions::Screen(details) => {
+                let device = GstMediaDevice::from_screen_id_or_name(&details.screen_id_or_name)?;
+                let mode = device.negotiate_video(constraints)?;
+                details.codec = mode.codec.clone();
+                details.width = mode.width;
+                details.height = mode.height;
+                details.framerate = mode.framerate;
+                Ok(mode)
+            }
+            _ => Err(GStreamerError::PipelineError(
+                "Only camera and screen streams have video modes".to_string(),
+            )),
+        }
+    }
+
+    /// Switches a microphone stream to its supported mode closest to the
+    /// constraints. Takes effect on the next start.
+    pub fn negotiate_audio(
+        &mut self,
+        constraints: &AudioConstraints,
+    ) -> Result<NegotiatedAudioMode, GStreamerError> {
+        match &mut self.publish_options {
+            PublishOptions::Audio(details) => {
+                let device = GstMediaDevice::from_device_path(&details.device_id)?;
+                let mode = device.negotiate_audio(constraints)?;
+                details.codec = mode.codec.clone();
+                details.channels = mode.channels;
+                details.framerate = mode.framerate;
+                Ok(mode)
+            }
+            _ => Err(GStreamerError::PipelineError(
+                "Only microphone streams have audio modes".to_string(),
+            )),
+        }
+    }
+
+    /// Encodes the next frame of a running video stream as a JPEG, scaled down to
+    /// fit in `max_size`x`max_size`
+    pub async fn snapshot_jpeg(&self, max_size: i32) -> Result<VideoSnapshot, GStreamerError> {
+        if matches!(
+            self.publish_options,
+            PublishOptions::Audio(_) | PublishOptions::Mixer(_)
+        ) {
+            return Err(GStreamerError::PipelineError(
+                "Snapshots need a video stream".to_string(),
+            ));
+        }
+        let mut frames_rx = self
+            .subscribe_video_frames(BackpressurePolicy::LatestOnly)
+            .ok_or_else(|| GStreamerError::PipelineError("Stream has not started".to_string()))?;
+
+        let timeout = std::time::Duration::from_secs(SNAPSHOT_TIMEOUT_SECS);
+        let frame = tokio::time::timeout(timeout, frames_rx.recv())
+            .await
+            .map_err(|_| GStreamerError::PipelineError("No frame for the snapshot".to_string()))?
+            .map_err(|e| GStreamerError::PipelineError(e.to_string()))?;
+
+        let sample = gstreamer::Sample::builder()
+            .buffer(&frame.buffer)
+            .caps(&frame.caps)
+            .build();
+
+        let (width, height) = published_size(
+            frame.width() as i32,
+            frame.height() as i32,
+            Some(PublishResolution {
+                width: max_size,
+                height: max_size,
+            }),
+        );
+        let jpeg_caps = gstreamer::Caps::builder("image/jpeg")
+            .field("width", width)
+            .field("height", height)
+            .build();
+
+        // Converting builds and runs a pipeline synchronously
+        let jpeg = tokio::task::spawn_blocking(move || {
+            let converted = gstreamer_video::convert_sample(
+                &sample,
+                &jpeg_caps,
+                gstreamer::ClockTime::from_seconds(SNAPSHOT_TIMEOUT_SECS),
+            )
+            .map_err(|e| GStreamerError::PipelineError(format!("Failed to encode JPEG: {}", e)))?;
+            let buffer = converted.buffer().ok_or_else(|| {
+                GStreamerError::PipelineError("Encoded snapshot has no buffer".to_string())
+            })?;
+            let map = buffer
+                .map_readable()
+                .map_err(|e| GStreamerError::PipelineError(e.to_string()))?;
+            Ok::<_, GStreamerError>(map.as_slice().to_vec())
+        })
+        .await
+        .map_err(|e| GStreamerError::PipelineError(e.to_string()))??;
+
+        Ok(VideoSnapshot {
+            jpeg,
+            width,
+            height,
+        })
     }
 
     pub fn details(&self) -> Option<PublishOptions> {
@@ -317,6 +1164,70 @@ impl GstMediaStream {
     pub fn get_device_name(&self) -> Option<String> {
         self.handle.as_ref().map(|h| h.device.display_name.clone())
     }
+
+    /// The device the running stream captures from
+    pub fn device(&self) -> Option<GstMediaDevice> {
+        self.handle.as_ref().map(|h| h.device.clone())
+    }
+
+    /// Names of the files the running stream records to
+    pub fn recording_filenames(&self) -> Vec<String> {
+        self.handle
+            .as_ref()
+            .map(|h| {
+                h.recording_timings
+                    .iter()
+                    .map(|(filename, _)| filename.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// DOT graph of the running pipeline, see `pipeline_dot`
+    pub fn pipeline_dot(&self) -> Option<String> {
+        self.handle.as_ref().map(|h| pipeline_dot(&h.pipeline))
+    }
+
+    /// Elements, negotiated caps and queue levels of the running pipeline
+    pub fn inspect(&self) -> Option<PipelineInfo> {
+        self.handle.as_ref().map(|h| inspect_pipeline(&h.pipeline))
+    }
+
+    /// DOT graph of the pipeline as it was when it last failed, with the caps it
+    /// had negotiated. The recordings of the failed pipeline get it as
+    /// `<file>.error.dot` as well.
+    pub fn error_graph(&self) -> Option<String> {
+        self.error_graph.lock().unwrap().clone()
+    }
+
+    /// Writes a support bundle of the stream to `output_dir`: the graph and the
+    /// introspection of the running pipeline, and the graph of the last failure
+    pub fn write_debug_dump(&self, output_dir: &str) -> Result<Vec<PathBuf>, GStreamerError> {
+        let name = format!(
+            "{}-{}-{}",
+            self.kind().to_lowercase(),
+            strict_sanitize_filename(self.get_device_name().unwrap_or_default()),
+            chrono::Local::now().format("%Y-%m-%d-%H-%M-%S")
+        );
+
+        let mut paths = match &self.handle {
+            Some(handle) => write_pipeline_dump(&handle.pipeline, output_dir, &name)?,
+            None => vec![],
+        };
+
+        if let Some(graph) = self.error_graph() {
+            std::fs::create_dir_all(output_dir).map_err(|e| {
+                GStreamerError::PipelineError(format!("Failed to create dump directory: {}", e))
+            })?;
+            let path = PathBuf::from(output_dir).join(format!("{}.error.dot", name));
+            std::fs::write(&path, graph).map_err(|e| {
+                GStreamerError::PipelineError(format!("Failed to write error graph: {}", e))
+            })?;
+            paths.push(path);
+        }
+
+        Ok(paths)
+    }
 }
 
 impl Drop for GstMediaStream {
@@ -329,3 +1240,94 @@ impl Drop for GstMediaStream {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::broadcast::error::RecvError;
+
+    /// A started video stream of test frames, without a capture device
+    fn test_stream() -> GstMediaStream {
+        gstreamer::init().unwrap();
+        let mut stream = GstMediaStream::new(PublishOptions::Video(VideoPublishOptions {
+            codec: "video/x-raw".to_string(),
+            device_id: "test".to_string(),
+            width: 320,
+            height: 240,
+            framerate: 30,
+            local_file_save_options: None,
+            publish_resolution: None,
+            frame_format: VideoFrameFormat::default(),
+        }));
+        let device = GstMediaDevice {
+            display_name: "Test".to_string(),
+            device_class: "Video/Source".to_string(),
+            device_path: "test".to_string(),
+        };
+
+        let frame_tx = Arc::new(StreamFrameSender::new());
+        let (events_tx, _) = broadcast::channel::<StreamEvent>(16);
+        let (levels_tx, _) = broadcast::channel::<AudioLevels>(16);
+        let (voice_activity_tx, _) = broadcast::channel::<VoiceActivityEvent>(16);
+
+        let pipeline = Pipeline::new();
+        let source = gstreamer::ElementFactory::make("videotestsrc")
+            .property("is-live", true)
+            .build()
+            .unwrap();
+        let appsink = device.broadcast_appsink(frame_tx.clone(), None).unwrap();
+        pipeline.add_many([&source, appsink.upcast_ref()]).unwrap();
+        source.link(&appsink).unwrap();
+
+        let task = tokio::spawn(run_pipeline_with_timings(
+            pipeline.clone(),
+            stream.state_tx.clone(),
+            events_tx.clone(),
+            levels_tx.clone(),
+            vec![],
+            vec![],
+            stream.error_graph.clone(),
+        ));
+        stream.handle = Some(StreamHandle {
+            events_tx,
+            frame_tx,
+            levels_tx,
+            voice_activity_tx,
+            task,
+            voice_activity_task: None,
+            pipeline,
+            device,
+            recording_timings: vec![],
+        });
+        stream
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_stop_with_full_lossless_subscriber() {
+        let mut stream = test_stream();
+        let (mut frames_rx, _) = stream
+            .subscribe_with(BackpressurePolicy::Lossless { capacity: 2 })
+            .unwrap();
+
+        // The subscriber does not receive until the stream stopped
+        while frames_rx.metrics().queued < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        tokio::time::timeout(Duration::from_secs(5), stream.stop())
+            .await
+            .expect("stop waited for the full subscriber")
+            .unwrap();
+
+        let mut received = 0;
+        loop {
+            match frames_rx.recv().await {
+                Ok(_) => received += 1,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+        assert_eq!(received, 2);
+        assert_eq!(stream.state(), StreamState::Stopped);
+    }
+}
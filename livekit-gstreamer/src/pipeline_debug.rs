@@ -0,0 +1,183 @@
+use crate::media_device::GStreamerError;
+use gstreamer::{prelude::*, DebugGraphDetails, Pipeline};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// A pad of an element, with the caps negotiated on it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PadInfo {
+    pub name: String,
+    /// `src` or `sink`
+    pub direction: String,
+    /// `None` until the pad negotiated, or once the pipeline is torn down
+    pub caps: Option<String>,
+    /// Path of the pad this one is linked to
+    pub peer: Option<String>,
+}
+
+/// How full a `queue` or `queue2` element is
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueLevel {
+    pub buffers: u32,
+    pub bytes: u32,
+    pub time_ns: u64,
+    /// The limits of the queue, 0 disables a limit
+    pub max_buffers: u32,
+    pub max_bytes: u32,
+    pub max_time_ns: u64,
+}
+
+impl QueueLevel {
+    /// Fill level relative to the closest limit, from 0 to 1. A queue close to 1
+    /// blocks upstream, or drops buffers when it is leaky.
+    pub fn fill_ratio(&self) -> f64 {
+        [
+            (self.buffers as f64, self.max_buffers as f64),
+            (self.bytes as f64, self.max_bytes as f64),
+            (self.time_ns as f64, self.max_time_ns as f64),
+        ]
+        .iter()
+        .filter(|(_, max)| *max > 0.0)
+        .map(|(level, max)| (level / max).min(1.0))
+        .fold(0.0, f64::max)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ElementInfo {
+    pub name: String,
+    /// Path of the element in the pipeline, e.g. `/GstPipeline:pipeline0/GstQueue:queue1`
+    pub path: String,
+    /// The factory the element was made with, e.g. `v4l2src`
+    pub factory: Option<String>,
+    pub state: String,
+    pub pads: Vec<PadInfo>,
+    pub queue: Option<QueueLevel>,
+}
+
+/// What a pipeline was built from and how it is doing, for support reports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PipelineInfo {
+    pub name: String,
+    pub state: String,
+    /// All the elements of the pipeline, including those nested in bins
+    pub elements: Vec<ElementInfo>,
+}
+
+impl PipelineInfo {
+    /// The queues of the pipeline, fullest first
+    pub fn queues(&self) -> Vec<(&ElementInfo, &QueueLevel)> {
+        let mut queues = self
+            .elements
+            .iter()
+            .filter_map(|element| element.queue.as_ref().map(|queue| (element, queue)))
+            .collect::<Vec<_>>();
+        queues.sort_by(|(_, a), (_, b)| b.fill_ratio().total_cmp(&a.fill_ratio()));
+        queues
+    }
+}
+
+/// The pipeline as a graphviz DOT graph, with the caps and properties of its
+/// elements. Render it with e.g. `dot -Tsvg pipeline.dot > pipeline.svg`.
+pub fn pipeline_dot(pipeline: &Pipeline) -> String {
+    gstreamer::debug_bin_to_dot_data(pipeline, DebugGraphDetails::all()).to_string()
+}
+
+fn queue_level(element: &gstreamer::Element) -> Option<QueueLevel> {
+    // Only `queue` and `queue2` expose their fill level
+    element.find_property("current-level-buffers")?;
+
+    Some(QueueLevel {
+        buffers: element.property::<u32>("current-level-buffers"),
+        bytes: element.property::<u32>("current-level-bytes"),
+        time_ns: element.property::<u64>("current-level-time"),
+        max_buffers: element.property::<u32>("max-size-buffers"),
+        max_bytes: element.property::<u32>("max-size-bytes"),
+        max_time_ns: element.property::<u64>("max-size-time"),
+    })
+}
+
+fn pad_info(pad: &gstreamer::Pad) -> PadInfo {
+    PadInfo {
+        name: pad.name().to_string(),
+        direction: format!("{:?}", pad.direction()).to_lowercase(),
+        caps: pad.current_caps().map(|caps| caps.to_string()),
+        peer: pad.peer().map(|peer| peer.path_string().to_string()),
+    }
+}
+
+/// Lists the elements of the pipeline with their negotiated caps and queue levels
+pub fn inspect_pipeline(pipeline: &Pipeline) -> PipelineInfo {
+    let elements = pipeline
+        .iterate_recurse()
+        .into_iter()
+        .filter_map(Result::ok)
+        .map(|element| ElementInfo {
+            name: element.name().to_string(),
+            path: element.path_string().to_string(),
+            factory: element.factory().map(|factory| factory.name().to_string()),
+            state: format!("{:?}", element.current_state()),
+            pads: element.pads().iter().map(pad_info).collect(),
+            queue: queue_level(&element),
+        })
+        .collect();
+
+    PipelineInfo {
+        name: pipeline.name().to_string(),
+        state: format!("{:?}", pipeline.current_state()),
+        elements,
+    }
+}
+
+/// Writes the DOT graph and the introspection of the pipeline to `output_dir`
+/// as `<name>.dot` and `<name>.json`, returning the paths written
+pub fn write_pipeline_dump(
+    pipeline: &Pipeline,
+    output_dir: &str,
+    name: &str,
+) -> Result<Vec<PathBuf>, GStreamerError> {
+    let output_dir = PathBuf::from(output_dir);
+    std::fs::create_dir_all(&output_dir).map_err(|e| {
+        GStreamerError::PipelineError(format!("Failed to create dump directory: {}", e))
+    })?;
+
+    let info = serde_json::to_string_pretty(&inspect_pipeline(pipeline)).map_err(|e| {
+        GStreamerError::PipelineError(format!("Failed to serialize pipeline info: {}", e))
+    })?;
+
+    let dot_path = output_dir.join(format!("{}.dot", name));
+    let info_path = output_dir.join(format!("{}.json", name));
+    for (path, content) in [(&dot_path, pipeline_dot(pipeline)), (&info_path, info)] {
+        std::fs::write(path, content).map_err(|e| {
+            GStreamerError::PipelineError(format!("Failed to write pipeline dump: {}", e))
+        })?;
+    }
+
+    Ok(vec![dot_path, info_path])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_fill_ratio() {
+        let level = QueueLevel {
+            buffers: 150,
+            bytes: 1024,
+            time_ns: 500_000_000,
+            max_buffers: 200,
+            max_bytes: 0,
+            max_time_ns: 1_000_000_000,
+        };
+        // The buffer limit is the closest, the disabled byte limit is ignored
+        assert_eq!(level.fill_ratio(), 0.75);
+
+        // A queue without limits never fills up
+        assert_eq!(QueueLevel::default().fill_ratio(), 0.0);
+    }
+}
@@ -0,0 +1,442 @@
+use crate::devices::get_devices_info;
+use crate::lk_participant::{LKParticipant, LKParticipantError};
+use crate::media_stream::{GstMediaStream, PublishOptions};
+use base64::Engine;
+use livekit::participant::{RpcError, RpcInvocationData};
+use livekit::Room;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+
+/// Prefix of the RPC methods registered for remote control, followed by the
+/// camelCase name of a `LKRemoteCommand`
+pub const REMOTE_CONTROL_METHOD_PREFIX: &str = "syncflow.";
+
+pub const REMOTE_CONTROL_METHODS: [&str; 8] = [
+    "listDevices",
+    "listTracks",
+    "muteTrack",
+    "unmuteTrack",
+    "startTrack",
+    "stopTrack",
+    "snapshot",
+    "setResolution",
+];
+
+/// Error codes sent back to the caller, outside of the range LiveKit reserves
+pub const RPC_INVALID_REQUEST: u32 = 400;
+pub const RPC_NOT_AUTHORIZED: u32 = 403;
+pub const RPC_COMMAND_FAILED: u32 = 500;
+
+/// Largest snapshot, in pixels on either side, that fits in an RPC response
+const DEFAULT_SNAPSHOT_SIZE: i32 = 240;
+
+/// LiveKit caps RPC responses at 15 KiB
+const MAX_RPC_RESPONSE_BYTES: usize = 15360;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LKRemoteControlOptions {
+    /// Identities of the participants allowed to call the methods, nobody when empty
+    pub allowed_identities: Vec<String>,
+}
+
+/// A command of a remote participant, the payload of the RPC call holds its fields
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    tag = "method",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum LKRemoteCommand {
+    ListDevices,
+    ListTracks,
+    MuteTrack {
+        track_sid: String,
+    },
+    UnmuteTrack {
+        track_sid: String,
+    },
+    /// Restarts the stream of the track, if it was stopped
+    StartTrack {
+        track_sid: String,
+    },
+    /// Stops the stream of the track, finishing its recording. The track stays
+    /// published without frames.
+    StopTrack {
+        track_sid: String,
+    },
+    /// A base64 JPEG of the next frame, see `LKSnapshot`
+    Snapshot {
+        track_sid: String,
+        max_size: Option<i32>,
+    },
+    /// Restarts the stream of a video track capturing at another resolution
+    SetResolution {
+        track_sid: String,
+        width: i32,
+        height: i32,
+        framerate: Option<i32>,
+    },
+}
+
+impl LKRemoteCommand {
+    /// Parses the payload of a call to one of the `REMOTE_CONTROL_METHODS`
+    pub fn parse(method: &str, payload: &str) -> Result<Self, String> {
+        let name = method
+            .strip_prefix(REMOTE_CONTROL_METHOD_PREFIX)
+            .ok_or_else(|| format!("Unknown method {}", method))?;
+        let mut value = match payload.trim() {
+            "" => serde_json::Value::Object(Default::default()),
+            payload => serde_json::from_str(payload).map_err(|e| e.to_string())?,
+        };
+        let object = value
+            .as_object_mut()
+            .ok_or_else(|| "Payload must be a JSON object".to_string())?;
+        object.insert("method".to_string(), name.into());
+        serde_json::from_value(value).map_err(|e| e.to_string())
+    }
+
+    pub fn track_sid(&self) -> Option<&str> {
+        match self {
+            LKRemoteCommand::ListDevices | LKRemoteCommand::ListTracks => None,
+            LKRemoteCommand::MuteTrack { track_sid }
+            | LKRemoteCommand::UnmuteTrack { track_sid }
+            | LKRemoteCommand::StartTrack { track_sid }
+            | LKRemoteCommand::StopTrack { track_sid }
+            | LKRemoteCommand::Snapshot { track_sid, .. }
+            | LKRemoteCommand::SetResolution { track_sid, .. } => Some(track_sid),
+        }
+    }
+}
+
+/// A device of the publishing machine, without its capabilities to keep the
+/// response small
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LKRemoteDevice {
+    pub device_path: String,
+    pub display_name: String,
+    pub device_class: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LKSnapshot {
+    pub width: i32,
+    pub height: i32,
+    /// Base64 encoded JPEG
+    pub jpeg: String,
+}
+
+/// A remote control call waiting to be run on the participant
+#[derive(Debug)]
+pub struct LKRemoteRequest {
+    pub caller_identity: String,
+    pub command: LKRemoteCommand,
+    response_tx: oneshot::Sender<Result<String, String>>,
+}
+
+impl LKRemoteRequest {
+    /// Sends the JSON response, or the error, back to the caller
+    pub fn respond(self, result: Result<String, LKParticipantError>) {
+        let _ = self.response_tx.send(result.map_err(|e| e.to_string()));
+    }
+}
+
+/// Registers the remote control RPC methods on the local participant of the room.
+/// Calls of authorized participants are handed over through `requests_tx`, to be
+/// run with `LKParticipant::handle_remote_request` by the owner of the participant.
+pub fn register_remote_control(
+    room: &Room,
+    options: LKRemoteControlOptions,
+    requests_tx: mpsc::Sender<LKRemoteRequest>,
+) {
+    let options = Arc::new(options);
+    for name in REMOTE_CONTROL_METHODS {
+        let method = format!("{}{}", REMOTE_CONTROL_METHOD_PREFIX, name);
+        let options = options.clone();
+        let requests_tx = requests_tx.clone();
+        let handler_method = method.clone();
+        room.local_participant()
+            .register_rpc_method(method, move |data: RpcInvocationData| {
+                let options = options.clone();
+                let requests_tx = requests_tx.clone();
+                let method = handler_method.clone();
+                Box::pin(async move {
+                    let caller_identity = data.caller_identity.to_string();
+                    if !options.allowed_identities.contains(&caller_identity) {
+                        return Err(RpcError::new(
+                            RPC_NOT_AUTHORIZED,
+                            format!(
+                                "{} is not allowed to control this participant",
+                                caller_identity
+                            ),
+                            None,
+                        ));
+                    }
+                    let command = LKRemoteCommand::parse(&method, &data.payload)
+                        .map_err(|e| RpcError::new(RPC_INVALID_REQUEST, e, None))?;
+
+                    let (response_tx, response_rx) = oneshot::channel();
+                    let request = LKRemoteRequest {
+                        caller_identity,
+                        command,
+                        response_tx,
+                    };
+                    requests_tx.send(request).await.map_err(|_| {
+                        RpcError::new(
+                            RPC_COMMAND_FAILED,
+                            "Remote control is no longer handled".to_string(),
+                            None,
+                        )
+                    })?;
+                    response_rx
+                        .await
+                        .map_err(|_| {
+                            RpcError::new(
+                                RPC_COMMAND_FAILED,
+                                "Request was dropped".to_string(),
+                                None,
+                            )
+                        })?
+                        .map_err(|e| RpcError::new(RPC_COMMAND_FAILED, e, None))
+                })
+            });
+    }
+}
+
+pub fn unregister_remote_control(room: &Room) {
+    for name in REMOTE_CONTROL_METHODS {
+        room.local_participant()
+            .unregister_rpc_method(format!("{}{}", REMOTE_CONTROL_METHOD_PREFIX, name));
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String, LKParticipantError> {
+    let json = serde_json::to_string(value)
+        .map_err(|e| LKParticipantError::StreamingError(e.to_string()))?;
+    if json.len() > MAX_RPC_RESPONSE_BYTES {
+        return Err(LKParticipantError::StreamingError(format!(
+            "Response of {} bytes is too large",
+            json.len()
+        )));
+    }
+    Ok(json)
+}
+
+fn owned_stream(
+    stream: Option<&mut GstMediaStream>,
+) -> Result<&mut GstMediaStream, LKParticipantError> {
+    stream.ok_or_else(|| {
+        LKParticipantError::StreamingError(
+            "The stream of the track is not owned by the participant".to_string(),
+        )
+    })
+}
+
+/// The options of a video stream capturing at another resolution
+fn with_resolution(
+    options: &PublishOptions,
+    width: i32,
+    height: i32,
+    framerate: Option<i32>,
+) -> Result<PublishOptions, LKParticipantError> {
+    let mut options = options.clone();
+    match &mut options {
+        PublishOptions::Video(details) => {
+            details.width = width;
+            details.height = height;
+            details.framerate = framerate.unwrap_or(details.framerate);
+        }
+        PublishOptions::Screen(details) => {
+            details.width = width;
+            details.height = height;
+            details.framerate = framerate.unwrap_or(details.framerate);
+        }
+        PublishOptions::Composite(details) => {
+            details.width = width;
+            details.height = height;
+            details.framerate = framerate.unwrap_or(details.framerate);
+        }
+        PublishOptions::Audio(_) | PublishOptions::Mixer(_) => {
+            return Err(LKParticipantError::StreamingError(
+                "Cannot change the resolution of an audio stream".to_string(),
+            ))
+        }
+    }
+    Ok(options)
+}
+
+impl LKParticipant {
+    /// Lets the `allowed_identities` control the participant over RPC. The requests
+    /// have to be run with `handle_remote_request`, by the owner of the participant.
+    pub fn enable_remote_control(
+        &self,
+        options: LKRemoteControlOptions,
+    ) -> mpsc::Receiver<LKRemoteRequest> {
+        let (requests_tx, requests_rx) = mpsc::channel(8);
+        register_remote_control(&self.room(), options, requests_tx);
+        requests_rx
+    }
+
+    pub fn disable_remote_control(&self) {
+        unregister_remote_control(&self.room());
+    }
+
+    /// Runs a remote control request on the participant and responds to the caller
+    pub async fn handle_remote_request(&mut self, request: LKRemoteRequest) {
+        let track_sid = request.command.track_sid().map(str::to_string);
+        let mut stream = track_sid
+            .as_deref()
+            .and_then(|track_sid| self.take_owned_stream(track_sid));
+
+        let result = self
+            .run_remote_command(&request.command, stream.as_mut())
+            .await;
+
+        if let (Some(track_sid), Some(stream)) = (track_sid, stream) {
+            self.restore_owned_stream(&track_sid, stream);
+        }
+        request.respond(result);
+    }
+
+    /// Runs a remote control command and returns its JSON response. `stream` is the
+    /// stream of the track the command targets, required to start, stop, snapshot
+    /// or reconfigure it, and replaced when its resolution changes.
+    pub async fn run_remote_command(
+        &mut self,
+        command: &LKRemoteCommand,
+        stream: Option<&mut GstMediaStream>,
+    ) -> Result<String, LKParticipantError> {
+        let track = match command.track_sid() {
+            Some(track_sid) => Some(
+                self.tracks()
+                    .into_iter()
+                    .find(|track| track.sid == track_sid)
+                    .ok_or_else(|| {
+                        LKParticipantError::StreamingError(format!(
+                            "No published track {}",
+                            track_sid
+                        ))
+                    })?,
+            ),
+            None => None,
+        };
+        let track_info = |participant: &Self, track_sid: &str| {
+            participant
+                .tracks()
+                .into_iter()
+                .find(|track| track.sid == track_sid)
+        };
+
+        match command {
+            LKRemoteCommand::ListDevices => to_json(
+                &get_devices_info()
+                    .into_iter()
+                    .map(|device| LKRemoteDevice {
+                        device_path: device.device_path,
+                        display_name: device.display_name,
+                        device_class: device.device_class,
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            LKRemoteCommand::ListTracks => to_json(&self.tracks()),
+            LKRemoteCommand::MuteTrack { track_sid } => {
+                self.mute_track(track_sid)?;
+                to_json(&track_info(self, track_sid))
+            }
+            LKRemoteCommand::UnmuteTrack { track_sid } => {
+                self.unmute_track(track_sid)?;
+                to_json(&track_info(self, track_sid))
+            }
+            LKRemoteCommand::StartTrack { track_sid } => {
+                let stream = owned_stream(stream)?;
+                if !stream.state().is_active() {
+                    stream.start().await?;
+                    self.replace_stream(track_sid, stream).await?;
+                }
+                to_json(&track_info(self, track_sid))
+            }
+            LKRemoteCommand::StopTrack { track_sid } => {
+                owned_stream(stream)?.stop().await?;
+                to_json(&track_info(self, track_sid))
+            }
+            LKRemoteCommand::Snapshot {
+                track_sid: _,
+                max_size,
+            } => {
+                let snapshot = owned_stream(stream)?
+                    .snapshot_jpeg(max_size.unwrap_or(DEFAULT_SNAPSHOT_SIZE))
+                    .await?;
+                to_json(&LKSnapshot {
+                    width: snapshot.width,
+                    height: snapshot.height,
+                    jpeg: base64::engine::general_purpose::STANDARD.encode(&snapshot.jpeg),
+                })
+            }
+            LKRemoteCommand::SetResolution {
+                track_sid,
+                width,
+                height,
+                framerate,
+            } => {
+                let stream = owned_stream(stream)?;
+                // This unwrap is safe because track commands have a track
+                let options =
+                    with_resolution(&track.unwrap().options, *width, *height, *framerate)?;
+                // Capture devices can only be opened by one pipeline at a time, so the
+                // previous stream is stopped first. The track stays published meanwhile.
+                stream.stop().await?;
+                let mut new_stream = GstMediaStream::new(options);
+                if let Err(e) = self.replace_stream(track_sid, &mut new_stream).await {
+                    let _ = new_stream.stop().await;
+                    // Back to the previous resolution rather than a track without frames
+                    if let Err(restart_error) = self.replace_stream(track_sid, stream).await {
+                        eprintln!("Failed to restart the previous stream: {}", restart_error);
+                    }
+                    return Err(e);
+                }
+                *stream = new_stream;
+                to_json(&track_info(self, track_sid))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            LKRemoteCommand::parse("syncflow.listTracks", ""),
+            Ok(LKRemoteCommand::ListTracks)
+        );
+        assert_eq!(
+            LKRemoteCommand::parse(
+                "syncflow.setResolution",
+                r#"{"trackSid": "TR_abc", "width": 1280, "height": 720}"#
+            ),
+            Ok(LKRemoteCommand::SetResolution {
+                track_sid: "TR_abc".to_string(),
+                width: 1280,
+                height: 720,
+                framerate: None,
+            })
+        );
+        assert!(LKRemoteCommand::parse("syncflow.muteTrack", "{}").is_err());
+        assert!(LKRemoteCommand::parse("syncflow.reboot", "{}").is_err());
+        assert!(LKRemoteCommand::parse("other.listTracks", "").is_err());
+    }
+
+    #[test]
+    fn test_every_method_parses() {
+        for name in REMOTE_CONTROL_METHODS {
+            let payload = r#"{"trackSid": "TR_abc", "width": 640, "height": 480}"#;
+            let method = format!("{}{}", REMOTE_CONTROL_METHOD_PREFIX, name);
+            assert!(LKRemoteCommand::parse(&method, payload).is_ok(), "{}", name);
+        }
+    }
+}
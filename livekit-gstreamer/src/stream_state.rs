@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+/// Lifecycle of a `GstMediaStream`, observed with `GstMediaStream::watch_state`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", content = "reason")]
+pub enum StreamState {
+    /// Never started
+    #[default]
+    Idle,
+    /// The pipeline was built and is prerolling
+    Starting,
+    Playing,
+    /// Restarting after the pipeline failed
+    Recovering,
+    /// Stop was requested, the recordings are being finalized
+    Stopping,
+    Stopped,
+    /// The pipeline could not be built, or posted an error and was torn down
+    Failed(String),
+}
+
+impl StreamState {
+    /// Whether a pipeline is producing, or about to produce, frames
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self,
+            StreamState::Starting | StreamState::Playing | StreamState::Recovering
+        )
+    }
+
+    /// Whether the pipeline of the last start is gone
+    pub fn has_ended(&self) -> bool {
+        matches!(self, StreamState::Stopped | StreamState::Failed(_))
+    }
+}
+
+/// Waits until the pipeline of the watched stream ended and returns how. A dropped
+/// stream counts as stopped.
+pub async fn stream_ended(state_rx: &mut watch::Receiver<StreamState>) -> StreamState {
+    match state_rx.wait_for(StreamState::has_ended).await {
+        Ok(state) => state.clone(),
+        Err(_) => StreamState::Stopped,
+    }
+}
+
+/// A message posted on the bus of the pipeline of a stream
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all_fields = "camelCase")]
+pub enum StreamEvent {
+    Warning {
+        /// Path of the element that posted the message
+        source: Option<String>,
+        message: String,
+        debug: Option<String>,
+    },
+    /// The pipeline is torn down after an error, and the stream `Failed`
+    Error {
+        source: Option<String>,
+        message: String,
+        debug: Option<String>,
+    },
+    /// All the data was written out, after a stop or the end of the source
+    Eos,
+    /// The latency of the pipeline was (re)computed
+    Latency {
+        live: bool,
+        min_ns: u64,
+        max_ns: Option<u64>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stream_ended_waits_for_end() {
+        let (state_tx, mut state_rx) = watch::channel(StreamState::Starting);
+        let ended = tokio::spawn(async move { stream_ended(&mut state_rx).await });
+
+        state_tx.send_replace(StreamState::Playing);
+        state_tx.send_replace(StreamState::Failed("device unplugged".to_string()));
+        assert_eq!(
+            ended.await.unwrap(),
+            StreamState::Failed("device unplugged".to_string())
+        );
+
+        // A stream dropped while playing
+        let (state_tx, mut state_rx) = watch::channel(StreamState::Playing);
+        drop(state_tx);
+        assert_eq!(stream_ended(&mut state_rx).await, StreamState::Stopped);
+    }
+}
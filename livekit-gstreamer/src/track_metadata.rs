@@ -0,0 +1,154 @@
+use crate::media_stream::{GstMediaStream, PublishOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Participant attribute holding the host name of the publishing machine
+pub const HOST_NAME_ATTRIBUTE: &str = "syncflow.hostName";
+
+/// Prefix of the participant attributes holding the `LKTrackMetadata` of each
+/// published track, followed by the track SID. LiveKit has no per track metadata.
+pub const TRACK_METADATA_ATTRIBUTE_PREFIX: &str = "syncflow.track.";
+
+/// Describes the capture device behind a published track, so that room consumers
+/// can tell which physical camera or microphone the track comes from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LKTrackMetadata {
+    pub track_sid: String,
+    pub track_name: String,
+    /// One of `Video`, `Audio`, `Screen`, `Mixer` or `Composite`
+    pub kind: String,
+    pub device_name: String,
+    pub device_path: String,
+    pub device_class: String,
+    /// Capture resolution, for video streams
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub framerate: Option<i32>,
+    /// Channels captured from the device, for audio streams
+    pub channels: Option<i32>,
+    /// The (1-based) channel of the device published on its own
+    pub channel_index: Option<i32>,
+    pub sample_rate: Option<i32>,
+    pub host_name: Option<String>,
+    /// Files the stream is recorded to on the publishing machine
+    pub recordings: Vec<String>,
+}
+
+impl LKTrackMetadata {
+    /// Metadata of a running stream, `None` if it has not started
+    pub fn from_stream(
+        stream: &GstMediaStream,
+        track_sid: &str,
+        track_name: &str,
+        host_name: Option<String>,
+    ) -> Option<Self> {
+        let device = stream.device()?;
+        let mut metadata = Self {
+            track_sid: track_sid.to_string(),
+            track_name: track_name.to_string(),
+            kind: stream.kind().to_string(),
+            device_name: device.display_name,
+            device_path: device.device_path,
+            device_class: device.device_class,
+            width: None,
+            height: None,
+            framerate: None,
+            channels: None,
+            channel_index: None,
+            sample_rate: None,
+            host_name,
+            recordings: stream.recording_filenames(),
+        };
+
+        match stream.details()? {
+            PublishOptions::Video(details) => {
+                metadata.width = Some(details.width);
+                metadata.height = Some(details.height);
+                metadata.framerate = Some(details.framerate);
+            }
+            PublishOptions::Screen(details) => {
+                metadata.width = Some(details.width);
+                metadata.height = Some(details.height);
+                metadata.framerate = Some(details.framerate);
+            }
+            PublishOptions::Composite(details) => {
+                metadata.width = Some(details.width);
+                metadata.height = Some(details.height);
+                metadata.framerate = Some(details.framerate);
+            }
+            PublishOptions::Audio(details) => {
+                metadata.channels = Some(details.channels);
+                metadata.channel_index = details.selected_channel;
+                metadata.sample_rate = Some(details.framerate);
+            }
+            PublishOptions::Mixer(details) => {
+                metadata.channels = Some(details.channels);
+                metadata.sample_rate = Some(details.framerate);
+            }
+        }
+
+        Some(metadata)
+    }
+
+    pub fn attribute_key(&self) -> String {
+        format!("{}{}", TRACK_METADATA_ATTRIBUTE_PREFIX, self.track_sid)
+    }
+
+    /// The metadata of a track from the attributes of the participant that published it
+    pub fn from_attributes(attributes: &HashMap<String, String>, track_sid: &str) -> Option<Self> {
+        let value = attributes.get(&format!("{}{}", TRACK_METADATA_ATTRIBUTE_PREFIX, track_sid))?;
+        serde_json::from_str(value).ok()
+    }
+}
+
+/// Name for the track of a running stream telling its device, and the channel
+/// when a single channel of the device is published
+pub fn device_track_name(stream: &GstMediaStream) -> Option<String> {
+    let device_name = stream.get_device_name()?;
+    match stream.details()? {
+        PublishOptions::Audio(details) => match details.selected_channel {
+            Some(channel) => Some(format!("{}-channel-{}", device_name, channel)),
+            None => Some(device_name),
+        },
+        _ => Some(device_name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attributes_round_trip() {
+        let metadata = LKTrackMetadata {
+            track_sid: "TR_abc".to_string(),
+            track_name: "host-UMC1820-channel-2".to_string(),
+            kind: "Audio".to_string(),
+            device_name: "UMC1820".to_string(),
+            device_path: "hw:1".to_string(),
+            device_class: "Audio/Source".to_string(),
+            width: None,
+            height: None,
+            framerate: None,
+            channels: Some(10),
+            channel_index: Some(2),
+            sample_rate: Some(48000),
+            host_name: Some("host".to_string()),
+            recordings: vec!["umc1820.wav".to_string()],
+        };
+        let attributes = HashMap::from([(
+            metadata.attribute_key(),
+            serde_json::to_string(&metadata).unwrap(),
+        )]);
+
+        assert_eq!(
+            LKTrackMetadata::from_attributes(&attributes, "TR_abc"),
+            Some(metadata.clone())
+        );
+        assert_eq!(
+            LKTrackMetadata::from_attributes(&attributes, "TR_def"),
+            None
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use livekit::webrtc::stats::RtcStats;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Counters kept by the task forwarding the frames of a stream to WebRTC. They
+/// belong to the published track and carry over when its stream is replaced.
+#[derive(Debug, Default)]
+pub(crate) struct TrackCounters {
+    frames_received: AtomicU64,
+    frames_delivered: AtomicU64,
+    frames_lagged: AtomicU64,
+    frames_failed: AtomicU64,
+    latency_total_us: AtomicU64,
+    latency_max_us: AtomicU64,
+    latency_samples: AtomicU64,
+}
+
+impl TrackCounters {
+    pub(crate) fn frame_received(&self) {
+        self.frames_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The frame was handed to `capture_frame`, `latency` after it was captured
+    pub(crate) fn frame_delivered(&self, latency: Option<Duration>) {
+        self.frames_delivered.fetch_add(1, Ordering::Relaxed);
+        if let Some(latency) = latency {
+            let latency_us = latency.as_micros() as u64;
+            self.latency_total_us
+                .fetch_add(latency_us, Ordering::Relaxed);
+            self.latency_max_us.fetch_max(latency_us, Ordering::Relaxed);
+            self.latency_samples.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The forwarding task fell behind the pipeline and missed `count` frames
+    pub(crate) fn frames_lagged(&self, count: u64) {
+        self.frames_lagged.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// The frame could not be converted or captured
+    pub(crate) fn frame_failed(&self) {
+        self.frames_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> LKForwardingStats {
+        let latency_samples = self.latency_samples.load(Ordering::Relaxed);
+        LKForwardingStats {
+            frames_received: self.frames_received.load(Ordering::Relaxed),
+            frames_delivered: self.frames_delivered.load(Ordering::Relaxed),
+            frames_dropped: self.frames_lagged.load(Ordering::Relaxed),
+            frames_failed: self.frames_failed.load(Ordering::Relaxed),
+            mean_capture_latency_us: (latency_samples > 0)
+                .then(|| self.latency_total_us.load(Ordering::Relaxed) / latency_samples),
+            max_capture_latency_us: (latency_samples > 0)
+                .then(|| self.latency_max_us.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// What happened to the frames of a stream on their way from the pipeline to WebRTC
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LKForwardingStats {
+    /// Frames taken off the stream while the track was not muted
+    pub frames_received: u64,
+    /// Frames handed to the WebRTC source with `capture_frame`
+    pub frames_delivered: u64,
+    /// Frames the pipeline produced while the forwarding task was behind, and
+    /// that were dropped by the `BackpressurePolicy` of its subscription
+    pub frames_dropped: u64,
+    /// Frames received that could not be converted or captured
+    pub frames_failed: u64,
+    /// Time from the capture of a frame, its running time in the pipeline, until
+    /// it was handed to WebRTC
+    pub mean_capture_latency_us: Option<u64>,
+    pub max_capture_latency_us: Option<u64>,
+}
+
+/// Outbound RTP statistics of one encoding (simulcast layer) of a published
+/// track, joined with what the receiving end reported back about it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LKSenderStats {
+    pub ssrc: u32,
+    /// Simulcast layer, empty without simulcast
+    pub rid: String,
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub frames_encoded: u32,
+    pub frames_sent: u32,
+    pub frames_per_second: f64,
+    pub frame_width: u32,
+    pub frame_height: u32,
+    pub target_bitrate: f64,
+    pub nack_count: u32,
+    pub pli_count: u32,
+    /// Why the encoder lowered the quality, `none`, `cpu`, `bandwidth` or `other`
+    pub quality_limitation_reason: String,
+    pub packets_lost: Option<i64>,
+    pub fraction_lost: Option<f64>,
+    /// Seconds
+    pub round_trip_time: Option<f64>,
+    /// Seconds
+    pub jitter: Option<f64>,
+}
+
+impl LKSenderStats {
+    /// Picks the outbound RTP streams out of the WebRTC stats of a track
+    pub fn from_rtc_stats(stats: &[RtcStats]) -> Vec<Self> {
+        stats
+            .iter()
+            .filter_map(|stats| match stats {
+                RtcStats::OutboundRtp(outbound) => Some(outbound),
+                _ => None,
+            })
+            .map(|outbound| {
+                let remote_inbound = stats.iter().find_map(|stats| match stats {
+                    RtcStats::RemoteInboundRtp(remote)
+                        if remote.remote_inbound.local_id == outbound.rtc.id =>
+                    {
+                        Some(remote)
+                    }
+                    _ => None,
+                });
+                Self {
+                    ssrc: outbound.stream.ssrc,
+                    rid: outbound.outbound.rid.clone(),
+                    packets_sent: outbound.sent.packets_sent,
+                    bytes_sent: outbound.sent.bytes_sent,
+                    frames_encoded: outbound.outbound.frames_encoded,
+                    frames_sent: outbound.outbound.frames_sent,
+                    frames_per_second: outbound.outbound.frames_per_second,
+                    frame_width: outbound.outbound.frame_width,
+                    frame_height: outbound.outbound.frame_height,
+                    target_bitrate: outbound.outbound.target_bitrate,
+                    nack_count: outbound.outbound.nack_count,
+                    pli_count: outbound.outbound.pli_count,
+                    quality_limitation_reason: format!(
+                        "{:?}",
+                        outbound.outbound.quality_limitation_reason
+                    )
+                    .to_lowercase(),
+                    packets_lost: remote_inbound.map(|r| r.received.packets_lost),
+                    fraction_lost: remote_inbound.map(|r| r.remote_inbound.fraction_lost),
+                    round_trip_time: remote_inbound.map(|r| r.remote_inbound.round_trip_time),
+                    jitter: remote_inbound.map(|r| r.received.jitter),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Statistics of a track published by `LKParticipant`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LKTrackStats {
+    pub track_sid: String,
+    pub track_name: String,
+    pub muted: bool,
+    pub forwarding: LKForwardingStats,
+    /// Empty when WebRTC has no stats for the track (yet)
+    pub senders: Vec<LKSenderStats>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters_snapshot() {
+        let counters = TrackCounters::default();
+        assert_eq!(counters.snapshot().mean_capture_latency_us, None);
+
+        counters.frame_received();
+        counters.frame_received();
+        counters.frame_received();
+        counters.frame_delivered(Some(Duration::from_millis(10)));
+        counters.frame_delivered(Some(Duration::from_millis(30)));
+        counters.frame_failed();
+        counters.frames_lagged(4);
+
+        let stats = counters.snapshot();
+        assert_eq!(stats.frames_received, 3);
+        assert_eq!(stats.frames_delivered, 2);
+        assert_eq!(stats.frames_failed, 1);
+        assert_eq!(stats.frames_dropped, 4);
+        assert_eq!(stats.mean_capture_latency_us, Some(20_000));
+        assert_eq!(stats.max_capture_latency_us, Some(30_000));
+    }
+}
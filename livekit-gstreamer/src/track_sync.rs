@@ -0,0 +1,188 @@
+use crate::media_device::FileSinkTiming;
+use crate::stream_state::{stream_ended, StreamState};
+use crate::utils::system_time_nanos;
+use gstreamer::{prelude::*, Buffer};
+use livekit::{DataPacket, Room};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch};
+
+/// Data channel topic the track sync packets are published on
+pub const TRACK_SYNC_TOPIC: &str = "syncflow-track-sync";
+
+/// Seconds between the NTP epoch (1900) and the unix epoch (1970)
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+
+/// Offset of a frame into one of the local recordings of its stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingOffset {
+    pub filename: String,
+    pub offset_ns: i64,
+}
+
+/// Maps the media timestamp of a published frame to the publisher's wall clock
+/// and to the local recordings, so that subscribers can align live tracks with
+/// each other and with the recorded files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackSyncPacket {
+    pub track_sid: String,
+    pub track_name: String,
+    /// Timestamp of the frame in the stream (the buffer PTS), as handed to WebRTC
+    pub media_timestamp_us: i64,
+    /// Wall clock time of the frame, in nanoseconds since the unix epoch
+    pub system_time_ns: i64,
+    /// Same instant as a 64 bit NTP timestamp
+    pub ntp_time: u64,
+    pub recordings: Vec<RecordingOffset>,
+}
+
+impl TrackSyncPacket {
+    pub fn from_payload(payload: &[u8]) -> Option<Self> {
+        serde_json::from_slice(payload).ok()
+    }
+}
+
+/// Converts nanoseconds since the unix epoch to a 64 bit NTP timestamp, seconds
+/// since 1900 in the upper and the fraction of a second in the lower 32 bits
+pub fn ntp_timestamp(system_time_ns: i64) -> u64 {
+    let system_time_ns = system_time_ns.max(0) as u64;
+    let secs = system_time_ns / 1_000_000_000 + NTP_UNIX_OFFSET_SECS;
+    let fraction = ((system_time_ns % 1_000_000_000) << 32) / 1_000_000_000;
+    (secs << 32) | fraction
+}
+
+/// Maps buffer timestamps of a running stream to the wall clock. Assumes the
+/// buffer timestamps are running times, which holds for the live sources used
+/// by the pipelines as they start their segment at zero.
+#[derive(Debug, Clone)]
+pub struct StreamClock {
+    pipeline: gstreamer::Pipeline,
+    recordings: Vec<(String, Arc<Mutex<FileSinkTiming>>)>,
+}
+
+impl StreamClock {
+    pub(crate) fn new(
+        pipeline: gstreamer::Pipeline,
+        recordings: Vec<(String, Arc<Mutex<FileSinkTiming>>)>,
+    ) -> Self {
+        Self {
+            pipeline,
+            recordings,
+        }
+    }
+
+    /// Wall clock time of a buffer with the given timestamp, in nanoseconds since the unix epoch
+    pub fn system_time_ns(&self, pts: gstreamer::ClockTime) -> Option<i64> {
+        let now_running = self.pipeline.current_running_time()?;
+        let now = system_time_nanos();
+        Some(now - (now_running.nseconds() as i64 - pts.nseconds() as i64))
+    }
+
+    /// How long ago a buffer with the given timestamp was captured
+    pub fn capture_latency(&self, pts: gstreamer::ClockTime) -> Option<Duration> {
+        let now_running = self.pipeline.current_running_time()?;
+        Some(Duration::from_nanos(
+            now_running.nseconds().saturating_sub(pts.nseconds()),
+        ))
+    }
+
+    /// Offsets of a wall clock time into the recordings that have started writing
+    pub fn recording_offsets(&self, system_time_ns: i64) -> Vec<RecordingOffset> {
+        self.recordings
+            .iter()
+            .filter_map(|(filename, timing)| {
+                let start_time = timing.lock().unwrap().start_time?;
+                Some(RecordingOffset {
+                    filename: filename.clone(),
+                    offset_ns: system_time_ns - start_time,
+                })
+            })
+            .collect()
+    }
+
+    pub fn sync_packet(
+        &self,
+        track_sid: &str,
+        track_name: &str,
+        frame: &Buffer,
+    ) -> Option<TrackSyncPacket> {
+        let pts = frame.pts()?;
+        let system_time_ns = self.system_time_ns(pts)?;
+        Some(TrackSyncPacket {
+            track_sid: track_sid.to_string(),
+            track_name: track_name.to_string(),
+            media_timestamp_us: pts.useconds() as i64,
+            system_time_ns,
+            ntp_time: ntp_timestamp(system_time_ns),
+            recordings: self.recording_offsets(system_time_ns),
+        })
+    }
+}
+
+/// Publishes a `TrackSyncPacket` for the first frame after every `interval`
+pub(crate) async fn track_sync_task(
+    room: Arc<Room>,
+    clock: StreamClock,
+    track_sid: String,
+    track_name: String,
+    interval: Duration,
+    mut frames_rx: broadcast::Receiver<Arc<Buffer>>,
+    mut state_rx: watch::Receiver<StreamState>,
+) {
+    let mut last_sent: Option<Instant> = None;
+
+    loop {
+        tokio::select! {
+            _ = stream_ended(&mut state_rx) => {
+                break;
+            }
+            frame = frames_rx.recv() => {
+                let frame = match frame {
+                    Ok(frame) => frame,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if last_sent.is_some_and(|sent| sent.elapsed() < interval) {
+                    continue;
+                }
+                let Some(packet) = clock.sync_packet(&track_sid, &track_name, &frame) else {
+                    continue;
+                };
+                let payload = match serde_json::to_vec(&packet) {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        eprintln!("Failed to serialize track sync packet: {}", e);
+                        continue;
+                    }
+                };
+                let data_packet = DataPacket {
+                    payload,
+                    topic: Some(TRACK_SYNC_TOPIC.to_string()),
+                    reliable: true,
+                    ..Default::default()
+                };
+                if let Err(e) = room.local_participant().publish_data(data_packet).await {
+                    eprintln!("Failed to publish track sync packet: {}", e);
+                }
+                last_sent = Some(Instant::now());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ntp_timestamp() {
+        assert_eq!(ntp_timestamp(0), NTP_UNIX_OFFSET_SECS << 32);
+        assert_eq!(
+            ntp_timestamp(1_500_000_000),
+            ((NTP_UNIX_OFFSET_SECS + 1) << 32) | (1 << 31)
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use crate::media_device::GStreamerError;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 
 pub fn random_string(prefix: &str) -> String {
@@ -15,3 +16,26 @@ pub fn system_time_nanos() -> i64 {
         .map(|d| d.as_nanos() as i64)
         .unwrap_or(0)
 }
+
+/// Scales `width`x`height` to the largest size that fits the box, keeping the aspect ratio
+pub fn fit_resolution(width: i32, height: i32, box_width: i32, box_height: i32) -> (i32, i32) {
+    if width <= 0 || height <= 0 {
+        return (box_width, box_height);
+    }
+    let scale = f64::min(
+        box_width as f64 / width as f64,
+        box_height as f64 / height as f64,
+    );
+    (
+        (width as f64 * scale).round() as i32,
+        (height as f64 * scale).round() as i32,
+    )
+}
+
+/// Creates a uniquely named element from `factory`
+pub fn make_element(factory: &str) -> Result<gstreamer::Element, GStreamerError> {
+    gstreamer::ElementFactory::make(factory)
+        .name(random_string(factory))
+        .build()
+        .map_err(|_| GStreamerError::PipelineError(format!("Failed to create {}", factory)))
+}
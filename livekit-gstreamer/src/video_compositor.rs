@@ -0,0 +1,427 @@
+use crate::frame_subscription::StreamFrameSender;
+use crate::media_device::{GStreamerError, GstMediaDevice, VideoStreamFormat};
+use crate::media_stream::{CompositeInput, CompositeInputKind};
+use crate::utils::{fit_resolution, make_element, random_string};
+use gstreamer::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub const VIDEO_COMPOSITOR_DEVICE_CLASS: &str = "Video/Compositor";
+
+/// Placement of an input inside the composite frame, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompositeRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PipCorner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PictureInPictureLayout {
+    pub corner: PipCorner,
+    /// Width of each overlay relative to the width of the composite
+    pub scale: f64,
+    /// Distance in pixels between the overlays and the edges of the frame
+    pub margin: i32,
+}
+
+impl Default for PictureInPictureLayout {
+    fn default() -> Self {
+        Self {
+            corner: PipCorner::BottomRight,
+            scale: 0.25,
+            margin: 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum CompositeLayout {
+    /// The first input fills the frame, letterboxed to keep its aspect ratio, and the
+    /// others are stacked in a corner on top of it. Overlays that do not fit are rejected.
+    PictureInPicture(PictureInPictureLayout),
+    /// All inputs in an evenly sized grid, in order, letterboxed to keep their aspect ratio
+    Grid,
+    /// Every input is placed at its own `rect`
+    Custom,
+}
+
+impl CompositeLayout {
+    /// Where each input ends up in a `width`x`height` composite, in input order
+    pub fn rects(
+        &self,
+        width: i32,
+        height: i32,
+        inputs: &[CompositeInput],
+    ) -> Result<Vec<CompositeRect>, GStreamerError> {
+        if inputs.is_empty() {
+            return Err(GStreamerError::PipelineError(
+                "Compositor needs at least one input".to_string(),
+            ));
+        }
+
+        match self {
+            CompositeLayout::PictureInPicture(pip) => {
+                let main = &inputs[0];
+                let (w, h) = fit_resolution(main.width, main.height, width, height);
+                let mut rects = vec![CompositeRect {
+                    x: (width - w) / 2,
+                    y: (height - h) / 2,
+                    width: w,
+                    height: h,
+                }];
+                let overlay_width = (width as f64 * pip.scale).round() as i32;
+                let mut offset = pip.margin;
+                for (idx, input) in inputs.iter().enumerate().skip(1) {
+                    let (w, h) = fit_resolution(input.width, input.height, overlay_width, height);
+                    let x = match pip.corner {
+                        PipCorner::TopLeft | PipCorner::BottomLeft => pip.margin,
+                        PipCorner::TopRight | PipCorner::BottomRight => width - pip.margin - w,
+                    };
+                    let y = match pip.corner {
+                        PipCorner::TopLeft | PipCorner::TopRight => offset,
+                        PipCorner::BottomLeft | PipCorner::BottomRight => height - offset - h,
+                    };
+                    if x < 0 || y < 0 || x + w > width || y + h > height {
+                        return Err(GStreamerError::PipelineError(format!(
+                            "Overlay {} does not fit in the {}x{} composite, use a smaller scale or margin",
+                            idx, width, height
+                        )));
+                    }
+                    rects.push(CompositeRect {
+                        x,
+                        y,
+                        width: w,
+                        height: h,
+                    });
+                    offset += h + pip.margin;
+                }
+                Ok(rects)
+            }
+            CompositeLayout::Grid => {
+                let columns = (inputs.len() as f64).sqrt().ceil() as i32;
+                let rows = (inputs.len() as i32 + columns - 1) / columns;
+                let cell_width = width / columns;
+                let cell_height = height / rows;
+                Ok(inputs
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, input)| {
+                        let (column, row) = (idx as i32 % columns, idx as i32 / columns);
+                        let (w, h) =
+                            fit_resolution(input.width, input.height, cell_width, cell_height);
+                        CompositeRect {
+                            x: column * cell_width + (cell_width - w) / 2,
+                            y: row * cell_height + (cell_height - h) / 2,
+                            width: w,
+                            height: h,
+                        }
+                    })
+                    .collect())
+            }
+            CompositeLayout::Custom => inputs
+                .iter()
+                .map(|input| {
+                    input.rect.ok_or_else(|| {
+                        GStreamerError::PipelineError(format!(
+                            "Input {} has no rect in a custom layout",
+                            input.device_id
+                        ))
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+impl GstMediaDevice {
+    /// A virtual device standing in for several cameras and screens composited into one stream
+    pub fn video_compositor(devices: &[GstMediaDevice]) -> Self {
+        GstMediaDevice {
+            display_name: format!(
+                "Composite ({})",
+                devices
+                    .iter()
+                    .map(|d| d.display_name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            device_class: VIDEO_COMPOSITOR_DEVICE_CLASS.to_string(),
+            device_path: devices
+                .iter()
+                .map(|d| d.device_path.as_str())
+                .collect::<Vec<_>>()
+                .join("+"),
+        }
+    }
+
+    /// Composites the given inputs with `compositor` into a single stream of
+    /// `stream_format`. Later inputs are drawn on top of earlier ones.
+    pub fn video_compositor_pipeline(
+        &self,
+        inputs: &[(GstMediaDevice, CompositeInput, CompositeRect)],
+        stream_format: VideoStreamFormat,
+        framerate: i32,
+        tx: Arc<StreamFrameSender>,
+        filename: Option<String>,
+    ) -> Result<gstreamer::Pipeline, GStreamerError> {
+        if self.device_class != VIDEO_COMPOSITOR_DEVICE_CLASS {
+            return Err(GStreamerError::PipelineError(
+                "Device is not a video compositor".to_string(),
+            ));
+        }
+
+        if inputs.is_empty() {
+            return Err(GStreamerError::PipelineError(
+                "Compositor needs at least one input".to_string(),
+            ));
+        }
+
+        for (device, input, _) in inputs {
+            let supported = match input.kind {
+                CompositeInputKind::Camera => {
+                    device.supports_video(&input.codec, input.width, input.height, input.framerate)
+                }
+                CompositeInputKind::Screen => device.supports_screen_share(
+                    &input.codec,
+                    input.width,
+                    input.height,
+                    input.framerate,
+                ),
+            };
+            if !supported {
+                return Err(GStreamerError::PipelineError(format!(
+                    "Device {} does not support requested configuration",
+                    device.display_name
+                )));
+            }
+        }
+
+        let pipeline = gstreamer::Pipeline::with_name(&random_string("video-compositor"));
+
+        let compositor = make_element("compositor")?;
+        compositor.set_property_from_str("background", "black");
+        let convert = make_element("videoconvert")?;
+        let caps = self.stream_caps(stream_format, framerate);
+        let capsfilter = make_element("capsfilter")?;
+        capsfilter.set_property("caps", &caps);
+        let tee = make_element("tee")?;
+        let queue_appsink = make_element("queue")?;
+        let broadcast_appsink = self.broadcast_appsink(tx, Some(&caps))?;
+
+        pipeline
+            .add_many([
+                &compositor,
+                &convert,
+                &capsfilter,
+                &tee,
+                &queue_appsink,
+                broadcast_appsink.upcast_ref(),
+            ])
+            .map_err(|_| {
+                GStreamerError::PipelineError("Failed to add elements to pipeline".to_string())
+            })?;
+
+        gstreamer::Element::link_many([&compositor, &convert, &capsfilter, &tee])
+            .map_err(|_| GStreamerError::PipelineError("Failed to link compositor".to_string()))?;
+
+        for (zorder, (device, input, rect)) in inputs.iter().enumerate() {
+            self.add_compositor_input(&pipeline, &compositor, device, input, rect, zorder as u32)?;
+        }
+
+        let tee_appsink_pad = tee.request_pad_simple("src_%u").ok_or_else(|| {
+            GStreamerError::PipelineError("Failed to request tee pad for appsink".into())
+        })?;
+
+        let queue_appsink_pad = queue_appsink
+            .static_pad("sink")
+            .ok_or_else(|| GStreamerError::PipelineError("Appsink queue has no sink pad".into()))?;
+
+        tee_appsink_pad.link(&queue_appsink_pad).map_err(|_| {
+            GStreamerError::PipelineError("Failed to link tee to appsink queue".into())
+        })?;
+
+        gstreamer::Element::link_many([&queue_appsink, broadcast_appsink.upcast_ref()])
+            .map_err(|_| GStreamerError::PipelineError("Failed to link appsink".to_string()))?;
+
+        if let Some(ref path) = filename {
+            self.add_video_file_branch(&pipeline, &tee, path)?;
+        }
+
+        pipeline
+            .iterate_elements()
+            .foreach(|e| {
+                let _ = e.sync_state_with_parent();
+            })
+            .map_err(|_| {
+                GStreamerError::PipelineError("Failed to sync state with parent".to_string())
+            })?;
+
+        Ok(pipeline)
+    }
+
+    fn add_compositor_input(
+        &self,
+        pipeline: &gstreamer::Pipeline,
+        compositor: &gstreamer::Element,
+        device: &GstMediaDevice,
+        input: &CompositeInput,
+        rect: &CompositeRect,
+        zorder: u32,
+    ) -> Result<(), GStreamerError> {
+        let mut elements = vec![];
+        match input.kind {
+            CompositeInputKind::Camera => {
+                elements.push(device.get_video_element()?);
+                let caps = gstreamer::Caps::builder(input.codec.as_str())
+                    .field("width", input.width)
+                    .field("height", input.height)
+                    .field("framerate", gstreamer::Fraction::new(input.framerate, 1))
+                    .build();
+                let capsfilter = make_element("capsfilter")?;
+                capsfilter.set_property("caps", caps);
+                elements.push(capsfilter);
+                match input.codec.as_str() {
+                    "image/jpeg" => elements.push(make_element("jpegdec")?),
+                    "video/x-h264" => {
+                        elements.push(make_element("h264parse")?);
+                        elements.push(make_element("avdec_h264")?);
+                    }
+                    "video/x-raw" => {}
+                    codec => {
+                        return Err(GStreamerError::PipelineError(format!(
+                            "Unsupported codec {}",
+                            codec
+                        )))
+                    }
+                }
+            }
+            CompositeInputKind::Screen => {
+                elements.push(device.get_screen_element()?);
+                elements.push(make_element("videoconvert")?);
+                elements.push(make_element("videoscale")?);
+                let caps = gstreamer::Caps::builder("video/x-raw")
+                    .field("width", input.width)
+                    .field("height", input.height)
+                    .field("framerate", gstreamer::Fraction::new(input.framerate, 1))
+                    .field("pixel-aspect-ratio", gstreamer::Fraction::new(1, 1))
+                    .build();
+                let capsfilter = make_element("capsfilter")?;
+                capsfilter.set_property("caps", caps);
+                elements.push(capsfilter);
+            }
+        }
+        elements.push(make_element("videoconvert")?);
+        elements.push(make_element("queue")?);
+
+        pipeline.add_many(&elements).map_err(|_| {
+            GStreamerError::PipelineError("Failed to add compositor input".to_string())
+        })?;
+
+        gstreamer::Element::link_many(&elements).map_err(|_| {
+            GStreamerError::PipelineError("Failed to link compositor input".to_string())
+        })?;
+
+        let compositor_sink_pad = compositor.request_pad_simple("sink_%u").ok_or_else(|| {
+            GStreamerError::PipelineError("Failed to request compositor pad".into())
+        })?;
+        compositor_sink_pad.set_property("xpos", rect.x);
+        compositor_sink_pad.set_property("ypos", rect.y);
+        compositor_sink_pad.set_property("width", rect.width);
+        compositor_sink_pad.set_property("height", rect.height);
+        compositor_sink_pad.set_property("zorder", zorder);
+
+        // The vector always ends with the queue pushed above
+        let queue_src_pad = elements
+            .last()
+            .and_then(|queue| queue.static_pad("src"))
+            .ok_or_else(|| GStreamerError::PipelineError("Queue has no src pad".into()))?;
+        queue_src_pad.link(&compositor_sink_pad).map_err(|_| {
+            GStreamerError::PipelineError("Failed to link input to compositor".into())
+        })?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(width: i32, height: i32) -> CompositeInput {
+        CompositeInput {
+            kind: CompositeInputKind::Camera,
+            device_id: "test".to_string(),
+            codec: "video/x-raw".to_string(),
+            width,
+            height,
+            framerate: 30,
+            rect: None,
+        }
+    }
+
+    #[test]
+    fn test_picture_in_picture_layout() {
+        let layout = CompositeLayout::PictureInPicture(PictureInPictureLayout::default());
+        let rects = layout
+            .rects(1280, 720, &[input(1920, 1080), input(640, 480)])
+            .unwrap();
+
+        assert_eq!(
+            rects[1],
+            CompositeRect {
+                x: 1280 - 16 - 320,
+                y: 720 - 16 - 240,
+                width: 320,
+                height: 240,
+            }
+        );
+
+        // The main input is letterboxed instead of stretched
+        let rects = layout
+            .rects(1280, 720, &[input(640, 480), input(640, 480)])
+            .unwrap();
+        assert_eq!(
+            rects[0],
+            CompositeRect {
+                x: 160,
+                y: 0,
+                width: 960,
+                height: 720,
+            }
+        );
+
+        // Overlays stacked past the edge of the frame are rejected
+        let overflowing = vec![input(640, 480); 5];
+        assert!(layout.rects(1280, 720, &overflowing).is_err());
+    }
+
+    #[test]
+    fn test_grid_layout() {
+        let rects = CompositeLayout::Grid
+            .rects(
+                1280,
+                720,
+                &[input(640, 360), input(640, 360), input(640, 360)],
+            )
+            .unwrap();
+
+        assert_eq!(rects.len(), 3);
+        assert_eq!(rects[1].x, 640);
+        assert_eq!(rects[2].y, 360);
+        assert_eq!(rects[2].width, 640);
+    }
+}
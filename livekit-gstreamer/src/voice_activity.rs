@@ -0,0 +1,216 @@
+use crate::media_device::{AudioLevels, GStreamerError};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Options for the level based voice activity detector on audio streams
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct VoiceActivityOptions {
+    /// RMS level (in dB) above which a channel is considered to carry speech
+    pub threshold_db: f64,
+    /// How long the level has to stay above the threshold before speech starts
+    pub min_speech_ms: u64,
+    /// How long the level has to stay below the threshold before speech ends
+    pub hangover_ms: u64,
+    /// Write the detected events as `<recording>.vad.json` next to the recording
+    pub write_sidecar: bool,
+}
+
+impl Default for VoiceActivityOptions {
+    fn default() -> Self {
+        Self {
+            threshold_db: -45.0,
+            min_speech_ms: 200,
+            hangover_ms: 500,
+            write_sidecar: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum VoiceActivityKind {
+    SpeechStart,
+    SpeechEnd,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VoiceActivityEvent {
+    pub kind: VoiceActivityKind,
+    pub running_time_ns: Option<u64>,
+    pub system_time_ns: i64,
+}
+
+#[derive(Debug, Clone)]
+struct LevelInstant {
+    running_time_ns: Option<u64>,
+    system_time_ns: i64,
+}
+
+impl LevelInstant {
+    fn from_levels(levels: &AudioLevels) -> Self {
+        Self {
+            running_time_ns: levels.running_time_ns,
+            system_time_ns: levels.system_time_ns,
+        }
+    }
+
+    fn elapsed_ms(&self, now: &LevelInstant) -> u64 {
+        match (self.running_time_ns, now.running_time_ns) {
+            (Some(start), Some(end)) => end.saturating_sub(start) / 1_000_000,
+            _ => (now.system_time_ns - self.system_time_ns).max(0) as u64 / 1_000_000,
+        }
+    }
+
+    fn into_event(self, kind: VoiceActivityKind) -> VoiceActivityEvent {
+        VoiceActivityEvent {
+            kind,
+            running_time_ns: self.running_time_ns,
+            system_time_ns: self.system_time_ns,
+        }
+    }
+}
+
+/// A simple energy based detector that turns a stream of `AudioLevels` into
+/// speech-start/speech-end events. The loudest channel decides.
+#[derive(Debug)]
+pub struct VoiceActivityDetector {
+    options: VoiceActivityOptions,
+    speaking: bool,
+    pending: Option<LevelInstant>,
+    last: Option<LevelInstant>,
+}
+
+impl VoiceActivityDetector {
+    pub fn new(options: VoiceActivityOptions) -> Self {
+        Self {
+            options,
+            speaking: false,
+            pending: None,
+            last: None,
+        }
+    }
+
+    pub fn is_speaking(&self) -> bool {
+        self.speaking
+    }
+
+    pub fn process(&mut self, levels: &AudioLevels) -> Option<VoiceActivityEvent> {
+        let loudest = levels
+            .channels
+            .iter()
+            .map(|c| c.rms_db)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let is_loud = loudest >= self.options.threshold_db;
+        let now = LevelInstant::from_levels(levels);
+        self.last = Some(now.clone());
+
+        // `pending` tracks when the level crossed the threshold in the direction
+        // that would flip the current state
+        if is_loud == self.speaking {
+            self.pending = None;
+            return None;
+        }
+
+        let pending = self.pending.get_or_insert(now.clone());
+        let required_ms = if self.speaking {
+            self.options.hangover_ms
+        } else {
+            self.options.min_speech_ms
+        };
+
+        if pending.elapsed_ms(&now) < required_ms {
+            return None;
+        }
+
+        let kind = if self.speaking {
+            VoiceActivityKind::SpeechEnd
+        } else {
+            VoiceActivityKind::SpeechStart
+        };
+        self.speaking = !self.speaking;
+        self.pending.take().map(|p| p.into_event(kind))
+    }
+
+    /// Close an open speech segment at the last seen level, used when the stream ends
+    pub fn finish(&mut self) -> Option<VoiceActivityEvent> {
+        if !self.speaking {
+            return None;
+        }
+        self.speaking = false;
+        self.pending = None;
+        self.last
+            .clone()
+            .map(|l| l.into_event(VoiceActivityKind::SpeechEnd))
+    }
+}
+
+pub fn write_voice_activity_sidecar(
+    parent_dir: &str,
+    filename: &str,
+    events: &[VoiceActivityEvent],
+) -> Result<(), GStreamerError> {
+    let content = serde_json::json!({
+        "filename": filename,
+        "events": events,
+    });
+
+    let string_content = serde_json::to_string(&content).map_err(|e| {
+        GStreamerError::PipelineError(format!("Failed to serialize voice activity: {}", e))
+    })?;
+
+    std::fs::write(
+        PathBuf::from(parent_dir).join(format!("{}.vad.json", filename)),
+        string_content,
+    )
+    .map_err(|e| GStreamerError::PipelineError(format!("Failed to write voice activity: {}", e)))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media_device::ChannelLevel;
+
+    fn levels(rms_db: f64, running_time_ms: u64) -> AudioLevels {
+        AudioLevels {
+            channels: vec![ChannelLevel {
+                rms_db,
+                peak_db: rms_db,
+                decay_db: rms_db,
+            }],
+            running_time_ns: Some(running_time_ms * 1_000_000),
+            system_time_ns: 0,
+        }
+    }
+
+    #[test]
+    fn test_speech_segment() {
+        let mut detector = VoiceActivityDetector::new(VoiceActivityOptions::default());
+        let mut events = vec![];
+        let samples = [
+            -70.0, -20.0, -20.0, -20.0, -70.0, -70.0, -70.0, -70.0, -70.0, -70.0,
+        ];
+        for (idx, rms) in samples.iter().enumerate() {
+            events.extend(detector.process(&levels(*rms, idx as u64 * 100)));
+        }
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].kind, VoiceActivityKind::SpeechStart);
+        assert_eq!(events[0].running_time_ns, Some(100_000_000));
+        assert_eq!(events[1].kind, VoiceActivityKind::SpeechEnd);
+        assert_eq!(events[1].running_time_ns, Some(400_000_000));
+    }
+
+    #[test]
+    fn test_short_noise_is_ignored() {
+        let mut detector = VoiceActivityDetector::new(VoiceActivityOptions::default());
+        let samples = [-70.0, -20.0, -70.0, -20.0, -70.0];
+        for (idx, rms) in samples.iter().enumerate() {
+            assert!(detector.process(&levels(*rms, idx as u64 * 100)).is_none());
+        }
+        assert!(detector.finish().is_none());
+    }
+}
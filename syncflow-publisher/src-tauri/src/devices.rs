@@ -13,20 +13,13 @@ use crate::models::DeviceRecordingAndStreamingConfig;
 use crate::session_listener::initialize_session_listener;
 use crate::utils::save_json;
 
-fn verify_device_exists_and_supports_codec(
-    option: &PublishOptions,
-) -> Result<bool, SyncFlowPublisherError> {
+/// Finds the device with `device_id` among the available ones
+fn find_media_device(device_id: &str) -> Result<GstMediaDevice, SyncFlowPublisherError> {
     let existing_devices = get_devices();
 
-    let device_id = match option {
-        PublishOptions::Audio(details) => &details.device_id,
-        PublishOptions::Video(details) => &details.device_id,
-        PublishOptions::Screen(details) => &details.screen_id_or_name,
-    };
-
     let device = existing_devices
         .iter()
-        .find(|d| d.device_path == *device_id)
+        .find(|d| d.device_path == device_id)
         .ok_or_else(|| {
             SyncFlowPublisherError::ConfigError(format!("Device with path {} not found", device_id))
         })?;
@@ -36,25 +29,52 @@ fn verify_device_exists_and_supports_codec(
         _ => GstMediaDevice::from_screen_id_or_name(&device.device_path)?,
     };
 
-    let supports_codec = match option {
-        PublishOptions::Audio(details) => {
-            media_device.supports_audio(&details.codec, details.channels, details.framerate)
-        }
-        PublishOptions::Video(details) => media_device.supports_video(
-            &details.codec,
-            details.width,
-            details.height,
-            details.framerate,
-        ),
-        PublishOptions::Screen(details) => media_device.supports_screen_share(
-            &details.codec,
-            details.width,
-            details.height,
-            details.framerate,
+    Ok(media_device)
+}
+
+/// Checks the device of a single device stream, or each input of a mixer or
+/// composite stream
+fn verify_device_exists_and_supports_codec(
+    option: &PublishOptions,
+) -> Result<bool, SyncFlowPublisherError> {
+    match option {
+        PublishOptions::Audio(details) => Ok(find_media_device(&details.device_id)?
+            .supports_audio(&details.codec, details.channels, details.framerate)),
+        PublishOptions::Video(details) => Ok(find_media_device(&details.device_id)?
+            .supports_video(
+                &details.codec,
+                details.width,
+                details.height,
+                details.framerate,
+            )),
+        PublishOptions::Screen(details) => Ok(find_media_device(&details.screen_id_or_name)?
+            .supports_screen_share(
+                &details.codec,
+                details.width,
+                details.height,
+                details.framerate,
+            )),
+        PublishOptions::Mixer(details) => verify_inputs_exist_and_support_codec(
+            details
+                .input_options()
+                .into_iter()
+                .map(PublishOptions::Audio),
         ),
-    };
+        PublishOptions::Composite(details) => {
+            verify_inputs_exist_and_support_codec(details.input_options())
+        }
+    }
+}
 
-    Ok(supports_codec)
+fn verify_inputs_exist_and_support_codec(
+    input_options: impl IntoIterator<Item = PublishOptions>,
+) -> Result<bool, SyncFlowPublisherError> {
+    for input_option in input_options {
+        if !verify_device_exists_and_supports_codec(&input_option)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
 }
 
 #[tauri::command]
@@ -13,6 +13,7 @@ use std::{
     sync::{Arc, Mutex},
 };
 
+use livekit_gstreamer::LKRemoteControlOptions;
 use tokio::sync::Mutex as AsyncMutex;
 
 use devices::{delete_streaming_config, get_devices, get_streaming_config, set_streaming_config};
@@ -80,11 +81,26 @@ pub fn run() {
                     (None, None)
                 };
 
+            // Participants allowed to control the published streams over LiveKit RPC
+            let remote_control_file = app_dir.join("remote_control.json");
+            let remote_control = if remote_control_file.exists() {
+                match load_json::<LKRemoteControlOptions>(&remote_control_file) {
+                    Ok(options) => Some(options),
+                    Err(e) => {
+                        println!("Failed to load remote control options: {}", e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
             let app_handle = app.handle().clone();
             let _id = app.listen("new-session", move |event| {
                 let payload = event.payload();
                 let handle = app_handle.clone();
                 let recordings_dir_cloned = recordings_dir.clone();
+                let remote_control = remote_control.clone();
 
                 if let Ok(new_session_details) =
                     serde_json::from_str::<ClonableNewSessionMessage>(payload)
@@ -128,6 +144,7 @@ pub fn run() {
                                 &recordings_dir_cloned,
                                 s3_client,
                                 bucket_name,
+                                remote_control,
                             )
                             .await;
                         }
@@ -1,4 +1,4 @@
-use livekit_gstreamer::PublishOptions;
+use livekit_gstreamer::{LKPublishSettings, PublishOptions};
 use serde::{Deserialize, Serialize};
 use std::{
     path::PathBuf,
@@ -14,6 +14,8 @@ use crate::{register::RegistrationResponse, session_listener::SessionListener};
 pub struct DeviceRecordingAndStreamingConfig {
     pub publish_options: PublishOptions,
     pub enable_streaming: bool,
+    #[serde(default)]
+    pub publish_settings: LKPublishSettings,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1,12 +1,16 @@
-use std::{path::PathBuf, sync::Arc, vec};
+use std::{path::PathBuf, vec};
 
 use crate::{
     errors::SyncFlowPublisherError, models::DeviceRecordingAndStreamingConfig,
-    s3_uploader::upload_to_s3,
+    s3_uploader::upload_to_s3, utils as app_utils,
 };
-use livekit::{participant, Room, RoomOptions};
+use livekit::participant;
 use livekit_gstreamer::utils::system_time_nanos;
-use livekit_gstreamer::{lk_participant, GstMediaStream, LocalFileSaveOptions, PublishOptions};
+use livekit_gstreamer::{
+    device_track_name, AudioLevels, GstMediaStream, LKConnectionInfo, LKParticipantError,
+    LKPublishSettings, LKReconnectOptions, LKRemoteControlOptions, LKResilientEvent,
+    LKResilientParticipant, LKTokenProvider, LocalFileSaveOptions, PublishOptions, StreamState,
+};
 use serde::{Deserialize, Serialize};
 use syncflow_shared::{
     device_models::NewSessionMessage,
@@ -16,6 +20,9 @@ use tauri::Emitter;
 
 use tokio::sync::mpsc::channel;
 
+/// Reconnection attempts before a session is given up, about ten minutes of backoff
+const RECONNECT_MAX_ATTEMPTS: u32 = 25;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "kind", rename_all = "camelCase")]
 pub enum PublicationNotification {
@@ -54,19 +61,87 @@ pub struct SessionEndedData {
     pub session_id: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioLevelsNotification {
+    pub session_id: String,
+    pub device_name: String,
+    pub levels: AudioLevels,
+}
+
+fn forward_audio_levels(
+    stream: &GstMediaStream,
+    session_id: String,
+    event_emitter: tauri::AppHandle,
+) {
+    let (Some(mut levels_rx), Some(device_name)) =
+        (stream.subscribe_levels(), stream.get_device_name())
+    else {
+        return;
+    };
+
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match levels_rx.recv().await {
+                Ok(levels) => {
+                    let _ = event_emitter.emit(
+                        "audio-levels",
+                        AudioLevelsNotification {
+                            session_id: session_id.clone(),
+                            device_name: device_name.clone(),
+                            levels,
+                        },
+                    );
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// Reports a pipeline failure of the stream, e.g. an unplugged device, as a publication failure
+fn forward_stream_failure(
+    stream: &GstMediaStream,
+    session_id: String,
+    event_emitter: tauri::AppHandle,
+) {
+    let mut state_rx = stream.watch_state();
+    let device_name = stream
+        .get_device_name()
+        .unwrap_or("Unknown Device".to_string());
+
+    tauri::async_runtime::spawn(async move {
+        while state_rx.changed().await.is_ok() {
+            let state = state_rx.borrow_and_update().clone();
+            if let StreamState::Failed(reason) = state {
+                eprintln!("Stream of {} failed: {}", device_name, reason);
+                let _ = event_emitter.emit(
+                    "publication-notification",
+                    PublicationNotification::Failure(FailureData {
+                        session_id: session_id.clone(),
+                        reason: format!("{}: {}", device_name, reason),
+                    }),
+                );
+            }
+        }
+    });
+}
+
 async fn generate_session_token(
     pc: &syncflow_client::ProjectClient,
     participant_name: String,
-    session_details: &NewSessionMessage,
+    session_id: &str,
+    session_name: &str,
 ) -> Result<TokenResponse, SyncFlowPublisherError> {
     let token_response = pc
         .generate_session_token(
-            &session_details.session_id,
+            session_id,
             &TokenRequest {
                 identity: participant_name.clone(),
                 name: Some(participant_name),
                 video_grants: VideoGrantsWrapper {
-                    room: session_details.session_name.clone(),
+                    room: session_name.to_string(),
                     can_publish: true,
                     room_join: true,
                     room_create: false,
@@ -80,6 +155,39 @@ async fn generate_session_token(
     Ok(token_response)
 }
 
+/// Fetches a fresh session token every time the participant (re)joins the room
+fn session_token_provider(
+    pc: &syncflow_client::ProjectClient,
+    participant_name: &str,
+    session_details: &NewSessionMessage,
+) -> impl LKTokenProvider + 'static {
+    let pc = pc.clone();
+    let participant_name = participant_name.to_string();
+    let session_id = session_details.session_id.clone();
+    let session_name = session_details.session_name.clone();
+
+    move || {
+        let pc = pc.clone();
+        let participant_name = participant_name.clone();
+        let session_id = session_id.clone();
+        let session_name = session_name.clone();
+        async move {
+            let token = generate_session_token(&pc, participant_name, &session_id, &session_name)
+                .await
+                .map_err(|e| LKParticipantError::StreamingError(e.to_string()))?;
+            Ok(LKConnectionInfo {
+                url: token.livekit_server_url.ok_or_else(|| {
+                    LKParticipantError::StreamingError(
+                        "Token response has no LiveKit server url".to_string(),
+                    )
+                })?,
+                token: token.token,
+                e2ee: None,
+            })
+        }
+    }
+}
+
 pub async fn record_publish_to_syncflow(
     participant_name: String,
     session_details: NewSessionMessage,
@@ -89,6 +197,7 @@ pub async fn record_publish_to_syncflow(
     out_dir: &PathBuf,
     s3_client: Option<rusoto_s3::S3Client>,
     bucket_name: Option<String>,
+    remote_control: Option<LKRemoteControlOptions>,
 ) {
     let participant_name = participant_name.replace(".", "-").replace(" ", "-");
     let session_id = session_details.session_id.clone();
@@ -96,13 +205,14 @@ pub async fn record_publish_to_syncflow(
         "{}-{}-{}",
         session_id, session_details.session_name, participant_name
     ));
-    let mut streams_and_recording_config: Vec<(GstMediaStream, bool)> = configs
+    let streams_and_recording_config: Vec<(GstMediaStream, bool, LKPublishSettings)> = configs
         .into_iter()
         .map(|config| {
             let mut cloned_publish_options = config.publish_options.clone();
 
             let local_file_save_options = Some(LocalFileSaveOptions {
                 output_dir: output_dir.to_string_lossy().to_string(),
+                ..Default::default()
             });
 
             match &mut cloned_publish_options {
@@ -115,37 +225,33 @@ pub async fn record_publish_to_syncflow(
                 PublishOptions::Screen(screen_publish_options) => {
                     screen_publish_options.local_file_save_options = local_file_save_options;
                 }
+                PublishOptions::Mixer(mixer_publish_options) => {
+                    mixer_publish_options.local_file_save_options = local_file_save_options;
+                }
+                PublishOptions::Composite(composite_publish_options) => {
+                    composite_publish_options.local_file_save_options = local_file_save_options;
+                }
             }
 
             let stream = GstMediaStream::new(cloned_publish_options);
-            (stream, config.enable_streaming)
+            let publish_settings = LKPublishSettings {
+                host_name: Some(app_utils::host_name()),
+                ..config.publish_settings
+            };
+            (stream, config.enable_streaming, publish_settings)
         })
         .collect();
 
-    let token_result =
-        generate_session_token(project_client, participant_name.clone(), &session_details).await;
-
-    if let Err(e) = token_result {
-        let _ = event_emitter.emit(
-            "publication-notification",
-            PublicationNotification::Failure(FailureData {
-                session_id: session_id.clone(),
-                reason: e.to_string(),
-            }),
-        );
-        return;
-    }
-
-    let token = token_result.unwrap();
-
-    let room_result = Room::connect(
-        &token.livekit_server_url.unwrap(),
-        &token.token,
-        RoomOptions::default(),
+    let participant_result = LKResilientParticipant::connect(
+        session_token_provider(project_client, &participant_name, &session_details),
+        LKReconnectOptions {
+            max_attempts: Some(RECONNECT_MAX_ATTEMPTS),
+            ..Default::default()
+        },
     )
     .await;
 
-    if let Err(e) = room_result {
+    if let Err(e) = participant_result {
         let _ = event_emitter.emit(
             "publication-notification",
             PublicationNotification::Failure(FailureData {
@@ -156,25 +262,42 @@ pub async fn record_publish_to_syncflow(
         return;
     }
 
-    let (room, mut room_rx) = room_result.unwrap();
-
-    let room_arc = Arc::new(room);
+    let mut participant = participant_result.unwrap();
+    if let Some(remote_control) = remote_control {
+        participant.enable_remote_control(remote_control);
+    }
 
-    let mut participant = lk_participant::LKParticipant::new(room_arc.clone());
+    // Streams that are only recorded locally, the published ones are owned by the
+    // participant so that it can republish them after a reconnection
+    let mut local_streams = vec![];
 
     let mut all_failures = vec![];
 
-    for (stream, enable_streaming) in streams_and_recording_config.iter_mut() {
+    for (mut stream, enable_streaming, publish_settings) in streams_and_recording_config {
+        // Falls back to the closest mode when the device lacks the configured one
+        if !matches!(
+            stream.options(),
+            PublishOptions::Mixer(_) | PublishOptions::Composite(_)
+        ) {
+            match stream.negotiate() {
+                Ok(mode) => println!("Negotiated capture mode: {:?}", mode),
+                Err(e) => eprintln!("Failed to negotiate capture mode: {}", e),
+            }
+        }
         stream.start().await.unwrap();
-        if *enable_streaming {
-            let device_name = stream
-                .get_device_name()
-                .unwrap_or("Unknown Device".to_string());
+        forward_audio_levels(&stream, session_id.clone(), event_emitter.clone());
+        forward_stream_failure(&stream, session_id.clone(), event_emitter.clone());
+        if enable_streaming {
+            let device_name = device_track_name(&stream).unwrap_or("Unknown Device".to_string());
             let track_name = format!("{}-{}", participant_name, device_name);
-            let result = participant.publish_stream(stream, Some(track_name)).await;
+            let result = participant
+                .publish_stream(stream, track_name, publish_settings)
+                .await;
             if let Err(e) = result {
                 all_failures.push(e.to_string());
             }
+        } else {
+            local_streams.push(stream);
         }
     }
 
@@ -195,35 +318,67 @@ pub async fn record_publish_to_syncflow(
                 session_id: session_id.clone(),
                 session_name: session_details.session_name.clone(),
                 started_at: system_time_nanos().to_string(),
-                devices: streams_and_recording_config
-                    .iter()
-                    .filter_map(|(stream, _)| stream.get_device_name())
+                devices: participant
+                    .streams()
+                    .chain(local_streams.iter())
+                    .filter_map(|stream| stream.get_device_name())
                     .collect(),
             }),
         );
     }
 
-    while let Some(msg) = room_rx.recv().await {
-        match msg {
-            livekit::RoomEvent::Disconnected { reason } => {
+    while let Some(event) = participant.recv().await {
+        match event {
+            LKResilientEvent::Room(livekit::RoomEvent::Disconnected { reason }) => {
                 println!("Disconnected from room: {:?}", reason);
-                for stream in streams_and_recording_config.iter_mut() {
-                    stream.0.stop().await.unwrap();
-                }
+                break;
+            }
+            LKResilientEvent::Room(msg) => {
+                println!("Received room event: {:?}", msg);
+            }
+            LKResilientEvent::Reconnecting { attempt } => {
+                println!("Connection lost, reconnecting (attempt {})", attempt);
+            }
+            LKResilientEvent::Reconnected => {
+                println!("Reconnected to room, streams republished");
+            }
+            LKResilientEvent::RemoteCommand {
+                caller_identity,
+                command,
+                error,
+            } => match error {
+                None => println!("Ran {:?} for {}", command, caller_identity),
+                Some(error) => println!(
+                    "Failed to run {:?} for {}: {}",
+                    command, caller_identity, error
+                ),
+            },
+            LKResilientEvent::ReconnectFailed(reason) => {
                 let _ = event_emitter.emit(
                     "publication-notification",
-                    PublicationNotification::SessionEnded(SessionEndedData {
+                    PublicationNotification::Failure(FailureData {
                         session_id: session_id.clone(),
+                        reason: format!("Failed to reconnect: {}", reason),
                     }),
                 );
                 break;
             }
-            _ => {
-                println!("Received room event: {:?}", msg);
-            }
         }
     }
 
+    if let Err(e) = participant.close().await {
+        println!("Failed to close participant: {}", e);
+    }
+    for stream in local_streams.iter_mut() {
+        stream.stop().await.unwrap();
+    }
+    let _ = event_emitter.emit(
+        "publication-notification",
+        PublicationNotification::SessionEnded(SessionEndedData {
+            session_id: session_id.clone(),
+        }),
+    );
+
     println!(
         "S3Client: {:?}, bucket_name: {:?}, all_failures: {:?}",
         s3_client.is_some(),
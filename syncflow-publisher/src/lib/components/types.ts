@@ -48,6 +48,7 @@ export interface RegistrationResponse {
 
 export interface LocalFileSaveOptions {
     outputDir: string;
+    recordingDurationToleranceNs?: number;
 }
 
 export interface LocalSaveFileMetadata {
@@ -56,6 +57,13 @@ export interface LocalSaveFileMetadata {
     startedAt: string;
 }
 
+export interface PublishResolution {
+    width: number;
+    height: number;
+}
+
+export type VideoFrameFormat = 'I420' | 'NV12';
+
 export interface VideoPublishOptions {
     codec: string;
     deviceId: string;
@@ -63,6 +71,23 @@ export interface VideoPublishOptions {
     height: number;
     framerate: number;
     localFileSaveOptions?: LocalFileSaveOptions;
+    publishResolution?: PublishResolution;
+    frameFormat?: VideoFrameFormat;
+}
+
+export interface VoiceActivityOptions {
+    thresholdDb: number;
+    minSpeechMs: number;
+    hangoverMs: number;
+    writeSidecar: boolean;
+}
+
+export interface AudioProcessingOptions {
+    noiseSuppression: boolean;
+    gainControl: boolean;
+    highPassFilter: boolean;
+    echoCancellation: boolean;
+    applyToRecording: boolean;
 }
 
 export interface AudioPublishOptions {
@@ -72,6 +97,8 @@ export interface AudioPublishOptions {
     channels: number;
     selectedChannel?: number;
     localFileSaveOptions?: LocalFileSaveOptions;
+    voiceActivity?: VoiceActivityOptions;
+    processing?: AudioProcessingOptions;
 }
 
 export interface ScreenPublishOptions {
@@ -81,16 +108,88 @@ export interface ScreenPublishOptions {
     height: number;
     framerate: number;
     localFileSaveOptions?: LocalFileSaveOptions;
+    publishResolution?: PublishResolution;
+    frameFormat?: VideoFrameFormat;
+}
+
+export interface AudioMixerInput {
+    deviceId: string;
+    channels: number;
+    selectedChannel?: number;
+    gain?: number;
+}
+
+export interface AudioMixerPublishOptions {
+    codec: string;
+    framerate: number;
+    channels: number;
+    inputs: AudioMixerInput[];
+    recordInputs?: boolean;
+    localFileSaveOptions?: LocalFileSaveOptions;
+}
+
+export interface CompositeRect {
+    x: number;
+    y: number;
+    width: number;
+    height: number;
+}
+
+export interface CompositeInput {
+    kind: 'Camera' | 'Screen';
+    deviceId: string;
+    codec: string;
+    width: number;
+    height: number;
+    framerate: number;
+    rect?: CompositeRect;
+}
+
+export interface PictureInPictureLayout {
+    corner?: 'TopLeft' | 'TopRight' | 'BottomLeft' | 'BottomRight';
+    scale?: number;
+    margin?: number;
+}
+
+export type CompositeLayout =
+    | ({ kind: 'PictureInPicture' } & PictureInPictureLayout)
+    | { kind: 'Grid' }
+    | { kind: 'Custom' };
+
+export interface CompositePublishOptions {
+    width: number;
+    height: number;
+    framerate: number;
+    inputs: CompositeInput[];
+    layout: CompositeLayout;
+    localFileSaveOptions?: LocalFileSaveOptions;
+    frameFormat?: VideoFrameFormat;
 }
 
 export type PublishOptions =
     | ({ kind: 'Video' } & VideoPublishOptions)
     | ({ kind: 'Audio' } & AudioPublishOptions)
-    | ({ kind: 'Screen' } & ScreenPublishOptions);
+    | ({ kind: 'Screen' } & ScreenPublishOptions)
+    | ({ kind: 'Mixer' } & AudioMixerPublishOptions)
+    | ({ kind: 'Composite' } & CompositePublishOptions);
+
+export interface LKPublishSettings {
+    simulcast?: boolean;
+    videoCodec?: 'VP8' | 'VP9' | 'H264' | 'AV1';
+    maxBitrate?: number;
+    maxFramerate?: number;
+    dtx?: boolean;
+    red?: boolean;
+    source?: 'Camera' | 'Microphone' | 'Screenshare' | 'ScreenshareAudio';
+    syncIntervalMs?: number | null;
+    publishMetadata?: boolean;
+    hostName?: string | null;
+}
 
 export interface DeviceRecordingAndStreamingConfig {
     enableStreaming: boolean;
     publishOptions: PublishOptions;
+    publishSettings?: LKPublishSettings;
 }
 
 export interface NewSessionMessage {
@@ -128,3 +227,21 @@ export interface SessionEnded {
     kind: 'sessionEnded';
     sessionId: string;
 }
+
+export interface ChannelLevel {
+    rmsDb: number;
+    peakDb: number;
+    decayDb: number;
+}
+
+export interface AudioLevels {
+    channels: ChannelLevel[];
+    runningTimeNs?: number;
+    systemTimeNs: number;
+}
+
+export interface AudioLevelsNotification {
+    sessionId: string;
+    deviceName: string;
+    levels: AudioLevels;
+}